@@ -1,14 +1,72 @@
+use num::BigInt;
+
 pub type Location = (usize, usize);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ProgramAll {
+    pub imports: Imports,
+    pub constants: Constants,
+    pub globals: Globals,
     pub procedures: Procedures,
     pub main: Main,
 }
 
 impl ProgramAll {
-    pub fn new(p: Procedures, m: Main) -> Self {
-        return Self{procedures: p, main: m};
+    pub fn new(i: Imports, c: Constants, g: Globals, p: Procedures, m: Main) -> Self {
+        return Self{imports: i, constants: c, globals: g, procedures: p, main: m};
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Module {
+    pub imports: Imports,
+    pub procedures: Procedures,
+}
+
+impl Module {
+    pub fn new(i: Imports, p: Procedures) -> Self {
+        return Self{imports: i, procedures: p};
+    }
+}
+
+pub type Imports = Vec<Import>;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Import {
+    pub path: String,
+    pub location: Location,
+}
+
+impl Import {
+    pub fn new(p: String, l: Location) -> Self {
+        return Self{path: p, location: l};
+    }
+}
+
+pub type Constants = Vec<Constant>;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Constant {
+    pub name: Pidentifier,
+    pub value: Value,
+    pub location: Location,
+}
+
+impl Constant {
+    pub fn new(p: Pidentifier, v: Value, l: Location) -> Self {
+        return Self{name: p, value: v, location: l};
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Globals {
+    pub declarations: Declarations,
+    pub location: Location,
+}
+
+impl Globals {
+    pub fn new(d: Declarations, l: Location) -> Self {
+        return Self{declarations: d, location: l};
     }
 }
 
@@ -20,11 +78,12 @@ pub struct Procedure {
     pub declarations: Declarations,
     pub commands: Commands,
     pub location: Location,
+    pub doc: Option<String>, // the description in the doc comments above it
 }
 
 impl Procedure {
     pub fn new(ph: ProcHead, d: Declarations, c: Commands, l: Location) -> Self {
-        return Self{proc_head: ph, declarations: d, commands: c, location: l};
+        return Self{proc_head: ph, declarations: d, commands: c, location: l, doc: None};
     }
 }
 
@@ -55,6 +114,20 @@ pub enum Command {
     Write(Value, Location),
 }
 
+impl Command {
+    pub fn location(&self) -> Location {
+        let (Command::Assignment(_, _, location)
+        | Command::IfElse(_, _, _, location)
+        | Command::If(_, _, location)
+        | Command::While(_, _, location)
+        | Command::Repeat(_, _, location)
+        | Command::ProcedureCall(_, location)
+        | Command::Read(_, location)
+        | Command::Write(_, location)) = self;
+        return *location;
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ProcHead {
     pub name: Pidentifier,
@@ -71,11 +144,16 @@ impl ProcHead {
 pub struct ProcCall {
     pub name: Pidentifier,
     pub args: Arguments,
+    pub halts: bool, // the program stops once the procedure returns; only the calls added by the compiler do
 }
 
 impl ProcCall {
     pub fn new(p: Pidentifier, a: Arguments) -> Self {
-        return Self{name: p, args: a};
+        return Self{name: p, args: a, halts: false};
+    }
+
+    pub fn halting(p: Pidentifier, a: Arguments) -> Self {
+        return Self{name: p, args: a, halts: true};
     }
 }
 
@@ -83,19 +161,40 @@ pub type Declarations = Vec<Declaration>;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Declaration {
-    Var(Pidentifier),
-    Arr(Pidentifier, Num),
+    Var(Pidentifier, Location),
+    Arr(Pidentifier, Num, Location),
+}
+
+impl Declaration {
+    pub fn location(&self) -> Location {
+        let (Declaration::Var(_, location) | Declaration::Arr(_, _, location)) = self;
+        return *location;
+    }
 }
 
 pub type ArgumentDeclarations = Vec<ArgumentDeclaration>;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ArgumentDeclaration {
-    Var(Pidentifier),
-    Arr(Pidentifier),
+    Var(Pidentifier, ParameterMode, Location),
+    Arr(Pidentifier, ParameterMode, Location),
+}
+
+impl ArgumentDeclaration {
+    pub fn location(&self) -> Location {
+        let (ArgumentDeclaration::Var(_, _, location) | ArgumentDeclaration::Arr(_, _, location)) = self;
+        return *location;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum ParameterMode {
+    In,
+    Out,
+    InOut,
 }
 
-pub type Arguments = Vec<Pidentifier>;
+pub type Arguments = Vec<Value>;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Expression {
@@ -120,6 +219,7 @@ pub enum Condition {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Value {
     Number(Num),
+    Negative(Num), // the literal -n, only allowed in the signed mode
     Id(Identifier),
 }
 
@@ -132,4 +232,4 @@ pub enum Identifier {
 
 pub type Pidentifier = String;
 
-pub type Num = u64;
+pub type Num = BigInt;
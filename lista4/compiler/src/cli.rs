@@ -0,0 +1,362 @@
+use std::fmt;
+
+use crate::diagnostics::ErrorFormat;
+use crate::lints::{Level, LintLevels};
+use crate::trace::Category;
+use crate::translation::passes::{Pass, PassManager, DEFAULT_LEVEL, MAX_LEVEL};
+
+// the exit codes of the compiler
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_PARSE: i32 = 2;
+pub const EXIT_SEMANTIC: i32 = 3;
+pub const EXIT_IO: i32 = 4;
+pub const EXIT_RUNTIME: i32 = 5;
+pub const EXIT_UNFORMATTED: i32 = 6;
+
+// the path standing for the standard input or output
+pub const STDIO_PATH: &str = "-";
+
+pub const USAGE: &str = "\
+usage: compiler <command> [options] <input>
+       compiler <input> <output>
+       compiler repl
+       compiler lsp
+       compiler --print-passes [options]
+
+commands:
+  compile     compile the program into virtual machine code
+  check       only report the diagnostics of the program
+  run         compile the program and execute it in the emulator
+  debug       compile the program and step through it in the emulator
+  interpret   execute the program directly, without compiling it, stopping at reads of unset variables
+  dump-ast    print the syntax tree of the program
+  dump-ir     print the program as it enters code generation
+  fmt         print the program laid out in the canonical way, keeping its comments
+  doc         print the signatures and the descriptions of the procedures of a program or a module as Markdown
+  repl        run the commands typed in one by one, keeping the variables between them
+  lsp         serve the editors as a language server over the standard input and output
+
+options:
+  -o <path>                   the output file (`-` for the standard output)
+  --emit=code|transformed-source
+                              what to compile the program into: the code, or the program as it is after
+                              the transformation, which compiles (without --signed) into the same code
+  --listing=<path>            also write the code side by side with the source it comes from
+  --map[=<path>]              also write the addresses of the variables and the origin of every instruction
+                              as JSON, by default into the output file with the .map extension
+  -O<level>                   the optimisation level: 0, 1 or 2 (default 2)
+  --enable-pass=<passes>      run the comma-separated passes regardless of the optimisation level
+  --disable-pass=<passes>     skip the comma-separated passes regardless of the optimisation level
+  --print-passes              list the passes and whether they are enabled
+  --signed                    use signed integers
+  --strict                    accept only the dialect of the course, without digits in the identifiers
+  --check                     with fmt, only check that the program is laid out in the canonical way
+  --error-format=human|json   the format of the diagnostics
+  -W<lint>, -A<lint>, -D<lint>
+                              warn about, allow or deny a lint (`warnings` for all of them)
+  --trace=<categories>        log the work of the compiler onto the standard error,
+                              the categories being a comma-separated list of regalloc, codegen and inline
+
+an input or output path `-` stands for the standard input or output";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Subcommand {
+    Compile,
+    Check,
+    Run,
+    Debug,
+    Interpret,
+    DumpAst,
+    DumpIr,
+    Fmt,
+    Doc,
+    Repl,
+    Lsp,
+}
+
+impl Subcommand {
+    fn parse(name: &str) -> Option<Self> {
+        return match name {
+            "compile" => Some(Subcommand::Compile),
+            "check" => Some(Subcommand::Check),
+            "run" => Some(Subcommand::Run),
+            "debug" => Some(Subcommand::Debug),
+            "interpret" => Some(Subcommand::Interpret),
+            "dump-ast" => Some(Subcommand::DumpAst),
+            "dump-ir" => Some(Subcommand::DumpIr),
+            "fmt" => Some(Subcommand::Fmt),
+            "doc" => Some(Subcommand::Doc),
+            "repl" => Some(Subcommand::Repl),
+            "lsp" => Some(Subcommand::Lsp),
+            _ => None,
+        };
+    }
+}
+
+// what the compilation produces
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Emit {
+    Code,
+    TransformedSource,
+}
+
+impl Emit {
+    fn parse(name: &str) -> Option<Self> {
+        return match name {
+            "code" => Some(Emit::Code),
+            "transformed-source" => Some(Emit::TransformedSource),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UsageError {
+    NoInput,
+    TooManyPaths(String),
+    MissingValue(String),
+    UnknownFlag(String),
+    UnknownLint(String),
+    InvalidOptLevel(String),
+    InvalidErrorFormat(String),
+    InvalidEmit(String),
+    UnknownTraceCategory(String),
+    UnknownPass(String),
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageError::NoInput => write!(f, "No input file given"),
+            UsageError::TooManyPaths(path) => write!(f, "Unexpected argument \"{}\"", path),
+            UsageError::MissingValue(flag) => write!(f, "The option \"{}\" needs a value", flag),
+            UsageError::UnknownFlag(flag) => write!(f, "Unknown option \"{}\"", flag),
+            UsageError::UnknownLint(name) => write!(f, "Unknown lint \"{}\"", name),
+            UsageError::InvalidOptLevel(level) => write!(f, "Invalid optimisation level \"{}\"", level),
+            UsageError::InvalidErrorFormat(name) => write!(f, "Invalid error format \"{}\"", name),
+            UsageError::InvalidEmit(name) => write!(f, "Invalid kind of output \"{}\"", name),
+            UsageError::UnknownTraceCategory(name) => write!(f, "Unknown trace category \"{}\"", name),
+            UsageError::UnknownPass(name) => write!(f, "Unknown pass \"{}\"", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub subcommand: Subcommand,
+    pub input: String,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub listing: Option<String>,
+    pub map: Option<String>,
+    pub opt_level: u8,
+    pub passes: PassManager,
+    pub print_passes: bool,
+    pub check: bool,
+    pub signed: bool,
+    pub strict: bool,
+    pub error_format: ErrorFormat,
+    pub lint_levels: LintLevels,
+    pub trace: Vec<Category>,
+}
+
+impl Options {
+
+    // the output of the compilation: the input file with the .mr extension, unless given;
+    // the transformed program is only looked at, so it goes onto the standard output
+    pub fn output_path(&self) -> String {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+        if self.input == STDIO_PATH || self.emit == Emit::TransformedSource {
+            return STDIO_PATH.to_owned();
+        }
+        let stem = self.input.strip_suffix(".imp").unwrap_or(&self.input);
+        return stem.to_owned() + ".mr";
+    }
+
+    // the debug information goes next to the code unless given
+    fn default_map_path(&self) -> Option<String> {
+        let output = self.output_path();
+        if output == STDIO_PATH {
+            return None;
+        }
+        let stem = output.strip_suffix(".mr").unwrap_or(&output);
+        return Some(stem.to_owned() + ".map");
+    }
+}
+
+fn parse_passes(names: &str) -> Result<Vec<Pass>, UsageError> {
+    return names.split(',').map(|name| Pass::from_name(name).ok_or(UsageError::UnknownPass(name.to_owned()))).collect();
+}
+
+// parse the arguments following the name of the executable
+pub fn parse_args(args: &[String]) -> Result<Options, UsageError> {
+
+    // without a command, the arguments are the input and output files of the compilation
+
+    let (subcommand, args) = match args.first().and_then(|arg| Subcommand::parse(arg)) {
+        Some(subcommand) => (subcommand, &args[1..]),
+        None => (Subcommand::Compile, args),
+    };
+
+    let mut options = Options{
+        subcommand,
+        input: String::new(),
+        output: None,
+        emit: Emit::Code,
+        listing: None,
+        map: None,
+        opt_level: DEFAULT_LEVEL,
+        passes: PassManager::default(),
+        print_passes: false,
+        check: false,
+        signed: false,
+        strict: false,
+        error_format: ErrorFormat::Human,
+        lint_levels: LintLevels::new(),
+        trace: Vec::new(),
+    };
+    let mut paths = Vec::new();
+    let mut pass_switches = Vec::new();
+    let mut default_map = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            options.output = Some(args.next().ok_or(UsageError::MissingValue(arg.clone()))?.clone());
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            options.opt_level = match level.parse() {
+                Ok(level) if level <= MAX_LEVEL => level,
+                _ => return Err(UsageError::InvalidOptLevel(level.to_owned())),
+            };
+        } else if let Some(names) = arg.strip_prefix("--enable-pass=") {
+            pass_switches.extend(parse_passes(names)?.into_iter().map(|pass| (pass, true)));
+        } else if let Some(names) = arg.strip_prefix("--disable-pass=") {
+            pass_switches.extend(parse_passes(names)?.into_iter().map(|pass| (pass, false)));
+        } else if arg == "--print-passes" {
+            options.print_passes = true;
+        } else if let Some(name) = arg.strip_prefix("--emit=") {
+            options.emit = Emit::parse(name).ok_or(UsageError::InvalidEmit(name.to_owned()))?;
+        } else if let Some(path) = arg.strip_prefix("--listing=") {
+            options.listing = Some(path.to_owned());
+        } else if arg == "--map" {
+            default_map = true;
+        } else if let Some(path) = arg.strip_prefix("--map=") {
+            options.map = Some(path.to_owned());
+        } else if arg == "--check" {
+            options.check = true;
+        } else if arg == "--signed" {
+            options.signed = true;
+        } else if arg == "--strict" {
+            options.strict = true;
+        } else if let Some(name) = arg.strip_prefix("--error-format=") {
+            options.error_format = ErrorFormat::parse(name).ok_or(UsageError::InvalidErrorFormat(name.to_owned()))?;
+        } else if let Some((level, name)) = Level::from_flag(arg) {
+            if !options.lint_levels.set(name, level) {
+                return Err(UsageError::UnknownLint(name.to_owned()));
+            }
+        } else if let Some(names) = arg.strip_prefix("--trace=") {
+            for name in names.split(',') {
+                options.trace.push(Category::from_name(name).ok_or(UsageError::UnknownTraceCategory(name.to_owned()))?);
+            }
+        } else if arg.starts_with('-') && arg != STDIO_PATH {
+            return Err(UsageError::UnknownFlag(arg.clone()));
+        } else {
+            paths.push(arg.clone());
+        }
+    }
+
+    // the switches of single passes override the optimisation level, wherever it is given
+
+    options.passes = PassManager::for_level(options.opt_level);
+    for (pass, enabled) in pass_switches {
+        match enabled {
+            true => options.passes.enable(pass),
+            false => options.passes.disable(pass),
+        }
+    }
+
+    // the output file may also be given as the second path, but only for the compilation;
+    // the language server gets its files from the editor
+
+    let mut paths = paths.into_iter();
+    options.input = match paths.next() {
+        Some(path) if matches!(subcommand, Subcommand::Repl | Subcommand::Lsp) => return Err(UsageError::TooManyPaths(path)),
+        Some(input) => input,
+        None if options.print_passes || matches!(subcommand, Subcommand::Repl | Subcommand::Lsp) => String::new(),
+        None => return Err(UsageError::NoInput),
+    };
+    if subcommand == Subcommand::Compile && options.output.is_none() {
+        options.output = paths.next();
+    }
+    if let Some(path) = paths.next() {
+        return Err(UsageError::TooManyPaths(path));
+    }
+    if default_map && options.map.is_none() {
+        options.map = Some(options.default_map_path().ok_or(UsageError::MissingValue("--map".to_owned()))?);
+    }
+
+    return Ok(options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, UsageError> {
+        return parse_args(&args.split_whitespace().map(|arg| arg.to_owned()).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn subcommands_and_paths() {
+        let options = parse("prog.imp prog.mr").unwrap();
+        assert_eq!((options.subcommand, options.input.as_str(), options.output_path()), (Subcommand::Compile, "prog.imp", "prog.mr".to_owned()));
+
+        let options = parse("compile -O0 prog.imp").unwrap();
+        assert_eq!((options.opt_level, options.output_path()), (0, "prog.mr".to_owned()));
+
+        let options = parse("run - --signed").unwrap();
+        assert_eq!((options.subcommand, options.input.as_str(), options.signed), (Subcommand::Run, "-", true));
+        assert_eq!(options.output_path(), "-");
+        assert_eq!(parse("debug -O0 a.imp").unwrap().subcommand, Subcommand::Debug);
+        assert_eq!(parse("interpret --signed a.imp").unwrap().subcommand, Subcommand::Interpret);
+
+        assert_eq!(parse("fmt a.imp -o -").unwrap().output.as_deref(), Some("-"));
+        assert!(parse("fmt --check a.imp").unwrap().check);
+        assert_eq!(parse("a.imp --emit=transformed-source").unwrap().output_path(), "-");
+        assert_eq!(parse("a.imp --emit=code").unwrap().output_path(), "a.mr");
+        assert_eq!(parse("a.imp --emit=ir").unwrap_err(), UsageError::InvalidEmit("ir".to_owned()));
+        assert_eq!(parse("a.imp a.mr --listing=a.lst").unwrap().listing.as_deref(), Some("a.lst"));
+        assert_eq!(parse("a.imp --map").unwrap().map.as_deref(), Some("a.map"));
+        assert_eq!(parse("a.imp -o b.out --map").unwrap().map.as_deref(), Some("b.out.map"));
+        assert_eq!(parse("- --map=a.json").unwrap().map.as_deref(), Some("a.json"));
+        assert_eq!(parse("check a.imp --trace=regalloc,inline").unwrap().trace, [Category::Regalloc, Category::Inline]);
+        assert!(parse("--print-passes").unwrap().print_passes);
+        assert_eq!(parse("lsp").unwrap().subcommand, Subcommand::Lsp);
+        assert_eq!(parse("repl").unwrap().subcommand, Subcommand::Repl);
+    }
+
+    #[test]
+    fn passes() {
+        assert_eq!(parse("a.imp").unwrap().passes, PassManager::for_level(DEFAULT_LEVEL));
+        assert_eq!(parse("a.imp -O0").unwrap().passes.enabled(), []);
+
+        let passes = parse("a.imp --disable-pass=inline,dead-procedures -O1 --enable-pass=inline").unwrap().passes;
+        assert_eq!(passes.enabled(), [Pass::ConstantFolding, Pass::Inline, Pass::RegisterCaching]);
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(parse("check").unwrap_err(), UsageError::NoInput);
+        assert_eq!(parse("check a.imp b.imp").unwrap_err(), UsageError::TooManyPaths("b.imp".to_owned()));
+        assert_eq!(parse("lsp a.imp").unwrap_err(), UsageError::TooManyPaths("a.imp".to_owned()));
+        assert_eq!(parse("compile a.imp -o").unwrap_err(), UsageError::MissingValue("-o".to_owned()));
+        assert_eq!(parse("compile - --map").unwrap_err(), UsageError::MissingValue("--map".to_owned()));
+        assert_eq!(parse("compile a.imp -O3").unwrap_err(), UsageError::InvalidOptLevel("3".to_owned()));
+        assert_eq!(parse("compile a.imp -Wfoo").unwrap_err(), UsageError::UnknownLint("foo".to_owned()));
+        assert_eq!(parse("compile a.imp --foo").unwrap_err(), UsageError::UnknownFlag("--foo".to_owned()));
+        assert_eq!(parse("compile a.imp --trace=codegen,foo").unwrap_err(), UsageError::UnknownTraceCategory("foo".to_owned()));
+        assert_eq!(parse("compile a.imp --enable-pass=foo").unwrap_err(), UsageError::UnknownPass("foo".to_owned()));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::ast::Location;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CommentKind {
+    Line,  // from `#` to the end of the line
+    Doc,   // from `##` to the end of the line, describing the procedure right below
+    Block, // from `(*` to the matching `*)`, the block comments nesting
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub span: Location, // without the line break ending the line comments
+    pub closed: bool,   // whether a block comment is closed before the end of the file
+}
+
+// the comments of the source, skipping the strings of the imports
+pub fn scan(source: &str) -> Vec<Comment> {
+    let bytes = source.as_bytes();
+    let mut comments = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += 1;
+                }
+                pos += 1;
+            },
+            b'#' => {
+                let start = pos;
+                while pos < bytes.len() && bytes[pos] != b'\n' && bytes[pos] != b'\r' {
+                    pos += 1;
+                }
+
+                // a line of only hashes is a separator, not a description
+
+                let kind = match source[start..pos].strip_prefix("##") {
+                    Some(rest) if !rest.starts_with('#') => CommentKind::Doc,
+                    _ => CommentKind::Line,
+                };
+                comments.push(Comment{kind, span: (start, pos), closed: true});
+            },
+            b'(' if bytes.get(pos + 1) == Some(&b'*') => {
+                let start = pos;
+                let mut depth = 0;
+                while pos < bytes.len() {
+                    if bytes[pos..].starts_with(b"(*") {
+                        depth += 1;
+                        pos += 2;
+                    } else if bytes[pos..].starts_with(b"*)") {
+                        depth -= 1;
+                        pos += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        pos += 1;
+                    }
+                }
+                comments.push(Comment{kind: CommentKind::Block, span: (start, pos), closed: depth == 0});
+            },
+            _ => pos += 1,
+        }
+    }
+    return comments;
+}
+
+// the source with the comments replaced by spaces, keeping the line breaks and the offsets of everything else
+pub fn blank(source: &str, comments: &[Comment]) -> String {
+    let mut code = source.to_owned();
+    for comment in comments {
+        let (start, end) = comment.span;
+        let blanked: String = source[start..end].chars().map(|c| match c {
+            '\n' | '\r' => c.to_string(),
+            _ => " ".repeat(c.len_utf8()),
+        }).collect();
+        code.replace_range(start..end, &blanked);
+    }
+    return code;
+}
+
+// the description of the procedure starting at the offset: the doc comments in the lines right above it,
+// without the `##` and the space after it
+pub fn doc_before(source: &str, comments: &[Comment], offset: usize) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut next = offset;
+    for comment in comments.iter().rev().skip_while(|comment| comment.span.0 >= offset) {
+        if comment.kind != CommentKind::Doc || !source[comment.span.1..next].trim().is_empty() {
+            break;
+        }
+        let text = &source[comment.span.0 + 2..comment.span.1];
+        lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end());
+        next = comment.span.0;
+    }
+    lines.reverse();
+    let doc = lines.join("\n");
+    return match doc.trim_matches('\n') {
+        "" => None,
+        doc => Some(doc.to_owned()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_and_nesting() {
+        let source = "IMPORT \"a#b\"; # note\n(* outer (* inner *) still # outer *)\n########\n## Adds.\n##\n## Twice.\nPROCEDURE p(a) IS IN a := a; END\n(* open";
+        let comments = scan(source);
+        let kinds: Vec<CommentKind> = comments.iter().map(|comment| comment.kind).collect();
+        assert_eq!(kinds, [CommentKind::Line, CommentKind::Block, CommentKind::Line, CommentKind::Doc, CommentKind::Doc, CommentKind::Doc, CommentKind::Block]);
+        assert_eq!(&source[comments[1].span.0..comments[1].span.1], "(* outer (* inner *) still # outer *)");
+        assert!(comments[1].closed && !comments[6].closed);
+
+        let code = blank(source, &comments);
+        assert_eq!(code.len(), source.len());
+        assert!(code.starts_with("IMPORT \"a#b\";       \n") && !code.contains("inner"));
+
+        let procedure = source.find("PROCEDURE").unwrap();
+        assert_eq!(doc_before(source, &comments, procedure).as_deref(), Some("Adds.\n\nTwice."));
+        assert_eq!(doc_before(source, &comments, source.find("a := a").unwrap()), None);
+    }
+}
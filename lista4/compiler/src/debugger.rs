@@ -0,0 +1,469 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use num::BigInt;
+
+use crate::emulator::{parse_code, register_index, EmulatorError, Machine, MachineIo};
+use crate::modules::SourceMap;
+use crate::translation::translation_structures::{AnnotatedLine, Layout, Scope, SymbolTableEntry};
+
+const REGISTER_NAMES: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+pub const HELP: &str = "\
+commands:
+  break <line>, b <line>     stop at the statements of the source line (`<file>:<line>` in a module)
+  delete <line>, d <line>    remove the breakpoint from the source line
+  continue, c                run until a breakpoint or the end of the program
+  step, s                    run until the next statement
+  stepi, si                  execute a single instruction
+  print <name>, p <name>     print a variable, an array or an element of an array, e.g. `p t[i]`
+  registers, r               print the registers next to what the compiler assumes about them
+  where, w                   print the current statement and instruction
+  help, h                    print this text
+  quit, q                    stop debugging";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DebuggerError {
+    Emulator(EmulatorError),
+    UnknownCommand(String),
+    InvalidLine(String),
+    NoCodeAtLine(String),
+    UnknownVariable(String),
+    NotAnArray(String),
+    IndexOutOfBounds(String, BigInt),
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebuggerError::Emulator(e) => write!(f, "{}", e),
+            DebuggerError::UnknownCommand(command) => write!(f, "Unknown command \"{}\", try \"help\"", command),
+            DebuggerError::InvalidLine(line) => write!(f, "Invalid source line \"{}\"", line),
+            DebuggerError::NoCodeAtLine(line) => write!(f, "No statement starts at line {}", line),
+            DebuggerError::UnknownVariable(name) => write!(f, "No variable \"{}\" here", name),
+            DebuggerError::NotAnArray(name) => write!(f, "\"{}\" is not an array", name),
+            DebuggerError::IndexOutOfBounds(name, idx) => write!(f, "Index {} is out of the bounds of \"{}\"", idx, name),
+        }
+    }
+}
+
+impl From<EmulatorError> for DebuggerError {
+    fn from(e: EmulatorError) -> Self {
+        return DebuggerError::Emulator(e);
+    }
+}
+
+// the place in the source a line of code comes from
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Origin {
+    file: String,
+    line: usize,
+    column: usize,
+    text: String,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}  {}", self.file, self.line, self.column, self.text)
+    }
+}
+
+// the debugger drives the emulator over the annotated code, translating between the instructions and the source
+pub struct Debugger {
+    pub machine: Machine,
+    lines: Vec<AnnotatedLine>,
+    origins: Vec<Option<Origin>>,
+    scopes: Vec<Scope>,
+    main_file: String,
+    breakpoints: BTreeSet<(String, usize)>,
+}
+
+impl Debugger {
+    pub fn new(code: &[String], layout: Layout, source_map: &SourceMap) -> Result<Self, EmulatorError> {
+        let machine = Machine::new(parse_code(code)?);
+        let lines: Vec<AnnotatedLine> = code.iter().map(|line| AnnotatedLine::parse(line)).collect();
+
+        // the line map: the source line of every line of code
+
+        let origins = lines.iter().map(|line| line.location.filter(|_| !source_map.files.is_empty()).map(|location| {
+            let (file, (start, _)) = source_map.lookup(location);
+            let (line, column) = file.line_column(start);
+            let text = file.source.lines().nth(line - 1).unwrap_or_default().trim().to_owned();
+            Origin{file: file.path.display().to_string(), line, column, text}
+        })).collect();
+        let main_file = source_map.files.first().map(|file| file.path.display().to_string()).unwrap_or_default();
+
+        return Ok(Self{machine, lines, origins, scopes: layout.scopes, main_file, breakpoints: BTreeSet::new()});
+    }
+
+    // whether the instruction is the first one of a statement, or the condition of a loop checked again
+    fn is_statement_start(&self, counter: usize) -> bool {
+        return self.lines.get(counter).is_some_and(|line| line.registers.is_some() || line.labels.iter().any(|label| label == "loop head"));
+    }
+
+    fn is_breakpoint(&self, counter: usize) -> bool {
+        return self.is_statement_start(counter) && self.origins[counter].as_ref()
+            .is_some_and(|origin| self.breakpoints.contains(&(origin.file.clone(), origin.line)));
+    }
+
+    // parse the source line of a breakpoint, the file being the program itself unless given
+    fn parse_source_line(&self, text: &str) -> Result<(String, usize), DebuggerError> {
+        let (file, line) = match text.rsplit_once(':') {
+            Some((file, line)) => (file.to_owned(), line),
+            None => (self.main_file.clone(), text),
+        };
+        return match line.parse() {
+            Ok(line) => Ok((file, line)),
+            Err(_) => Err(DebuggerError::InvalidLine(text.to_owned())),
+        };
+    }
+
+    // stop at every statement starting at the source line
+    pub fn add_breakpoint(&mut self, text: &str) -> Result<Vec<usize>, DebuggerError> {
+        let source_line = self.parse_source_line(text)?;
+        let instructions: Vec<usize> = (0..self.lines.len()).filter(|&counter| self.is_statement_start(counter) && self.origins[counter].as_ref()
+            .is_some_and(|origin| (&origin.file, origin.line) == (&source_line.0, source_line.1))).collect();
+        if instructions.is_empty() {
+            return Err(DebuggerError::NoCodeAtLine(text.to_owned()));
+        }
+        self.breakpoints.insert(source_line);
+        return Ok(instructions);
+    }
+
+    pub fn remove_breakpoint(&mut self, text: &str) -> Result<bool, DebuggerError> {
+        let source_line = self.parse_source_line(text)?;
+        return Ok(self.breakpoints.remove(&source_line));
+    }
+
+    // execute a single instruction
+    pub fn step_instruction(&mut self, io: &mut dyn MachineIo) -> Result<(), DebuggerError> {
+        self.machine.step(io)?;
+        return Ok(());
+    }
+
+    // run until the next statement begins
+    pub fn step_statement(&mut self, io: &mut dyn MachineIo) -> Result<(), DebuggerError> {
+        self.machine.step(io)?;
+        while !self.machine.halted && !self.is_statement_start(self.machine.counter) {
+            self.machine.step(io)?;
+        }
+        return Ok(());
+    }
+
+    // run until a breakpoint or the end of the program
+    pub fn resume(&mut self, io: &mut dyn MachineIo) -> Result<(), DebuggerError> {
+        self.machine.step(io)?;
+        while !self.machine.halted && !self.is_breakpoint(self.machine.counter) {
+            self.machine.step(io)?;
+        }
+        return Ok(());
+    }
+
+    // the procedure or main being executed; the lines outside all of them belong to main
+    fn scope(&self) -> Option<&Scope> {
+        let counter = self.machine.counter;
+        return self.scopes.iter().find(|scope| scope.start <= counter && counter < scope.end).or(self.scopes.last());
+    }
+
+    // the register holding the value of the variable at the start of the current statement
+    fn register_holding(&self, name: &str) -> Option<usize> {
+        let registers = self.lines.get(self.machine.counter)?.registers.as_ref()?;
+        return registers.iter().find(|(_, content)| content == name).and_then(|(register, _)| register_index(register));
+    }
+
+    // the value of the variable or the array element, together with where it is held
+    fn read(&self, name: &str, address: BigInt) -> (BigInt, String) {
+        return match self.register_holding(name) {
+            Some(register) => (self.machine.registers[register].clone(), format!("register {}", REGISTER_NAMES[register])),
+            None => (self.machine.load(&address), format!("memory {}", address)),
+        };
+    }
+
+    fn lookup(&self, name: &str) -> Result<&SymbolTableEntry, DebuggerError> {
+        return self.scope().and_then(|scope| scope.symbols.get(name))
+            .filter(|entry| !matches!(entry, SymbolTableEntry::Ret(_)))
+            .ok_or(DebuggerError::UnknownVariable(name.to_owned()));
+    }
+
+    // the value of a variable, following the reference of a parameter
+    fn variable(&self, name: &str) -> Result<(BigInt, String), DebuggerError> {
+        return match self.lookup(name)? {
+            SymbolTableEntry::Var(var) if var.is_ref => Ok(self.read(name, self.machine.load(&BigInt::from(var.memloc)))),
+            SymbolTableEntry::Var(var) => Ok(self.read(name, BigInt::from(var.memloc))),
+            _ => Err(DebuggerError::UnknownVariable(name.to_owned())),
+        };
+    }
+
+    // the address of the first element of an array and its length, unknown for an array passed to a procedure
+    fn array(&self, name: &str) -> Result<(BigInt, Option<u64>), DebuggerError> {
+        return match self.lookup(name)? {
+            SymbolTableEntry::Arr(arr) if arr.is_ref => Ok((self.machine.load(&BigInt::from(arr.memloc)), None)),
+            SymbolTableEntry::Arr(arr) => Ok((BigInt::from(arr.memloc), Some(arr.len))),
+            _ => Err(DebuggerError::NotAnArray(name.to_owned())),
+        };
+    }
+
+    // print a variable, an array or an element of an array indexed by a number or a variable
+    pub fn print(&self, expression: &str) -> Result<String, DebuggerError> {
+        let expression: String = expression.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if let Some((name, idx)) = expression.strip_suffix(']').and_then(|element| element.split_once('[')) {
+            let (base, len) = self.array(name)?;
+            let idx_value = match idx.parse::<BigInt>() {
+                Ok(num) => num,
+                Err(_) => self.variable(idx)?.0,
+            };
+            if idx_value < BigInt::from(0) || len.is_some_and(|len| idx_value >= BigInt::from(len)) {
+                return Err(DebuggerError::IndexOutOfBounds(name.to_owned(), idx_value));
+            }
+
+            // the register states name the elements the way the code does, by number or by variable
+
+            let held_as = match self.register_holding(&expression) {
+                Some(_) => expression.clone(),
+                None => format!("{}[{}]", name, idx_value),
+            };
+            let (value, place) = self.read(&held_as, &base + &idx_value);
+            return Ok(format!("{}[{}] = {}  ({})", name, idx_value, value, place));
+        }
+
+        return match self.lookup(&expression)? {
+            SymbolTableEntry::Arr(_) => {
+                let (base, len) = self.array(&expression)?;
+                match len {
+                    Some(len) => {
+                        let elements: Vec<String> = (0..len).map(|idx| self.read(&format!("{}[{}]", expression, idx), &base + idx).0.to_string()).collect();
+                        Ok(format!("{} = [{}]  (memory {})", expression, elements.join(", "), base))
+                    },
+                    None => Ok(format!("{} = array passed by reference  (memory {}), print its elements with {}[<index>]", expression, base, expression)),
+                }
+            },
+            _ => {
+                let (value, place) = self.variable(&expression)?;
+                Ok(format!("{} = {}  ({})", expression, value, place))
+            },
+        };
+    }
+
+    // the actual registers side by side with their contents assumed at the start of the current statement
+    pub fn registers(&self) -> String {
+        let counter = self.machine.counter;
+        let start = (0..=counter.min(self.lines.len().saturating_sub(1))).rev().find(|&line| self.is_statement_start(line));
+        let assumed = start.and_then(|line| self.lines[line].registers.clone()).unwrap_or_default();
+
+        let mut text = match start {
+            Some(line) if line == counter => "assumed by the compiler at this statement:\n".to_owned(),
+            Some(line) => format!("assumed by the compiler at the start of the statement (instruction {}):\n", line),
+            None => "nothing assumed by the compiler before the first statement:\n".to_owned(),
+        };
+        for (register, name) in REGISTER_NAMES.iter().enumerate() {
+            let value = &self.machine.registers[register];
+            let row = match assumed.iter().find(|(assumed_register, _)| assumed_register == name) {
+
+                // a constant can be checked against the value, the mismatch being a bug of the compiler
+
+                Some((_, content)) if start == Some(counter) && content.parse::<BigInt>().is_ok_and(|num| &num != value) => {
+                    format!("{}  {:>12}  {}  (differs!)", name, value, content)
+                },
+                Some((_, content)) => format!("{}  {:>12}  {}", name, value, content),
+                None => format!("{}  {:>12}", name, value),
+            };
+            text += &row;
+            text += "\n";
+        }
+        return text;
+    }
+
+    // the statement and the instruction about to be executed
+    pub fn location(&self) -> String {
+        let counter = self.machine.counter;
+        if self.machine.halted {
+            return format!("the program has halted, cost {}", self.machine.cost);
+        }
+        let instruction = self.lines.get(counter).map(|line| line.instruction.clone()).unwrap_or_default();
+        let scope = self.scope().map(|scope| scope.name.clone()).unwrap_or_default();
+        return match &self.origins[counter] {
+            Some(origin) => format!("{}  [in {}, instruction {}: {}]", origin, scope, counter, instruction),
+            None => format!("[in {}, instruction {}: {}]", scope, counter, instruction),
+        };
+    }
+
+    // carry out a command of the user and describe the result
+    pub fn execute(&mut self, command: &str, io: &mut dyn MachineIo) -> Result<String, DebuggerError> {
+        let mut words = command.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or_default();
+        let argument = words.next().unwrap_or_default().trim();
+
+        return match name {
+            "break" | "b" => {
+                let instructions = self.add_breakpoint(argument)?;
+                let instructions: Vec<String> = instructions.iter().map(|counter| counter.to_string()).collect();
+                Ok(format!("breakpoint at line {} (instructions {})", argument, instructions.join(", ")))
+            },
+            "delete" | "d" => match self.remove_breakpoint(argument)? {
+                true => Ok(format!("removed the breakpoint at line {}", argument)),
+                false => Ok(format!("no breakpoint at line {}", argument)),
+            },
+            "continue" | "c" | "step" | "s" | "stepi" | "si" if self.machine.halted => Ok(self.location()),
+            "continue" | "c" => {
+                self.resume(io)?;
+                Ok(self.location())
+            },
+            "step" | "s" => {
+                self.step_statement(io)?;
+                Ok(self.location())
+            },
+            "stepi" | "si" => {
+                self.step_instruction(io)?;
+                Ok(self.location())
+            },
+            "print" | "p" => self.print(argument),
+            "registers" | "r" => Ok(self.registers()),
+            "where" | "w" => Ok(self.location()),
+            "help" | "h" => Ok(HELP.to_owned()),
+            _ => Err(DebuggerError::UnknownCommand(command.to_owned())),
+        };
+    }
+}
+
+// the input of the program is asked for on the terminal, in between the commands of the debugger
+#[derive(Debug, Default)]
+pub struct PromptIo;
+
+impl MachineIo for PromptIo {
+    fn read(&mut self) -> Option<BigInt> {
+        loop {
+            print!("? ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {},
+            }
+            match line.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => println!("Error: \"{}\" is not a number", line.trim()),
+            }
+        }
+    }
+
+    fn write(&mut self, value: &BigInt) {
+        println!("> {}", value);
+    }
+}
+
+// read the commands from the standard input until it ends or the user quits
+pub fn session(debugger: &mut Debugger, io: &mut dyn MachineIo) {
+    println!("{}", debugger.location());
+    loop {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {},
+        }
+        let command = line.trim();
+        match command {
+            "" => continue,
+            "quit" | "q" => break,
+            _ => match debugger.execute(command, io) {
+                Ok(text) => println!("{}", text.trim_end()),
+                Err(e) => println!("Error: {}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::BufferedIo;
+    use crate::test_support::{compile_with_layout, load};
+    use crate::translation::passes::PassManager;
+    use super::*;
+
+    const PROGRAM: &str = "\
+PROCEDURE fill(T t, IN n, s) IS i IN
+  i := 0;
+  WHILE i < n DO
+    t[i] := i * i;
+    i := i + 1;
+  ENDWHILE
+  s := t[3];
+END
+PROGRAM IS a, t[4] IN
+  READ a;
+  fill(t, 4, a);
+  WRITE a;
+END
+";
+
+    fn debugger(level: u8) -> Debugger {
+        let mut source_map = SourceMap::new();
+        let ast = load(PROGRAM, &mut source_map);
+        let (code, layout) = compile_with_layout(ast, &PassManager::for_level(level));
+        return Debugger::new(&code, layout, &source_map).unwrap();
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut debugger = debugger(1);
+        let mut io = BufferedIo::new(&[BigInt::from(7)]);
+
+        assert_eq!(debugger.execute("break 8", &mut io), Err(DebuggerError::NoCodeAtLine("8".to_owned())));
+        assert!(debugger.execute("break 5", &mut io).is_ok());
+
+        // the loop body runs four times
+
+        for i in 0..4 {
+            let location = debugger.execute("continue", &mut io).unwrap();
+            assert!(location.starts_with("test.imp:5:5  i := i + 1;  [in fill"), "{}", location);
+            assert_eq!(debugger.print("i").unwrap().split("  ").next().unwrap(), format!("i = {}", i));
+            assert_eq!(debugger.print("t[i]").unwrap().split("  ").next().unwrap(), format!("t[{}] = {}", i, i * i));
+        }
+        assert_eq!(debugger.execute("delete 5", &mut io).unwrap(), "removed the breakpoint at line 5");
+
+        // a statement takes a few instructions
+
+        let location = debugger.execute("step", &mut io).unwrap();
+        assert!(location.starts_with("test.imp:3:3"), "{}", location);
+        let counter = debugger.machine.counter;
+        debugger.execute("stepi", &mut io).unwrap();
+        assert_eq!(debugger.machine.counter, counter + 1);
+
+        assert!(debugger.execute("continue", &mut io).unwrap().starts_with("the program has halted"));
+        assert_eq!(io.output, [BigInt::from(9)]);
+    }
+
+    #[test]
+    fn variables_and_registers() {
+        // the inlined procedures are debugged as a part of their callers, so the procedure is kept
+
+        for level in [0, 1] {
+            let mut debugger = debugger(level);
+            let mut io = BufferedIo::new(&[BigInt::from(7)]);
+            debugger.execute("break 7", &mut io).unwrap();
+            debugger.execute("continue", &mut io).unwrap();
+
+            // the parameters are read through their references, the array's length is unknown in the procedure
+
+            assert!(debugger.print("s").unwrap().starts_with("s = 7"), "-O{}", level);
+            assert!(debugger.print("n").unwrap().starts_with("n = 4"), "-O{}", level);
+            assert!(debugger.print("t[3]").unwrap().starts_with("t[3] = 9"), "-O{}", level);
+            assert!(debugger.print("t").unwrap().contains("passed by reference"));
+            assert_eq!(debugger.print("a"), Err(DebuggerError::UnknownVariable("a".to_owned())));
+
+            let registers = debugger.registers();
+            assert!(registers.starts_with("assumed by the compiler at this statement"), "{}", registers);
+            assert_eq!(registers.lines().count(), 9);
+
+            debugger.execute("break 12", &mut io).unwrap();
+            debugger.execute("continue", &mut io).unwrap();
+            assert_eq!(debugger.print("t").unwrap().split("  ").next().unwrap(), "t = [0, 1, 4, 9]");
+            assert!(debugger.print("a").unwrap().starts_with("a = 9"), "-O{}", level);
+            assert_eq!(debugger.print("t[4]"), Err(DebuggerError::IndexOutOfBounds("t".to_owned(), BigInt::from(4))));
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+
+use crate::ast::Location;
+use crate::err::ModuleError;
+use crate::interpreter::InterpreterError;
+use crate::modules::SourceMap;
+use crate::translation::translation_structures::TranslationError;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// the way the diagnostics are printed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        return match name {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        };
+    }
+}
+
+// a single message about the program, pointing at the location in the source map if it has one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub kind: String,
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub location: Option<Location>,
+    pub message: String,
+    pub help: Option<String>,
+    pub lint: bool, // whether the diagnostic comes from one of the lints
+}
+
+impl Diagnostic {
+    pub fn error(kind: &str, location: Option<Location>, message: String, help: Option<String>) -> Self {
+        return Self{kind: kind.to_owned(), severity: Severity::Error, file: None, location, message, help, lint: false};
+    }
+
+    pub fn lint(name: &str, severity: Severity, location: Location, message: String, help: Option<String>) -> Self {
+        return Self{kind: name.to_owned(), severity, file: None, location: Some(location), message, help, lint: true};
+    }
+
+    pub fn from_translation_error(err: &TranslationError) -> Self {
+        let (kind, location, message, help) = match err {
+            TranslationError::NoSuchVariable(location, name) => ("NoSuchVariable", location, format!("No such variable: \"{}\"", name), None),
+            TranslationError::NoSuchProcedure(location, name) => ("NoSuchProcedure", location, format!("No such procedure: \"{}\"", name), None),
+            TranslationError::RepeatedDeclaration(location, name) => ("RepeatedDeclaration", location, format!("Repeated declaration of \"{}\"", name), None),
+            TranslationError::NotAnArray(location, name) => ("NotAnArray", location, format!("The variable \"{}\" has not been declared as an array", name), Some(format!("remove the indexing {}[...]", name))),
+            TranslationError::NoArrayIndex(location, name) => ("NoArrayIndex", location, format!("The variable \"{}\" has been declared as array, but no indexing was found", name), Some(format!("add indexing {}[...]", name))),
+            TranslationError::ArrayExpected(location, name) => ("ArrayExpected", location, format!("Expected an array variable, but got single variable \"{}\"", name), None),
+            TranslationError::VariableExpected(location, name) => ("VariableExpected", location, format!("Expected a single variable, but got array variable \"{}\"", name), None),
+            TranslationError::RecurrenceNotAllowed(location, name) => ("RecurrenceNotAllowed", location, format!("Recurrence is NOT allowed: invoking procedure \"{}\" inside itself", name), None),
+            TranslationError::InvalidNumberOfArguments(location, name) => ("InvalidNumberOfArguments", location, format!("Invalid number of arguments found while trying to call \"{}\"", name), None),
+            TranslationError::UninitialisedVariable(location, name) => ("UninitialisedVariable", location, format!("The variable \"{}\" is uninitialised", name), None),
+            TranslationError::ReferenceExpected(location, name) => ("ReferenceExpected", location, format!("Expected a variable to pass by reference as parameter \"{}\"", name), Some("declare the parameter as IN to pass it by value".to_owned())),
+            TranslationError::InParameterModified(location, name) => ("InParameterModified", location, format!("The IN parameter \"{}\" cannot be modified", name), Some("declare the parameter as INOUT".to_owned())),
+            TranslationError::ConstantModified(location, name) => ("ConstantModified", location, format!("The constant \"{}\" cannot be modified", name), None),
+            TranslationError::NegativeNumber(location, num) => ("NegativeNumber", location, format!("Negative number -{} in unsigned mode", num), Some("compile with --signed to use signed integers".to_owned())),
+            TranslationError::ArrayTooBig(location, name) => ("ArrayTooBig", location, format!("The array \"{}\" is too big to fit in memory", name), None),
+        };
+        return Self::error(kind, Some(*location), message, help);
+    }
+
+    // the error stopping the interpreter, located at the command it could not execute
+    pub fn from_interpreter_error(err: &InterpreterError) -> Self {
+
+        // the array too big for the interpreter is reported the way the compiler reports it
+
+        let kind = match err {
+            InterpreterError::ArrayTooBig(..) => "ArrayTooBig",
+            _ => "Runtime",
+        };
+        return Self::error(kind, err.location(), err.to_string(), None);
+    }
+
+    // the diagnostics of the error, one for every syntax error found in the file
+    pub fn from_module_error(err: &ModuleError) -> Vec<Self> {
+        let message = err.to_string();
+        let diagnostic = match err {
+            ModuleError::Io(path, _, location) => {
+                let mut diagnostic = Self::error("Io", *location, message, None);
+                if location.is_none() {
+                    diagnostic.file = Some(path.clone());
+                }
+                diagnostic
+            },
+            ModuleError::Syntax(_, errors) => {
+                return errors.iter().map(|(e, location)| Self::error("Syntax", Some(*location), e.clone(), None)).collect();
+            },
+            ModuleError::InvalidModuleName(_, _, location) => Self::error("InvalidModuleName", Some(*location), message, Some("module names may only contain the characters [_a-z0-9] and do not start with a digit".to_owned())),
+            ModuleError::NamespaceClash(_, _, _, location) => Self::error("NamespaceClash", *location, message, Some("rename one of the module files".to_owned())),
+            ModuleError::NoSuchStdModule(_, location) => Self::error("NoSuchStdModule", *location, message, None),
+        };
+        return vec![diagnostic];
+    }
+
+    // the diagnostic as a human-readable text, possibly spanning multiple lines
+    pub fn to_human(&self, source_map: &SourceMap) -> String {
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        let mut text = match self.lint {
+            true => format!("{}[{}]: {}", severity, self.kind, self.message),
+            false => format!("{}: {}", severity, self.message),
+        };
+        if let Some(location) = self.location {
+            text += &format!(" at {}", source_map.describe(location));
+        }
+        if let Some(help) = &self.help {
+            text += &format!("\nHELP: {}", help);
+        }
+        return text;
+    }
+
+    // the diagnostic as a single-line JSON object
+    pub fn to_json(&self, source_map: &SourceMap) -> String {
+        let mut fields = vec![
+            ("kind", json_string(&self.kind)),
+            ("severity", json_string(&self.severity.to_string())),
+        ];
+
+        let file = match self.location {
+            Some(location) if !source_map.files.is_empty() => {
+                let (file, (start, end)) = source_map.lookup(location);
+                let (line_start, column_start) = file.line_column(start);
+                let (line_end, column_end) = file.line_column(end);
+                fields.push(("line_start", line_start.to_string()));
+                fields.push(("column_start", column_start.to_string()));
+                fields.push(("line_end", line_end.to_string()));
+                fields.push(("column_end", column_end.to_string()));
+                fields.push(("byte_start", start.to_string()));
+                fields.push(("byte_end", end.to_string()));
+                Some(file.path.display().to_string())
+            },
+            _ => self.file.clone(),
+        };
+        fields.insert(2, ("file", file.map_or("null".to_owned(), |file| json_string(&file))));
+
+        fields.push(("message", json_string(&self.message)));
+        fields.push(("help", self.help.as_ref().map_or("null".to_owned(), |help| json_string(help))));
+
+        let members: Vec<String> = fields.iter().map(|(key, value)| format!("{}:{}", json_string(key), value)).collect();
+        return format!("{{{}}}", members.join(","));
+    }
+
+    pub fn render(&self, source_map: &SourceMap, format: ErrorFormat) -> String {
+        return match format {
+            ErrorFormat::Human => self.to_human(source_map),
+            ErrorFormat::Json => self.to_json(source_map),
+        };
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::modules::{load_program_source, SourceMap};
+    use crate::test_support::{load, TEST_PATH};
+    use crate::translation::translate;
+    use crate::translation::passes::PassManager;
+    use crate::translation::transformation::transform;
+    use super::*;
+
+    fn first_diagnostic(source: &str) -> (Diagnostic, SourceMap) {
+        let mut source_map = SourceMap::new();
+        let diagnostic = match load_program_source(Path::new(TEST_PATH), source.to_owned(), &mut source_map) {
+            Ok(mut ast) => {
+                let err = transform(&mut ast, &PassManager::default()).and_then(|_| translate(ast, &PassManager::default()).map(|_| ())).unwrap_err();
+                Diagnostic::from_translation_error(&err)
+            },
+            Err(e) => Diagnostic::from_module_error(&e).remove(0),
+        };
+        return (diagnostic, source_map);
+    }
+
+    #[test]
+    fn translation_error_as_json() {
+        let (diagnostic, source_map) = first_diagnostic("PROGRAM IS a IN\n  a := 1;\n  b := a;\nEND\n");
+        assert_eq!(diagnostic.to_json(&source_map), concat!(
+            r#"{"kind":"NoSuchVariable","severity":"error","file":"test.imp","#,
+            r#""line_start":3,"column_start":3,"line_end":3,"column_end":10,"byte_start":28,"byte_end":35,"#,
+            r#""message":"No such variable: \"b\"","help":null}"#,
+        ));
+    }
+
+    #[test]
+    fn syntax_error_as_json() {
+        let (diagnostic, source_map) = first_diagnostic("PROGRAM IS a IN\n  a := 1\nEND\n");
+        let json = diagnostic.to_json(&source_map);
+        assert!(json.starts_with(r#"{"kind":"Syntax","severity":"error","file":"test.imp","line_start":3,"column_start":1,"line_end":3,"column_end":4,"#), "{}", json);
+    }
+
+    #[test]
+    fn human_format_with_help() {
+        let (diagnostic, source_map) = first_diagnostic("PROGRAM IS a IN\n  a := -1;\nEND\n");
+        assert_eq!(diagnostic.to_human(&source_map), "Error: Negative number -1 in unsigned mode at bytes (18, 26) of \"test.imp\"\nHELP: compile with --signed to use signed integers");
+    }
+
+    #[test]
+    fn out_parameter_read_before_assignment() {
+        let (diagnostic, source_map) = first_diagnostic("PROCEDURE p(OUT r) IS IN\n  r := r + 1;\nEND\nPROGRAM IS a IN\n  p(a);\nEND\n");
+        assert_eq!(diagnostic.to_human(&source_map), "Error: The variable \"r\" is uninitialised at bytes (27, 38) of \"test.imp\"");
+        let (diagnostic, _) = first_diagnostic("PROCEDURE p(IN c, OUT r) IS IN\n  IF c > 0 THEN r := 1; ENDIF\n  WRITE r;\nEND\nPROGRAM IS a IN\n  p(1, a);\nEND\n");
+        assert_eq!(diagnostic.kind, "UninitialisedVariable");
+
+        // reads after an assignment on every path are fine
+
+        let mut ast = load("PROCEDURE p(IN c, OUT r) IS IN\n  IF c > 0 THEN r := 1; ELSE READ r; ENDIF\n  WRITE r;\nEND\nPROGRAM IS a IN\n  p(1, a);\nEND\n", &mut SourceMap::new());
+        assert!(transform(&mut ast, &PassManager::default()).is_ok());
+    }
+}
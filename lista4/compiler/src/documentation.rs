@@ -0,0 +1,55 @@
+use crate::ast::*;
+use crate::format::format_proc_head;
+
+// the reference of the procedures in Markdown: the signature of every one of them, followed by its description
+pub fn documentation(title: &str, procedures: &[Procedure]) -> String {
+    let mut text = format!("# {}\n", title);
+    for procedure in procedures {
+        text += &format!("\n## {}\n\n```\nPROCEDURE {}\n```\n", procedure.proc_head.name, format_proc_head(&procedure.proc_head));
+        if let Some(doc) = &procedure.doc {
+            text += &format!("\n{}\n", doc);
+        }
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::modules::{parse_module, SourceMap};
+    use crate::stdlib;
+    use super::*;
+
+    #[test]
+    fn procedures_with_descriptions() {
+        let source = "## Swaps a and b.\n##\n## Both are passed by reference.\nPROCEDURE swap(a, b) IS t IN t := a; a := b; b := t; END\n\n# a plain comment\nPROCEDURE zero(OUT x, T t) IS IN x := 0; END\n";
+        let module = parse_module(Path::new("swap.imp"), source.to_owned(), &mut SourceMap::new()).unwrap();
+        assert_eq!(documentation("swap.imp", &module.procedures), "\
+# swap.imp
+
+## swap
+
+```
+PROCEDURE swap(a, b)
+```
+
+Swaps a and b.
+
+Both are passed by reference.
+
+## zero
+
+```
+PROCEDURE zero(OUT x, T t)
+```
+");
+
+        // every procedure of the standard library is described
+
+        for name in stdlib::module_names() {
+            let module = parse_module(Path::new(name), stdlib::module_source(name).unwrap().to_owned(), &mut SourceMap::new()).unwrap();
+            assert!(module.procedures.iter().all(|procedure| procedure.doc.is_some()), "{}", name);
+        }
+    }
+}
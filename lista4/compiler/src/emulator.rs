@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use num::{BigInt, Zero, One, Signed, ToPrimitive};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Instruction {
+    Read,
+    Write,
+    Load(usize),
+    Store(usize),
+    Add(usize),
+    Sub(usize),
+    Get(usize),
+    Put(usize),
+    Rst(usize),
+    Inc(usize),
+    Dec(usize),
+    Shl(usize),
+    Shr(usize),
+    Jump(usize),
+    Jpos(usize),
+    Jzero(usize),
+    Strk(usize),
+    Jumpr(usize),
+    Halt,
+}
+
+impl Instruction {
+
+    // the cost of executing the instruction on the virtual machine
+    pub fn cost(&self) -> u64 {
+        return match self {
+            Instruction::Read | Instruction::Write => 100,
+            Instruction::Load(_) | Instruction::Store(_) => 50,
+            Instruction::Add(_) | Instruction::Sub(_) => 5,
+            Instruction::Halt => 0,
+            _ => 1,
+        };
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    InvalidInstruction(usize, String),
+    InvalidJump(usize, BigInt),
+    MissingInput(usize),
+    StepLimitExceeded(u64),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::InvalidInstruction(line, text) => write!(f, "Invalid instruction \"{}\" at line {}", text, line),
+            EmulatorError::InvalidJump(line, target) => write!(f, "Jump to a non-existent instruction {} at line {}", target, line),
+            EmulatorError::MissingInput(line) => write!(f, "No more input for READ at line {}", line),
+            EmulatorError::StepLimitExceeded(limit) => write!(f, "The program did not halt within {} steps", limit),
+        }
+    }
+}
+
+pub fn register_index(name: &str) -> Option<usize> {
+    return match name {
+        "a" => Some(0),
+        "b" => Some(1),
+        "c" => Some(2),
+        "d" => Some(3),
+        "e" => Some(4),
+        "f" => Some(5),
+        "g" => Some(6),
+        "h" => Some(7),
+        _ => None,
+    };
+}
+
+// parse a single line of virtual machine code, ignoring the comment
+pub fn parse_instruction(line_number: usize, line: &str) -> Result<Instruction, EmulatorError> {
+    let text = line.split('#').next().unwrap_or("").trim();
+    let invalid = || EmulatorError::InvalidInstruction(line_number, text.to_owned());
+
+    let mut words = text.split_whitespace();
+    let opcode = words.next().ok_or_else(invalid)?;
+    let operand = words.next();
+    if words.next().is_some() {
+        return Err(invalid());
+    }
+
+    let register = || operand.and_then(register_index).ok_or_else(invalid);
+    let target = || operand.and_then(|op| op.parse::<usize>().ok()).ok_or_else(invalid);
+
+    return match opcode {
+        "READ" if operand.is_none() => Ok(Instruction::Read),
+        "WRITE" if operand.is_none() => Ok(Instruction::Write),
+        "HALT" if operand.is_none() => Ok(Instruction::Halt),
+        "LOAD" => Ok(Instruction::Load(register()?)),
+        "STORE" => Ok(Instruction::Store(register()?)),
+        "ADD" => Ok(Instruction::Add(register()?)),
+        "SUB" => Ok(Instruction::Sub(register()?)),
+        "GET" => Ok(Instruction::Get(register()?)),
+        "PUT" => Ok(Instruction::Put(register()?)),
+        "RST" => Ok(Instruction::Rst(register()?)),
+        "INC" => Ok(Instruction::Inc(register()?)),
+        "DEC" => Ok(Instruction::Dec(register()?)),
+        "SHL" => Ok(Instruction::Shl(register()?)),
+        "SHR" => Ok(Instruction::Shr(register()?)),
+        "STRK" => Ok(Instruction::Strk(register()?)),
+        "JUMPR" => Ok(Instruction::Jumpr(register()?)),
+        "JUMP" => Ok(Instruction::Jump(target()?)),
+        "JPOS" => Ok(Instruction::Jpos(target()?)),
+        "JZERO" => Ok(Instruction::Jzero(target()?)),
+        _ => Err(invalid()),
+    };
+}
+
+pub fn parse_code(code: &[String]) -> Result<Vec<Instruction>, EmulatorError> {
+    return code.iter().enumerate().map(|(line_number, line)| parse_instruction(line_number, line)).collect();
+}
+
+// the source of READ values and the destination of WRITE values
+pub trait MachineIo {
+    fn read(&mut self) -> Option<BigInt>;
+    fn write(&mut self, value: &BigInt);
+}
+
+// input given up front, output collected for later inspection
+#[derive(Debug, Default)]
+pub struct BufferedIo {
+    pub input: VecDeque<BigInt>,
+    pub output: Vec<BigInt>,
+}
+
+impl BufferedIo {
+    pub fn new(input: &[BigInt]) -> Self {
+        return Self{input: input.iter().cloned().collect(), output: Vec::new()};
+    }
+}
+
+impl MachineIo for BufferedIo {
+    fn read(&mut self) -> Option<BigInt> {
+        return self.input.pop_front();
+    }
+
+    fn write(&mut self, value: &BigInt) {
+        self.output.push(value.clone());
+    }
+}
+
+// input read from the standard input as the machine asks for it, output printed right away
+#[derive(Debug, Default)]
+pub struct ConsoleIo {
+    pending: VecDeque<String>,
+}
+
+impl MachineIo for ConsoleIo {
+    fn read(&mut self) -> Option<BigInt> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            match std::io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => self.pending.extend(line.split_whitespace().map(|token| token.to_owned())),
+            }
+        }
+        return self.pending.pop_front().and_then(|token| token.parse().ok());
+    }
+
+    fn write(&mut self, value: &BigInt) {
+        println!("{}", value);
+    }
+}
+
+// the virtual machine: registers a-h, unbounded memory and the instruction counter
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub code: Vec<Instruction>,
+    pub registers: [BigInt; 8],
+    pub memory: HashMap<BigInt, BigInt>,
+    pub counter: usize,
+    pub cost: u64,
+    pub steps: u64,
+    pub halted: bool,
+}
+
+impl Machine {
+    pub fn new(code: Vec<Instruction>) -> Self {
+        return Self{code, registers: Default::default(), memory: HashMap::new(), counter: 0, cost: 0, steps: 0, halted: false};
+    }
+
+    pub fn load(&self, address: &BigInt) -> BigInt {
+        return self.memory.get(address).cloned().unwrap_or_else(BigInt::zero);
+    }
+
+    fn jump(&mut self, target: &BigInt) -> Result<(), EmulatorError> {
+        match target.to_usize() {
+            Some(line) if line < self.code.len() => {
+                self.counter = line;
+                Ok(())
+            },
+            _ => Err(EmulatorError::InvalidJump(self.counter, target.clone())),
+        }
+    }
+
+    // execute a single instruction
+    pub fn step(&mut self, io: &mut dyn MachineIo) -> Result<(), EmulatorError> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let instruction = match self.code.get(self.counter) {
+            Some(instruction) => *instruction,
+            None => return Err(EmulatorError::InvalidJump(self.counter, BigInt::from(self.counter))),
+        };
+        self.cost += instruction.cost();
+        self.steps += 1;
+
+        let mut next = self.counter + 1;
+        match instruction {
+            Instruction::Read => {
+                self.registers[0] = io.read().ok_or(EmulatorError::MissingInput(self.counter))?;
+            },
+            Instruction::Write => io.write(&self.registers[0]),
+            Instruction::Load(x) => self.registers[0] = self.load(&self.registers[x]),
+            Instruction::Store(x) => {
+                self.memory.insert(self.registers[x].clone(), self.registers[0].clone());
+            },
+            Instruction::Add(x) => self.registers[0] = &self.registers[0] + &self.registers[x],
+            Instruction::Sub(x) => {
+                let difference = &self.registers[0] - &self.registers[x];
+                self.registers[0] = if difference.is_negative() { BigInt::zero() } else { difference };
+            },
+            Instruction::Get(x) => self.registers[0] = self.registers[x].clone(),
+            Instruction::Put(x) => self.registers[x] = self.registers[0].clone(),
+            Instruction::Rst(x) => self.registers[x] = BigInt::zero(),
+            Instruction::Inc(x) => self.registers[x] += BigInt::one(),
+            Instruction::Dec(x) => {
+                if self.registers[x].is_positive() {
+                    self.registers[x] -= BigInt::one();
+                }
+            },
+            Instruction::Shl(x) => self.registers[x] <<= 1,
+            Instruction::Shr(x) => self.registers[x] >>= 1,
+            Instruction::Jump(j) => return self.jump(&BigInt::from(j)),
+            Instruction::Jpos(j) => {
+                if self.registers[0].is_positive() {
+                    return self.jump(&BigInt::from(j));
+                }
+            },
+            Instruction::Jzero(j) => {
+                if self.registers[0].is_zero() {
+                    return self.jump(&BigInt::from(j));
+                }
+            },
+            Instruction::Strk(x) => self.registers[x] = BigInt::from(self.counter),
+            Instruction::Jumpr(x) => return self.jump(&self.registers[x].clone()),
+            Instruction::Halt => {
+                self.halted = true;
+                next = self.counter;
+            },
+        }
+        self.counter = next;
+
+        Ok(())
+    }
+
+    // execute the instructions until HALT, or until the step limit is exceeded
+    pub fn run(&mut self, io: &mut dyn MachineIo, step_limit: Option<u64>) -> Result<(), EmulatorError> {
+        while !self.halted {
+            if let Some(limit) = step_limit {
+                if self.steps >= limit {
+                    return Err(EmulatorError::StepLimitExceeded(limit));
+                }
+            }
+            self.step(io)?;
+        }
+        Ok(())
+    }
+}
+
+// the result of running a program to completion
+#[derive(Debug, PartialEq, Eq)]
+pub struct Execution {
+    pub output: Vec<BigInt>,
+    pub cost: u64,
+}
+
+// run the compiled code with the given input
+pub fn run(code: &[String], input: &[BigInt], step_limit: Option<u64>) -> Result<Execution, EmulatorError> {
+    let mut machine = Machine::new(parse_code(code)?);
+    let mut io = BufferedIo::new(input);
+    machine.run(&mut io, step_limit)?;
+    return Ok(Execution{output: io.output, cost: machine.cost});
+}
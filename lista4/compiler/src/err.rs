@@ -1,13 +1,27 @@
 use std::fmt;
 
+use crate::ast::Location;
+
+// the errors of reading and parsing the program and its modules;
+// the locations are the ones of the IMPORT statements, if the module has been imported
 #[derive(Debug)]
-pub enum ImpError {
-    NumberTooBig(String),
+pub enum ModuleError {
+    Io(String, std::io::Error, Option<Location>),
+    Syntax(String, Vec<(String, Location)>), // every syntax error of the file, in order
+    InvalidModuleName(String, String, Location),
+    NamespaceClash(String, String, String, Option<Location>),
+    NoSuchStdModule(String, Option<Location>),
 }
 
-impl fmt::Display for ImpError {
+impl fmt::Display for ModuleError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let ImpError::NumberTooBig(number) = self;
-        write!(f, "Number {} is too big", number)
+        match self {
+            ModuleError::Io(path, e, _) => write!(f, "Cannot read \"{}\": {}", path, e),
+            ModuleError::Syntax(path, errors) if errors.len() == 1 => write!(f, "{} in \"{}\"", errors[0].0, path),
+            ModuleError::Syntax(path, errors) => write!(f, "{} in \"{}\" (and {} more syntax errors)", errors[0].0, path, errors.len() - 1),
+            ModuleError::InvalidModuleName(path, name, _) => write!(f, "Cannot import \"{}\": \"{}\" is not a valid module name", path, name),
+            ModuleError::NamespaceClash(name, first_path, second_path, _) => write!(f, "Modules \"{}\" and \"{}\" share the name \"{}\"", first_path, second_path, name),
+            ModuleError::NoSuchStdModule(path, _) => write!(f, "Cannot import \"{}\": there is no such module in the standard library", path),
+        }
     }
 }
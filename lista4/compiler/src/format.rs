@@ -0,0 +1,811 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::*;
+use crate::comments;
+use crate::translation::translation_structures::{InlinedRegion, InlinedRegions};
+use crate::visit::VisitorMut;
+
+const INDENT: &str = "  ";
+
+pub fn format_id(id: &Identifier) -> String {
+    return match id {
+        Identifier::Pid(pid) => pid.clone(),
+        Identifier::ArrNum(arr, idx) => format!("{}[{}]", arr, idx),
+        Identifier::ArrPid(arr, idx) => format!("{}[{}]", arr, idx),
+    };
+}
+
+pub fn format_value(value: &Value) -> String {
+    return match value {
+        Value::Number(num) => num.to_string(),
+        Value::Negative(num) => format!("-{}", num),
+        Value::Id(id) => format_id(id),
+    };
+}
+
+fn format_expr(expr: &Expression) -> String {
+    let (lhs, op, rhs) = match expr {
+        Expression::Val(value) => return format_value(value),
+        Expression::Add(lhs, rhs) => (lhs, "+", rhs),
+        Expression::Sub(lhs, rhs) => (lhs, "-", rhs),
+        Expression::Mul(lhs, rhs) => (lhs, "*", rhs),
+        Expression::Div(lhs, rhs) => (lhs, "/", rhs),
+        Expression::Mod(lhs, rhs) => (lhs, "%", rhs),
+    };
+    return format!("{} {} {}", format_value(lhs), op, format_value(rhs));
+}
+
+fn format_condition(condition: &Condition) -> String {
+    let (lhs, op, rhs) = match condition {
+        Condition::Equal(lhs, rhs) => (lhs, "=", rhs),
+        Condition::NotEqual(lhs, rhs) => (lhs, "!=", rhs),
+        Condition::Greater(lhs, rhs) => (lhs, ">", rhs),
+        Condition::Lesser(lhs, rhs) => (lhs, "<", rhs),
+        Condition::GreaterOrEqual(lhs, rhs) => (lhs, ">=", rhs),
+        Condition::LesserOrEqual(lhs, rhs) => (lhs, "<=", rhs),
+    };
+    return format!("{} {} {}", format_value(lhs), op, format_value(rhs));
+}
+
+// the command in a single line, without the commands nested in it
+pub fn format_command_head(command: &Command) -> String {
+    return match command {
+        Command::Assignment(id, expr, _) => format!("{} := {};", format_id(id), format_expr(expr)),
+        Command::IfElse(condition, _, _, _) | Command::If(condition, _, _) => format!("IF {} THEN", format_condition(condition)),
+        Command::While(condition, _, _) => format!("WHILE {} DO", format_condition(condition)),
+        Command::Repeat(_, condition, _) => format!("REPEAT ... UNTIL {};", format_condition(condition)),
+        Command::ProcedureCall(proc_call, _) => {
+            let args: Vec<String> = proc_call.args.iter().map(format_value).collect();
+            format!("{}({});", proc_call.name, args.join(", "))
+        },
+        Command::Read(id, _) => format!("READ {};", format_id(id)),
+        Command::Write(value, _) => format!("WRITE {};", format_value(value)),
+    };
+}
+
+fn format_declarations(declarations: &[Declaration]) -> String {
+    return declarations.iter().map(|decl| match decl {
+        Declaration::Var(pid, _) => pid.clone(),
+        Declaration::Arr(pid, size, _) => format!("{}[{}]", pid, size),
+    }).collect::<Vec<String>>().join(", ");
+}
+
+pub fn format_proc_head(proc_head: &ProcHead) -> String {
+    let args: Vec<String> = proc_head.args_decl.iter().map(|arg_decl| {
+        let (mode, name) = match arg_decl {
+            ArgumentDeclaration::Var(pid, mode, _) => (mode, pid.clone()),
+            ArgumentDeclaration::Arr(pid, mode, _) => (mode, format!("T {}", pid)),
+        };
+
+        // the parameters without a mode are passed both ways
+
+        return match mode {
+            ParameterMode::In => format!("IN {}", name),
+            ParameterMode::Out => format!("OUT {}", name),
+            ParameterMode::InOut => name,
+        };
+    }).collect();
+    return format!("{}({})", proc_head.name, args.join(", "));
+}
+
+// a comment of the source; the parser skips them, so they are found by a separate scan of the text
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Comment {
+    offset: usize,
+    text: String,
+    own_line: bool, // whether nothing but whitespace precedes the comment in its line
+}
+
+// the comments of the source, with the text they take up in it
+fn source_comments(source: &str, scanned: &[comments::Comment]) -> Vec<Comment> {
+    return scanned.iter().map(|comment| {
+        let (start, end) = comment.span;
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        Comment{offset: start, text: source[start..end].trim_end().to_owned(), own_line: source[line_start..start].trim().is_empty()}
+    }).collect();
+}
+
+// the lines of the formatted program, with the comments of the source put back in between them
+struct Printer<'a> {
+    source: &'a str,
+    code: String, // the source with the comments blanked out, for finding the keywords
+    comments: Vec<Comment>,
+    next_comment: usize,
+    lines: Vec<String>,
+    last_offset: usize, // where the code of the last line comes from
+    inlined: Option<&'a InlinedRegions>, // the expanded procedures to mark, for the transformed program
+    regions: Vec<InlinedRegion>, // the ones of the current procedure or main, by their first command
+    next_region: usize,
+    open_regions: Vec<usize>, // the regions started and not yet ended, innermost last
+    command_number: usize, // the commands of the current procedure or main printed, the nested ones included
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        let scanned = comments::scan(source);
+        let code = comments::blank(source, &scanned);
+        let comments = source_comments(source, &scanned);
+        return Self{
+            source, code, comments, next_comment: 0, lines: Vec::new(), last_offset: 0,
+            inlined: None, regions: Vec::new(), next_region: 0, open_regions: Vec::new(), command_number: 0,
+        };
+    }
+
+    // start numbering the commands of the procedure, or of main for None
+    fn enter_scope(&mut self, name: Option<&Pidentifier>) {
+        self.regions = self.inlined.and_then(|inlined| inlined.get(&name.cloned())).cloned().unwrap_or_default();
+        self.regions.sort_by_key(|region| (region.start, std::cmp::Reverse(region.len)));
+        self.next_region = 0;
+        self.open_regions.clear();
+        self.command_number = 0;
+    }
+
+    // mark the start of every region beginning with the next command
+    fn open_regions(&mut self, depth: usize) {
+        while let Some(region) = self.regions.get(self.next_region).filter(|region| region.start == self.command_number) {
+            self.lines.push(format!("{}# inlined {}", INDENT.repeat(depth), region.procedure));
+            self.open_regions.push(self.next_region);
+            self.next_region += 1;
+        }
+        self.command_number += 1;
+    }
+
+    // mark the end of every region ending with the last command
+    fn close_regions(&mut self, depth: usize) {
+        while let Some(region) = self.open_regions.last().map(|idx| &self.regions[*idx]).filter(|region| region.start + region.len == self.command_number) {
+            self.lines.push(format!("{}# end of {}", INDENT.repeat(depth), region.procedure));
+            self.open_regions.pop();
+        }
+    }
+
+    // print the comments found before the offset of the source: the ones in their own lines at the given depth,
+    // the ones following some code at the end of the last line
+    fn flush_comments(&mut self, offset: usize, depth: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).filter(|comment| comment.offset < offset) {
+            match self.lines.last_mut() {
+                Some(line) if !comment.own_line && !line.is_empty() => *line += &format!(" {}", comment.text),
+                _ => self.lines.push(format!("{}{}", INDENT.repeat(depth), comment.text)),
+            }
+            self.next_comment += 1;
+        }
+    }
+
+    // print the line of code coming from the offset of the source
+    fn line(&mut self, offset: usize, depth: usize, text: &str) {
+        self.flush_comments(offset, depth);
+        self.lines.push(format!("{}{}", INDENT.repeat(depth), text));
+        self.last_offset = offset;
+    }
+
+    // separate the lines with an empty one, after the comments ending the last line
+    fn blank_line(&mut self) {
+        while let Some(comment) = self.comments.get(self.next_comment)
+            .filter(|comment| !comment.own_line && !self.source[self.last_offset.min(comment.offset)..comment.offset].contains('\n')) {
+            if let Some(line) = self.lines.last_mut() {
+                *line += &format!(" {}", comment.text);
+            }
+            self.next_comment += 1;
+        }
+        self.lines.push(String::new());
+    }
+
+    // the offset of the last keyword between the offsets of the source, or the end if there is no source
+    fn keyword(&self, from: usize, to: usize, keyword: &str) -> usize {
+        let to = to.min(self.code.len());
+        return self.code.get(from..to).and_then(|code| code.rfind(keyword)).map_or(to, |position| from + position);
+    }
+
+    // whether the source separates the two offsets with an empty line
+    fn has_blank_line(&self, from: usize, to: usize) -> bool {
+        return self.source.get(from..to).is_some_and(|text| text.split('\n').skip(1).collect::<Vec<_>>().split_last().is_some_and(|(_, middle)| middle.iter().any(|line| line.trim().is_empty())));
+    }
+
+    fn commands(&mut self, commands: &[Command], depth: usize) {
+        let mut previous_end = None;
+
+        // the grammar needs a command in every block, so the transformed program gets one the constant folding removes again
+
+        if commands.is_empty() && self.inlined.is_some() {
+            self.lines.push(format!("{}IF 0 != 0 THEN # left empty by the transformation", INDENT.repeat(depth)));
+            self.lines.push(format!("{}WRITE 0;", INDENT.repeat(depth + 1)));
+            self.lines.push(format!("{}ENDIF", INDENT.repeat(depth)));
+        }
+
+        for command in commands {
+            let (start, end) = command.location();
+
+            // a single empty line is kept between the commands, before their comments
+
+            if let Some(previous_end) = previous_end {
+                let next = self.comments.get(self.next_comment).filter(|comment| comment.own_line && comment.offset < start).map_or(start, |comment| comment.offset);
+                if self.has_blank_line(previous_end, next) {
+                    self.blank_line();
+                }
+            }
+            previous_end = Some(end);
+            self.open_regions(depth);
+
+            match command {
+                Command::Assignment(id, expr, _) => self.line(start, depth, &format!("{} := {};", format_id(id), format_expr(expr))),
+                Command::IfElse(condition, if_commands, else_commands, _) => {
+                    self.line(start, depth, &format!("IF {} THEN", format_condition(condition)));
+                    self.commands(if_commands, depth + 1);
+                    let else_start = else_commands.first().map_or(end, |command| command.location().0);
+                    let else_keyword = self.keyword(if_commands.last().map_or(start, |command| command.location().1), else_start, "ELSE");
+                    self.flush_comments(else_keyword, depth + 1);
+                    self.line(else_keyword, depth, "ELSE");
+                    self.commands(else_commands, depth + 1);
+                    self.flush_comments(self.keyword(start, end, "ENDIF"), depth + 1);
+                    self.line(end, depth, "ENDIF");
+                },
+                Command::If(condition, commands, _) => {
+                    self.line(start, depth, &format!("IF {} THEN", format_condition(condition)));
+                    self.commands(commands, depth + 1);
+                    self.flush_comments(self.keyword(start, end, "ENDIF"), depth + 1);
+                    self.line(end, depth, "ENDIF");
+                },
+                Command::While(condition, commands, _) => {
+                    self.line(start, depth, &format!("WHILE {} DO", format_condition(condition)));
+                    self.commands(commands, depth + 1);
+                    self.flush_comments(self.keyword(start, end, "ENDWHILE"), depth + 1);
+                    self.line(end, depth, "ENDWHILE");
+                },
+                Command::Repeat(commands, condition, _) => {
+                    self.line(start, depth, "REPEAT");
+                    self.commands(commands, depth + 1);
+                    let until = self.keyword(start, end, "UNTIL");
+                    self.flush_comments(until, depth + 1);
+                    self.line(until, depth, &format!("UNTIL {};", format_condition(condition)));
+                },
+                Command::ProcedureCall(proc_call, _) => {
+                    let args: Vec<String> = proc_call.args.iter().map(format_value).collect();
+                    self.line(start, depth, &format!("{}({});", proc_call.name, args.join(", ")));
+                },
+                Command::Read(id, _) => self.line(start, depth, &format!("READ {};", format_id(id))),
+                Command::Write(value, _) => self.line(start, depth, &format!("WRITE {};", format_value(value))),
+            }
+            self.close_regions(depth);
+        }
+    }
+
+    // a procedure or main: the head with the declarations, the commands and the closing END before the offset
+    fn block(&mut self, head: &str, declarations: &Declarations, location: Location, commands: &Commands, end: usize) {
+        let head = match declarations.is_empty() {
+            true => format!("{} IS IN", head),
+            false => format!("{} IS {} IN", head, format_declarations(declarations)),
+        };
+        self.line(location.0, 0, &head);
+        self.commands(commands, 1);
+        let end = self.keyword(location.1, end, "END");
+        self.flush_comments(end, 1);
+        self.line(end, 0, "END");
+    }
+
+    fn imports(&mut self, imports: &Imports) {
+        if !imports.is_empty() {
+            for import in imports {
+                self.line(import.location.0, 0, &format!("IMPORT \"{}\";", import.path));
+            }
+            self.blank_line();
+        }
+    }
+
+    fn globals(&mut self, globals: &Globals) {
+        let (start, end) = globals.location;
+        self.line(start, 0, "GLOBALS");
+        self.line(start, 1, &format_declarations(&globals.declarations));
+        let end = self.code.get(end..).and_then(|code| code.find("END")).map_or(end, |position| end + position);
+        self.flush_comments(end, 1);
+        self.line(end, 0, "END");
+    }
+
+    fn procedure(&mut self, procedure: &Procedure, end: usize) {
+        self.enter_scope(Some(&procedure.proc_head.name));
+
+        // printed from the source, the description comes with the other comments
+
+        if let Some(doc) = procedure.doc.as_ref().filter(|_| self.source.is_empty()) {
+            for line in doc.lines() {
+                self.lines.push(match line.is_empty() {
+                    true => "##".to_owned(),
+                    false => format!("## {}", line),
+                });
+            }
+        }
+        self.block(&format!("PROCEDURE {}", format_proc_head(&procedure.proc_head)), &procedure.declarations, procedure.location, &procedure.commands, end);
+    }
+
+    fn program(&mut self, ast: &ProgramAll) {
+        self.imports(&ast.imports);
+
+        if !ast.constants.is_empty() {
+            for constant in &ast.constants {
+                self.line(constant.location.0, 0, &format!("CONST {} = {};", constant.name, format_value(&constant.value)));
+            }
+            self.blank_line();
+        }
+
+        if !ast.globals.declarations.is_empty() {
+            self.globals(&ast.globals);
+            self.blank_line();
+        }
+
+        let ends: Vec<usize> = ast.procedures.iter().skip(1).map(|procedure| procedure.location.0).chain([ast.main.location.0]).collect();
+        for (procedure, end) in ast.procedures.iter().zip(ends) {
+            self.procedure(procedure, end);
+            self.blank_line();
+        }
+
+        self.enter_scope(None);
+        self.block("PROGRAM", &ast.main.declarations, ast.main.location, &ast.main.commands, self.source.len());
+        self.flush_comments(usize::MAX, 0);
+    }
+
+    fn text(&self) -> String {
+        return self.lines.iter().map(|line| line.trim_end().to_owned() + "\n").collect();
+    }
+}
+
+// lay out the program in the canonical way: one command per line, blocks indented by two spaces
+// and the sections of the program separated by blank lines
+pub fn format_program(ast: &ProgramAll) -> String {
+    return format_program_source(ast, "");
+}
+
+// lay out the program parsed from the source, keeping the comments of the source in place
+pub fn format_program_source(ast: &ProgramAll, source: &str) -> String {
+    let mut printer = Printer::new(source);
+    printer.program(ast);
+    return printer.text();
+}
+
+// the procedures of the modules are named with their namespace, which the grammar only allows in the calls;
+// they get the name joined with an underscore instead, unique among the other procedures
+fn unqualified_names(ast: &ProgramAll) -> HashMap<Pidentifier, Pidentifier> {
+    let mut names: HashSet<Pidentifier> = ast.procedures.iter().map(|procedure| procedure.proc_head.name.clone()).collect();
+    let mut renamed = HashMap::new();
+    for procedure in &ast.procedures {
+        let name = &procedure.proc_head.name;
+        if name.contains('.') {
+            let mut new_name = name.replace('.', "_");
+            while names.contains(&new_name) {
+                new_name.insert(0, '_');
+            }
+            names.insert(new_name.clone());
+            renamed.insert(name.clone(), new_name);
+        }
+    }
+    return renamed;
+}
+
+// gives the calls the new names of the procedures
+struct CallRenamer<'a>(&'a HashMap<Pidentifier, Pidentifier>);
+
+impl VisitorMut for CallRenamer<'_> {
+    fn visit_proc_call_mut(&mut self, proc_call: &mut ProcCall, _location: Location) {
+        if let Some(name) = self.0.get(&proc_call.name) {
+            proc_call.name = name.clone();
+        }
+    }
+}
+
+// lay out the program as the transformation leaves it, marking the commands of the expanded procedures;
+// the imported procedures are already part of it and the constants are substituted, so only the rest is printed
+pub fn format_transformed_program(ast: &ProgramAll, inlined: &InlinedRegions) -> String {
+    let mut ast = ast.clone();
+    ast.imports.clear();
+    ast.constants.clear();
+
+    let renamed = unqualified_names(&ast);
+    for procedure in ast.procedures.iter_mut() {
+        if let Some(name) = renamed.get(&procedure.proc_head.name) {
+            procedure.proc_head.name = name.clone();
+        }
+    }
+    CallRenamer(&renamed).visit_program_mut(&mut ast);
+
+    // the regions are known by the original names of the procedures
+
+    let inlined: InlinedRegions = inlined.iter().map(|(name, regions)| {
+        (name.as_ref().map(|name| renamed.get(name).unwrap_or(name).clone()), regions.clone())
+    }).collect();
+
+    let mut printer = Printer::new("");
+    printer.inlined = Some(&inlined);
+    printer.program(&ast);
+    return printer.text();
+}
+
+// the first line differing between the source and its formatted form: the 1-based number and both versions
+pub fn first_difference<'a>(source: &'a str, formatted: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    let mut source_lines = source.lines();
+    let mut formatted_lines = formatted.lines();
+    for number in 1.. {
+        match (source_lines.next(), formatted_lines.next()) {
+            (None, None) => return None,
+            (old, new) if old != new => return Some((number, old.unwrap_or_default(), new.unwrap_or_default())),
+            _ => {},
+        }
+    }
+    return None;
+}
+
+// every node prints as the source code it can be parsed from, laid out the way `format_program` does it;
+// the nodes spanning several lines print without the final line break, except for whole files
+
+fn print_with(f: &mut fmt::Formatter<'_>, print: impl FnOnce(&mut Printer)) -> fmt::Result {
+    let mut printer = Printer::new("");
+    print(&mut printer);
+    return write!(f, "{}", printer.text().trim_end_matches('\n'));
+}
+
+impl fmt::Display for ProgramAll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_program(self))
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::new("");
+        printer.imports(&self.imports);
+        for procedure in &self.procedures {
+            printer.procedure(procedure, 0);
+            printer.blank_line();
+        }
+        writeln!(f, "{}", printer.text().trim_end_matches('\n'))
+    }
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IMPORT \"{}\";", self.path)
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CONST {} = {};", self.name, self.value)
+    }
+}
+
+// the program without global variables has no GLOBALS section at all
+impl fmt::Display for Globals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.declarations.is_empty() {
+            return Ok(());
+        }
+        print_with(f, |printer| printer.globals(self))
+    }
+}
+
+impl fmt::Display for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        print_with(f, |printer| printer.procedure(self, 0))
+    }
+}
+
+impl fmt::Display for Main {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        print_with(f, |printer| printer.block("PROGRAM", &self.declarations, self.location, &self.commands, 0))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        print_with(f, |printer| printer.commands(std::slice::from_ref(self), 0))
+    }
+}
+
+impl fmt::Display for ProcHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_proc_head(self))
+    }
+}
+
+impl fmt::Display for ProcCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(format_value).collect();
+        write!(f, "{}({})", self.name, args.join(", "))
+    }
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_declarations(std::slice::from_ref(self)))
+    }
+}
+
+impl fmt::Display for ArgumentDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let head = format_proc_head(&ProcHead::new(String::new(), vec![self.clone()]));
+        write!(f, "{}", &head[1..head.len() - 1])
+    }
+}
+
+// the default mode has no keyword
+impl fmt::Display for ParameterMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterMode::In => write!(f, "IN"),
+            ParameterMode::Out => write!(f, "OUT"),
+            ParameterMode::InOut => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_expr(self))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_condition(self))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_value(self))
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_id(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::parse;
+    use crate::translation::passes::PassManager;
+    use crate::translation::transformation::transform_with_regions;
+    use crate::translation::translate;
+    use crate::translation::translation_structures::strip_annotations;
+    use super::*;
+
+    fn format_source(source: &str) -> String {
+        return format_program_source(&parse(source), source);
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let source = "IMPORT \"std:math\"; CONST n = -4;\nGLOBALS g END PROCEDURE p(IN a, T t, OUT b) IS IN b := a - n; t[a] := b; END\n\
+            PROGRAM IS x, t[3] IN READ x; IF x > 0 THEN p(x, t, x); ELSE REPEAT x := x + 1; UNTIL x >= 3; ENDIF WHILE x != 0 DO x := x / 2; ENDWHILE WRITE t[x]; math.gcd(x, g, x); END";
+        let expected = "\
+IMPORT \"std:math\";
+
+CONST n = -4;
+
+GLOBALS
+  g
+END
+
+PROCEDURE p(IN a, T t, OUT b) IS IN
+  b := a - n;
+  t[a] := b;
+END
+
+PROGRAM IS x, t[3] IN
+  READ x;
+  IF x > 0 THEN
+    p(x, t, x);
+  ELSE
+    REPEAT
+      x := x + 1;
+    UNTIL x >= 3;
+  ENDIF
+  WHILE x != 0 DO
+    x := x / 2;
+  ENDWHILE
+  WRITE t[x];
+  math.gcd(x, g, x);
+END
+";
+        let formatted = format_program(&parse(source));
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&parse(&formatted)), expected);
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let source = "\
+(* the (* nested *) counter *)
+CONST k = 3; # three
+GLOBALS g # the total
+END
+## adds a and k
+PROCEDURE p(IN a) IS IN # nothing local
+    g:=g+a;   # add
+
+
+    # and once more
+  g := g + k;
+  END
+PROGRAM IS x IN
+READ x; IF x > 0 THEN # positive
+p(x);
+# nothing else
+ELSE
+  # negative
+  x := 0;
+  # still nothing
+ENDIF
+REPEAT x := x - 1; # down
+# until zero
+UNTIL x = 0;
+WRITE g; END # done
+# the end
+";
+        let expected = "\
+(* the (* nested *) counter *)
+CONST k = 3; # three
+
+GLOBALS
+  g # the total
+END
+
+## adds a and k
+PROCEDURE p(IN a) IS IN # nothing local
+  g := g + a; # add
+
+  # and once more
+  g := g + k;
+END
+
+PROGRAM IS x IN
+  READ x;
+  IF x > 0 THEN # positive
+    p(x);
+    # nothing else
+  ELSE
+    # negative
+    x := 0;
+    # still nothing
+  ENDIF
+  REPEAT
+    x := x - 1; # down
+    # until zero
+  UNTIL x = 0;
+  WRITE g;
+END # done
+# the end
+";
+        assert_eq!(format_source(source), expected);
+        assert_eq!(format_source(expected), expected);
+        assert_eq!(first_difference(source, expected), Some((3, "GLOBALS g # the total", "")));
+        assert_eq!(first_difference(expected, expected), None);
+    }
+
+    // the locations of the nodes, which differ between the source and its printed form
+    fn without_locations(commands: &mut [Command]) {
+        for command in commands {
+            match command {
+                Command::Assignment(_, _, location) | Command::ProcedureCall(_, location) | Command::Read(_, location) | Command::Write(_, location) => *location = (0, 0),
+                Command::IfElse(_, if_commands, else_commands, location) => {
+                    without_locations(if_commands);
+                    without_locations(else_commands);
+                    *location = (0, 0);
+                },
+                Command::If(_, commands, location) | Command::While(_, commands, location) | Command::Repeat(commands, _, location) => {
+                    without_locations(commands);
+                    *location = (0, 0);
+                },
+            }
+        }
+    }
+
+    fn declarations_without_locations(declarations: &mut [Declaration]) {
+        for decl in declarations {
+            let (Declaration::Var(_, location) | Declaration::Arr(_, _, location)) = decl;
+            *location = (0, 0);
+        }
+    }
+
+    fn parse_without_locations(source: &str) -> ProgramAll {
+        let mut ast = parse(source);
+        for import in ast.imports.iter_mut() {
+            import.location = (0, 0);
+        }
+        for constant in ast.constants.iter_mut() {
+            constant.location = (0, 0);
+        }
+        ast.globals.location = (0, 0);
+        declarations_without_locations(&mut ast.globals.declarations);
+        for procedure in ast.procedures.iter_mut() {
+            procedure.location = (0, 0);
+            for arg_decl in procedure.proc_head.args_decl.iter_mut() {
+                let (ArgumentDeclaration::Var(_, _, location) | ArgumentDeclaration::Arr(_, _, location)) = arg_decl;
+                *location = (0, 0);
+            }
+            declarations_without_locations(&mut procedure.declarations);
+            without_locations(&mut procedure.commands);
+        }
+        ast.main.location = (0, 0);
+        declarations_without_locations(&mut ast.main.declarations);
+        without_locations(&mut ast.main.commands);
+        return ast;
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let source = "IMPORT \"std:math\"; CONST n = -4; CONST m = 7;\nPROCEDURE p(IN a, T t, OUT b, c) IS x, u[2] IN b := a % n; t[a] := b; c := u[x]; END\n\
+            PROCEDURE q(T t) IS IN p(1, t, t[0], t[1]); END\n\
+            PROGRAM IS x, t[3] IN READ x; IF x > 0 THEN p(x, t, x, x); ELSE REPEAT x := x * 1; UNTIL x >= 3; ENDIF WHILE x != 0 DO IF x <= n THEN x := 0; ENDIF ENDWHILE WRITE t[x]; math.gcd(x, m, x); END";
+        let ast = parse_without_locations(source);
+        assert_eq!(parse_without_locations(&ast.to_string()), ast);
+
+        // the descriptions of the procedures
+
+        let ast = parse_without_locations(&source.replace("PROCEDURE q", "## Calls p.\n##\n## Twice.\nPROCEDURE q"));
+        assert!(ast.to_string().contains("END\n\n## Calls p.\n##\n## Twice.\nPROCEDURE q(T t)"), "{}", ast);
+        assert_eq!(parse_without_locations(&ast.to_string()), ast);
+
+        // without the optional sections
+
+        let ast = parse_without_locations("PROGRAM IS IN WRITE 1; END");
+        assert_eq!(ast.to_string(), "PROGRAM IS IN\n  WRITE 1;\nEND\n");
+        assert_eq!(parse_without_locations(&ast.to_string()), ast);
+
+        // the single nodes
+
+        let ast = parse_without_locations(source);
+        assert_eq!(ast.procedures[0].proc_head.to_string(), "p(IN a, T t, OUT b, c)");
+        assert_eq!(ast.procedures[0].proc_head.args_decl[1].to_string(), "T t");
+        assert_eq!(ast.procedures[0].declarations[1].to_string(), "u[2]");
+        assert_eq!(ast.procedures[0].commands[0].to_string(), "b := a % n;");
+        assert_eq!(ast.constants[0].to_string(), "CONST n = -4;");
+        assert_eq!(ast.imports[0].to_string(), "IMPORT \"std:math\";");
+        assert_eq!(ast.main.commands[2].to_string(), "WHILE x != 0 DO\n  IF x <= n THEN\n    x := 0;\n  ENDIF\nENDWHILE");
+        assert_eq!(ast.globals.to_string(), "");
+    }
+
+    #[test]
+    fn transformed_program() {
+        let source = "GLOBALS g END\nPROCEDURE inc(x) IS IN x := x + 1; END\n\
+            PROCEDURE twice(IN v, y) IS t IN t := v; inc(t); IF t > 10 THEN inc(t); ENDIF y := t; END\n\
+            PROGRAM IS t, n IN READ n; twice(n, g); WHILE n > 0 DO IF 1 > 2 THEN n := 0; ENDIF ENDWHILE WRITE g; END";
+        let compile = |source: &str| {
+            let mut ast = parse(source);
+            let passes = PassManager::for_level(2);
+            let inlined = transform_with_regions(&mut ast, &passes).unwrap();
+            let transformed = format_transformed_program(&ast, &inlined);
+            return (transformed, strip_annotations(&translate(ast, &passes).unwrap()));
+        };
+
+        let (transformed, code) = compile(source);
+        assert_eq!(transformed, "\
+GLOBALS
+  g
+END
+
+PROGRAM IS t, n, _t, v IN
+  READ n;
+  # inlined twice
+  v := n;
+  _t := v;
+  # inlined inc
+  _t := _t + 1;
+  # end of inc
+  IF _t > 10 THEN
+    # inlined inc
+    _t := _t + 1;
+    # end of inc
+  ENDIF
+  g := _t;
+  # end of twice
+  WHILE n > 0 DO
+    IF 0 != 0 THEN # left empty by the transformation
+      WRITE 0;
+    ENDIF
+  ENDWHILE
+  WRITE g;
+END
+");
+        assert_eq!(compile(&transformed).1, code);
+    }
+}
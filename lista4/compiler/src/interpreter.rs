@@ -0,0 +1,441 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::iter::zip;
+use num::{BigInt, Integer, Zero};
+
+use crate::ast::*;
+use crate::emulator::{BufferedIo, MachineIo};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InterpreterError {
+    NoSuchVariable(Location, Pidentifier),
+    NoSuchProcedure(Location, Pidentifier),
+    RecurrenceNotAllowed(Location, Pidentifier),
+    InvalidNumberOfArguments(Location, Pidentifier),
+    NotAnArray(Location, Pidentifier),
+    NoArrayIndex(Location, Pidentifier),
+    ArrayExpected(Location, Pidentifier),
+    VariableExpected(Location, Pidentifier),
+    ReferenceExpected(Location, Pidentifier),
+    ConstantModified(Location, Pidentifier),
+    NegativeNumber(Location, Num),
+    UninitialisedVariable(Location, Pidentifier),
+    IndexOutOfBounds(Location, Pidentifier, BigInt),
+    ArrayTooBig(Location, Pidentifier),
+    MissingInput(Location),
+    StepLimitExceeded(u64),
+}
+
+impl InterpreterError {
+    // the command where the program went wrong, if there is one
+    pub fn location(&self) -> Option<Location> {
+        return match self {
+            InterpreterError::NoSuchVariable(location, _)
+            | InterpreterError::NoSuchProcedure(location, _)
+            | InterpreterError::RecurrenceNotAllowed(location, _)
+            | InterpreterError::InvalidNumberOfArguments(location, _)
+            | InterpreterError::NotAnArray(location, _)
+            | InterpreterError::NoArrayIndex(location, _)
+            | InterpreterError::ArrayExpected(location, _)
+            | InterpreterError::VariableExpected(location, _)
+            | InterpreterError::ReferenceExpected(location, _)
+            | InterpreterError::ConstantModified(location, _)
+            | InterpreterError::NegativeNumber(location, _)
+            | InterpreterError::UninitialisedVariable(location, _)
+            | InterpreterError::IndexOutOfBounds(location, _, _)
+            | InterpreterError::ArrayTooBig(location, _)
+            | InterpreterError::MissingInput(location) => Some(*location),
+            InterpreterError::StepLimitExceeded(_) => None,
+        };
+    }
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpreterError::NoSuchVariable(_, name) => write!(f, "No such variable: \"{}\"", name),
+            InterpreterError::NoSuchProcedure(_, name) => write!(f, "No such procedure: \"{}\"", name),
+            InterpreterError::RecurrenceNotAllowed(_, name) => write!(f, "Recurrence is NOT allowed: invoking procedure \"{}\" inside itself", name),
+            InterpreterError::InvalidNumberOfArguments(_, name) => write!(f, "Invalid number of arguments found while trying to call \"{}\"", name),
+            InterpreterError::NotAnArray(_, name) => write!(f, "The variable \"{}\" has not been declared as an array", name),
+            InterpreterError::NoArrayIndex(_, name) => write!(f, "The variable \"{}\" has been declared as array, but no indexing was found", name),
+            InterpreterError::ArrayExpected(_, name) => write!(f, "Expected an array variable, but got single variable \"{}\"", name),
+            InterpreterError::VariableExpected(_, name) => write!(f, "Expected a single variable, but got array variable \"{}\"", name),
+            InterpreterError::ReferenceExpected(_, name) => write!(f, "Expected a variable to pass by reference as parameter \"{}\"", name),
+            InterpreterError::ConstantModified(_, name) => write!(f, "The constant \"{}\" cannot be modified", name),
+            InterpreterError::NegativeNumber(_, num) => write!(f, "Negative number -{} in unsigned mode", num),
+            InterpreterError::UninitialisedVariable(_, name) => write!(f, "The variable \"{}\" is read before anything is stored in it", name),
+            InterpreterError::IndexOutOfBounds(_, name, index) => write!(f, "The index {} is out of the bounds of the array \"{}\"", index, name),
+            InterpreterError::ArrayTooBig(_, name) => write!(f, "The array \"{}\" is too big to fit in memory", name),
+            InterpreterError::MissingInput(_) => write!(f, "No more input for READ"),
+            InterpreterError::StepLimitExceeded(limit) => write!(f, "The program did not halt within {} steps", limit),
+        }
+    }
+}
+
+// the arrays have to end within the first half of the cells, which leaves the variables and the IN parameters
+// more cells than a program can ever use
+const ARRAY_CELLS: usize = usize::MAX / 2;
+
+// what a name stands for in a procedure or main: a memory cell, a run of cells or a constant
+#[derive(Debug, Clone)]
+enum Binding {
+    Var(usize),
+    Arr(usize, usize), // the first cell and the length
+    Const(Value),
+}
+
+type Scope = HashMap<Pidentifier, Binding>;
+
+// the program run directly on its syntax tree, as the reference for what the compiled code should do;
+// every value is a BigInt, only the cells something has been stored in are kept in the memory,
+// and the parameters other than the IN variables are bound to the cells of the caller
+pub struct Interpreter<'a> {
+    program: &'a ProgramAll,
+    signed: bool,
+    memory: BTreeMap<usize, BigInt>,
+    next_cell: usize, // the first cell not allocated yet
+    pub steps: u64, // the commands executed so far
+    step_limit: Option<u64>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a ProgramAll, signed: bool, step_limit: Option<u64>) -> Self {
+        return Self{program, signed, memory: BTreeMap::new(), next_cell: 0, steps: 0, step_limit};
+    }
+
+    // run main, the globals and the constants being visible everywhere
+    pub fn run(&mut self, io: &mut dyn MachineIo) -> Result<(), InterpreterError> {
+        let mut globals = Scope::new();
+        for constant in &self.program.constants {
+            globals.insert(constant.name.clone(), Binding::Const(constant.value.clone()));
+        }
+        self.allocate(&self.program.globals.declarations, &mut globals)?;
+
+        let mut scope = globals.clone();
+        self.allocate(&self.program.main.declarations, &mut scope)?;
+        return self.commands(&self.program.main.commands, &scope, &globals, self.program.procedures.len(), io);
+    }
+
+    // fresh cells for the declared variables, holding nothing yet
+    fn allocate(&mut self, declarations: &Declarations, scope: &mut Scope) -> Result<(), InterpreterError> {
+        for declaration in declarations {
+            match declaration {
+                Declaration::Var(pid, _) => {
+                    scope.insert(pid.clone(), Binding::Var(self.next_cell));
+                    self.next_cell += 1;
+                },
+                Declaration::Arr(pid, len, location) => {
+                    let end = len.try_into().ok().and_then(|len: usize| self.next_cell.checked_add(len))
+                        .filter(|&end| end <= ARRAY_CELLS)
+                        .ok_or_else(|| InterpreterError::ArrayTooBig(*location, pid.clone()))?;
+                    scope.insert(pid.clone(), Binding::Arr(self.next_cell, end - self.next_cell));
+                    self.next_cell = end;
+                },
+            }
+        }
+        return Ok(());
+    }
+
+    // run the commands of the procedure with the given number, which may only call the ones before it
+    fn commands(&mut self, commands: &Commands, scope: &Scope, globals: &Scope, procedure: usize, io: &mut dyn MachineIo) -> Result<(), InterpreterError> {
+        for command in commands {
+            self.steps += 1;
+            if let Some(limit) = self.step_limit {
+                if self.steps > limit {
+                    return Err(InterpreterError::StepLimitExceeded(limit));
+                }
+            }
+
+            match command {
+                Command::Assignment(id, expr, location) => {
+                    let value = self.expression(expr, scope, *location)?;
+                    let cell = self.cell(id, scope, *location)?;
+                    self.memory.insert(cell, value);
+                },
+                Command::IfElse(condition, if_commands, else_commands, location) => {
+                    match self.condition(condition, scope, *location)? {
+                        true => self.commands(if_commands, scope, globals, procedure, io)?,
+                        false => self.commands(else_commands, scope, globals, procedure, io)?,
+                    }
+                },
+                Command::If(condition, commands, location) => {
+                    if self.condition(condition, scope, *location)? {
+                        self.commands(commands, scope, globals, procedure, io)?;
+                    }
+                },
+                Command::While(condition, commands, location) => {
+                    while self.condition(condition, scope, *location)? {
+                        self.commands(commands, scope, globals, procedure, io)?;
+                    }
+                },
+                Command::Repeat(commands, condition, location) => {
+                    loop {
+                        self.commands(commands, scope, globals, procedure, io)?;
+                        if self.condition(condition, scope, *location)? {
+                            break;
+                        }
+                    }
+                },
+                Command::ProcedureCall(proc_call, location) => self.call(proc_call, scope, globals, procedure, *location, io)?,
+                Command::Read(id, location) => {
+                    let cell = self.cell(id, scope, *location)?;
+                    let value = io.read().ok_or(InterpreterError::MissingInput(*location))?;
+                    self.memory.insert(cell, value);
+                },
+                Command::Write(value, location) => {
+                    let value = self.value(value, scope, *location)?;
+                    io.write(&value);
+                },
+            }
+        }
+        return Ok(());
+    }
+
+    fn call(&mut self, proc_call: &ProcCall, scope: &Scope, globals: &Scope, caller: usize, location: Location, io: &mut dyn MachineIo) -> Result<(), InterpreterError> {
+        let name = &proc_call.name;
+        if self.program.procedures.get(caller).is_some_and(|procedure| &procedure.proc_head.name == name) {
+            return Err(InterpreterError::RecurrenceNotAllowed(location, name.clone()));
+        }
+        let (index, procedure) = self.program.procedures[..caller].iter().enumerate()
+            .find(|(_, procedure)| &procedure.proc_head.name == name)
+            .ok_or_else(|| InterpreterError::NoSuchProcedure(location, name.clone()))?;
+        if procedure.proc_head.args_decl.len() != proc_call.args.len() {
+            return Err(InterpreterError::InvalidNumberOfArguments(location, name.clone()));
+        }
+
+        // the IN variables get a copy of the argument, the other parameters share the cells of the caller
+
+        let mark = self.next_cell;
+        let mut callee = globals.clone();
+        for (arg_decl, arg) in zip(&procedure.proc_head.args_decl, &proc_call.args) {
+            let binding = match (arg_decl, arg) {
+                (ArgumentDeclaration::Var(_, ParameterMode::In, _), _) => {
+                    let value = self.value(arg, scope, location)?;
+                    self.memory.insert(self.next_cell, value);
+                    self.next_cell += 1;
+                    Binding::Var(self.next_cell - 1)
+                },
+                (ArgumentDeclaration::Var(param, _, _), Value::Id(Identifier::Pid(pid))) => match scope.get(pid) {
+                    Some(Binding::Var(cell)) => Binding::Var(*cell),
+                    Some(Binding::Arr(..)) => return Err(InterpreterError::VariableExpected(location, pid.clone())),
+                    Some(Binding::Const(_)) => return Err(InterpreterError::ReferenceExpected(location, param.clone())),
+                    None => return Err(InterpreterError::NoSuchVariable(location, pid.clone())),
+                },
+                (ArgumentDeclaration::Arr(_, _, _), Value::Id(Identifier::Pid(pid))) => match scope.get(pid) {
+                    Some(Binding::Arr(start, len)) => Binding::Arr(*start, *len),
+                    Some(_) => return Err(InterpreterError::ArrayExpected(location, pid.clone())),
+                    None => return Err(InterpreterError::NoSuchVariable(location, pid.clone())),
+                },
+                (ArgumentDeclaration::Var(param, _, _) | ArgumentDeclaration::Arr(param, _, _), _) => {
+                    return Err(InterpreterError::ReferenceExpected(location, param.clone()));
+                },
+            };
+            let (ArgumentDeclaration::Var(param, _, _) | ArgumentDeclaration::Arr(param, _, _)) = arg_decl;
+            callee.insert(param.clone(), binding);
+        }
+        self.allocate(&procedure.declarations, &mut callee)?;
+        self.commands(&procedure.commands, &callee, globals, index, io)?;
+
+        // the cells of the call are not needed anymore
+
+        self.memory.split_off(&mark);
+        self.next_cell = mark;
+        return Ok(());
+    }
+
+    // the cell the identifier refers to when something is stored in it
+    fn cell(&self, id: &Identifier, scope: &Scope, location: Location) -> Result<usize, InterpreterError> {
+        let (Identifier::Pid(pid) | Identifier::ArrNum(pid, _) | Identifier::ArrPid(pid, _)) = id;
+        let binding = scope.get(pid).ok_or_else(|| InterpreterError::NoSuchVariable(location, pid.clone()))?;
+        let index = match id {
+            Identifier::Pid(_) => None,
+            Identifier::ArrNum(_, num) => Some(num.clone()),
+            Identifier::ArrPid(_, idx) => Some(self.value(&Value::Id(Identifier::Pid(idx.clone())), scope, location)?),
+        };
+        return match (binding, index) {
+            (Binding::Var(cell), None) => Ok(*cell),
+            (Binding::Arr(..), None) => Err(InterpreterError::NoArrayIndex(location, pid.clone())),
+            (Binding::Var(_), Some(_)) => Err(InterpreterError::NotAnArray(location, pid.clone())),
+            (Binding::Arr(start, len), Some(index)) => match usize::try_from(&index) {
+                Ok(offset) if offset < *len => Ok(start + offset),
+                _ => Err(InterpreterError::IndexOutOfBounds(location, pid.clone(), index)),
+            },
+            (Binding::Const(_), _) => Err(InterpreterError::ConstantModified(location, pid.clone())),
+        };
+    }
+
+    fn value(&self, value: &Value, scope: &Scope, location: Location) -> Result<BigInt, InterpreterError> {
+        if let Value::Id(Identifier::Pid(pid)) = value {
+            if let Some(Binding::Const(constant)) = scope.get(pid) {
+                return self.value(constant, scope, location);
+            }
+        }
+        return match value {
+            Value::Number(num) => Ok(num.clone()),
+            Value::Negative(num) if self.signed => Ok(-num),
+            Value::Negative(num) => Err(InterpreterError::NegativeNumber(location, num.clone())),
+            Value::Id(id) => {
+                let cell = self.cell(id, scope, location)?;
+                let (Identifier::Pid(pid) | Identifier::ArrNum(pid, _) | Identifier::ArrPid(pid, _)) = id;
+                self.memory.get(&cell).cloned().ok_or_else(|| InterpreterError::UninitialisedVariable(location, pid.clone()))
+            },
+        };
+    }
+
+    // the arithmetic of the machine: in the unsigned mode the subtraction stops at 0, in the signed mode
+    // the division rounds down and the remainder takes the sign of the divisor; dividing by 0 gives 0
+    fn expression(&self, expr: &Expression, scope: &Scope, location: Location) -> Result<BigInt, InterpreterError> {
+        let (lhs, rhs) = match expr {
+            Expression::Val(value) => return self.value(value, scope, location),
+            Expression::Add(lhs, rhs) | Expression::Sub(lhs, rhs) | Expression::Mul(lhs, rhs) | Expression::Div(lhs, rhs) | Expression::Mod(lhs, rhs) => (lhs, rhs),
+        };
+        let (a, b) = (self.value(lhs, scope, location)?, self.value(rhs, scope, location)?);
+        return Ok(match expr {
+            Expression::Add(..) => a + b,
+            Expression::Sub(..) if !self.signed && a < b => BigInt::zero(),
+            Expression::Sub(..) => a - b,
+            Expression::Mul(..) => a * b,
+            Expression::Div(..) | Expression::Mod(..) if b.is_zero() => BigInt::zero(),
+            Expression::Div(..) => a.div_floor(&b),
+            Expression::Mod(..) => a.mod_floor(&b),
+            Expression::Val(_) => unreachable!(),
+        });
+    }
+
+    fn condition(&self, condition: &Condition, scope: &Scope, location: Location) -> Result<bool, InterpreterError> {
+        let (Condition::Equal(lhs, rhs)
+        | Condition::NotEqual(lhs, rhs)
+        | Condition::Greater(lhs, rhs)
+        | Condition::Lesser(lhs, rhs)
+        | Condition::GreaterOrEqual(lhs, rhs)
+        | Condition::LesserOrEqual(lhs, rhs)) = condition;
+        let (a, b) = (self.value(lhs, scope, location)?, self.value(rhs, scope, location)?);
+        return Ok(match condition {
+            Condition::Equal(..) => a == b,
+            Condition::NotEqual(..) => a != b,
+            Condition::Greater(..) => a > b,
+            Condition::Lesser(..) => a < b,
+            Condition::GreaterOrEqual(..) => a >= b,
+            Condition::LesserOrEqual(..) => a <= b,
+        });
+    }
+}
+
+// the output of the program for the given input, the oracle for the compiled code
+pub fn interpret(program: &ProgramAll, input: &[BigInt], signed: bool, step_limit: Option<u64>) -> Result<Vec<BigInt>, InterpreterError> {
+    let mut io = BufferedIo::new(input);
+    Interpreter::new(program, signed, step_limit).run(&mut io)?;
+    return Ok(io.output);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modules::SourceMap;
+    use crate::test_support::{compile_with, load, load_signed, numbers, run, run_signed, STEP_LIMIT};
+    use crate::translation::passes::{PassManager, MAX_LEVEL};
+    use super::*;
+
+    const PROGRAM: &str = "
+        IMPORT \"std:arrays\";
+        CONST k = 3;
+        GLOBALS calls END
+        PROCEDURE swap(a, b) IS t IN
+          t := a; a := b; b := t;
+          calls := calls + 1;
+        END
+        PROCEDURE fill(T t, IN n, IN x) IS i, v IN
+          i := 0;
+          WHILE i < n DO
+            v := x * i; v := v % 7;
+            t[i] := v + k;
+            i := i + 1;
+          ENDWHILE
+        END
+        PROCEDURE sum(T t, IN n, OUT s) IS i IN
+          s := 0; i := 0;
+          REPEAT
+            s := s + t[i];
+            i := i + 1;
+          UNTIL i >= n;
+        END
+        PROGRAM IS a, b, c, n, t[10] IN
+          calls := 0;
+          READ a; READ b;
+          swap(a, b); WRITE a; WRITE b;
+          c := a - b; WRITE c;
+          c := a / b; WRITE c;
+          c := a % b; WRITE c;
+          c := a * b; WRITE c;
+          n := a % 9; n := n + 1;
+          fill(t, n, b); sum(t, n, c); WRITE c;
+          arrays.sort(t, n); WRITE t[0];
+          swap(c, n); WRITE n;
+          IF a > b THEN swap(a, b); ELSE WRITE b; ENDIF
+          WRITE calls;
+        END
+    ";
+
+    #[test]
+    fn same_output_as_the_compiled_code() {
+        let ast = load(PROGRAM, &mut SourceMap::new());
+        for level in 0..=MAX_LEVEL {
+            let code = compile_with(ast.clone(), &PassManager::for_level(level));
+            for (a, b) in [(0, 0), (1, 5), (7, 3), (12, 0), (100, 101), (123456789, 1000)] {
+                let input = numbers(&[a, b]);
+                let expected = interpret(&ast, &input, false, Some(STEP_LIMIT)).unwrap();
+                assert_eq!(run(&code, &[a, b]), expected, "-O{} on {} and {}", level, a, b);
+            }
+        }
+        assert_eq!(interpret(&ast, &numbers(&[7, 3]), false, None).unwrap(), numbers(&[3, 7, 0, 0, 3, 21, 12, 3, 12, 7, 2]));
+    }
+
+    #[test]
+    fn signed_arithmetic() {
+        let source = "
+            PROGRAM IS a, b, c IN
+              READ a; READ b;
+              a := a - 10; b := b - 10;
+              c := a / b; WRITE c;
+              c := a % b; WRITE c;
+              c := a - b; WRITE c;
+              c := -4; c := c * a; WRITE c;
+            END
+        ";
+        let ast = load(source, &mut SourceMap::new());
+        let code = compile_with(load_signed(source, &mut SourceMap::new()), &PassManager::default());
+
+        for (a, b) in [(0, 0), (3, 7), (17, 6), (2, 13), (20, 10)] {
+            let input = numbers(&[a, b]);
+            assert_eq!(interpret(&ast, &input, true, Some(STEP_LIMIT)).unwrap(), run_signed(&code, &[a, b]), "{} and {}", a, b);
+        }
+        assert_eq!(interpret(&ast, &numbers(&[3, 14]), true, None).unwrap(), numbers(&[-2, 1, -11, 28]));
+    }
+
+    // the OUT parameters are passed by reference, like in the compiled code
+    #[test]
+    fn out_parameters_share_the_cell() {
+        let source = "GLOBALS g END PROCEDURE p(OUT r) IS IN r := 5; WRITE g; END PROGRAM IS IN g := 1; p(g); WRITE g; END";
+        let ast = load(source, &mut SourceMap::new());
+        assert_eq!(interpret(&ast, &[], false, None).unwrap(), numbers(&[5, 5]));
+        assert_eq!(run(&compile_with(ast, &PassManager::default()), &[]), numbers(&[5, 5]));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let run = |source: &str, input: &[i64]| interpret(&load(source, &mut SourceMap::new()), &numbers(input), false, Some(1000)).unwrap_err();
+
+        let e = run("PROGRAM IS a, b IN READ a; b := a + b; END", &[1]);
+        assert_eq!(e.to_string(), "The variable \"b\" is read before anything is stored in it");
+        let e = run("PROCEDURE p(OUT r) IS IN r := r + 1; END PROGRAM IS a IN p(a); END", &[]);
+        assert!(matches!(e, InterpreterError::UninitialisedVariable(_, name) if name == "r"));
+        let e = run("PROGRAM IS t[3], i IN READ i; t[i] := 1; END", &[3]);
+        assert_eq!(e, InterpreterError::IndexOutOfBounds(e.location().unwrap(), "t".to_owned(), BigInt::from(3)));
+        assert!(matches!(run("PROGRAM IS a IN READ a; READ a; END", &[1]), InterpreterError::MissingInput(_)));
+        assert_eq!(run("PROGRAM IS a, t[100000000000000000000000] IN WRITE 1; END", &[]), InterpreterError::ArrayTooBig((14, 15), "t".to_owned()));
+        let e = run("PROCEDURE p(IN n) IS t[4611686018427387904], u[4611686018427387904] IN WRITE n; END PROGRAM IS IN p(1); END", &[]);
+        assert_eq!(e.to_string(), "The array \"u\" is too big to fit in memory");
+        assert_eq!(run("PROGRAM IS a IN a := 0; WHILE a = 0 DO a := 0; ENDWHILE END", &[]), InterpreterError::StepLimitExceeded(1000));
+        assert!(matches!(run("PROCEDURE p(a) IS IN a := 1; END PROGRAM IS IN p(2); END", &[]), InterpreterError::ReferenceExpected(..)));
+    }
+}
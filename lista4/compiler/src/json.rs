@@ -0,0 +1,263 @@
+use std::fmt;
+
+use crate::diagnostics::json_string;
+
+// a JSON document, as exchanged with the editors; the numbers are integers since nothing else is used
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(members: Vec<(&str, Json)>) -> Self {
+        return Json::Object(members.into_iter().map(|(key, value)| (key.to_owned(), value)).collect());
+    }
+
+    pub fn string(text: &str) -> Self {
+        return Json::String(text.to_owned());
+    }
+
+    // the member of an object, or null if there is none
+    pub fn get(&self, key: &str) -> &Json {
+        return match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        };
+    }
+
+    // the member found by following the keys, e.g. ["position", "line"]
+    pub fn path(&self, keys: &[&str]) -> &Json {
+        return keys.iter().fold(self, |value, key| value.get(key));
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        return match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        };
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        return match self {
+            Json::Number(num) => Some(*num),
+            _ => None,
+        };
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        return match self {
+            Json::Array(items) => items,
+            _ => &[],
+        };
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(num) => write!(f, "{}", num),
+            Json::String(text) => write!(f, "{}", json_string(text)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct JsonError(pub usize);
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid JSON at byte {}", self.0)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.text.get(self.pos) != Some(&byte) {
+            return Err(JsonError(self.pos));
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(JsonError(self.pos));
+        }
+        self.pos += word.len();
+        return Ok(value);
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        while self.pos < self.text.len() && matches!(self.text[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+
+        // the fractions are cut off, no client sends them where they matter
+
+        return match text.parse::<i64>() {
+            Ok(num) => Ok(Json::Number(num)),
+            Err(_) => text.parse::<f64>().map(|num| Json::Number(num as i64)).map_err(|_| JsonError(start)),
+        };
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.text.get(self.pos).ok_or(JsonError(self.pos))?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.text.get(self.pos).ok_or(JsonError(self.pos))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'u' => {
+                            let code = self.text.get(self.pos..self.pos + 4)
+                                .and_then(|hex| u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                                .ok_or(JsonError(self.pos))?;
+                            self.pos += 4;
+                            let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            bytes.extend_from_slice(c.to_string().as_bytes());
+                        },
+                        other => bytes.push(other),
+                    }
+                },
+                other => bytes.push(other),
+            }
+        }
+        return String::from_utf8(bytes).map_err(|_| JsonError(self.pos));
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        return match self.text.get(self.pos) {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return Err(JsonError(self.pos)),
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        },
+                        _ => return Err(JsonError(self.pos)),
+                    }
+                }
+            },
+            _ => Err(JsonError(self.pos)),
+        };
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json, JsonError> {
+    let mut parser = Parser{text: text.as_bytes(), pos: 0};
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != text.len() {
+        return Err(JsonError(parser.pos));
+    }
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"id":1,"params":{"text":"a\n\"b\"A","ok":[true,false,null,-12]},"empty":{},"none":[]}"#;
+        let value = parse(&format!(" {}\n", text.replace(',', ", "))).unwrap();
+        assert_eq!(value.path(&["params", "text"]).as_str(), Some("a\n\"b\"A"));
+        assert_eq!(value.get("params").get("ok").as_array()[3].as_i64(), Some(-12));
+        assert_eq!(value.get("missing"), &Json::Null);
+        assert_eq!(value.to_string(), text);
+        assert_eq!(parse(r#""\u0041""#), Ok(Json::string("A")));
+
+        assert_eq!(parse("{\"a\" 1}"), Err(JsonError(5)));
+        assert_eq!(parse("[1] 2"), Err(JsonError(4)));
+    }
+}
@@ -0,0 +1,30 @@
+#![allow(clippy::needless_return, clippy::too_many_arguments, clippy::needless_range_loop)]
+
+use lalrpop_util::lalrpop_mod;
+
+pub mod err;
+pub mod ast;
+pub mod cli;
+pub mod comments;
+pub mod debugger;
+pub mod diagnostics;
+pub mod documentation;
+pub mod emulator;
+pub mod format;
+pub mod interpreter;
+pub mod json;
+pub mod lints;
+pub mod listing;
+pub mod lsp;
+pub mod modules;
+pub mod repl;
+pub mod stdlib;
+pub mod symbol_map;
+pub mod trace;
+pub mod translation;
+pub mod visit;
+
+#[cfg(test)]
+mod test_support;
+
+lalrpop_mod!(#[allow(clippy::all)] pub grammar);
@@ -0,0 +1,368 @@
+use std::collections::{HashMap, HashSet};
+
+use num::{BigInt, One, Zero};
+
+use crate::ast::*;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::visit::Visitor;
+
+// the suspicious patterns the compiler warns about
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Lint {
+    UnusedVariables,
+    UnusedParameters,
+    UnusedProcedures,
+    NoEffectAssignments,
+    ConstantConditions,
+    UnreachableCode,
+}
+
+pub const LINTS: [Lint; 6] = [
+    Lint::UnusedVariables,
+    Lint::UnusedParameters,
+    Lint::UnusedProcedures,
+    Lint::NoEffectAssignments,
+    Lint::ConstantConditions,
+    Lint::UnreachableCode,
+];
+
+// the name standing for all the lints at once
+pub const ALL_LINTS: &str = "warnings";
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Lint::UnusedVariables => "unused_variables",
+            Lint::UnusedParameters => "unused_parameters",
+            Lint::UnusedProcedures => "unused_procedures",
+            Lint::NoEffectAssignments => "no_effect_assignments",
+            Lint::ConstantConditions => "constant_conditions",
+            Lint::UnreachableCode => "unreachable_code",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return LINTS.into_iter().find(|lint| lint.name() == name);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    // split the command line flag -W<lint>, -A<lint> or -D<lint> into the level and the name of the lint
+    pub fn from_flag(flag: &str) -> Option<(Self, &str)> {
+        if let Some(name) = flag.strip_prefix("-W") {
+            return Some((Level::Warn, name));
+        } else if let Some(name) = flag.strip_prefix("-A") {
+            return Some((Level::Allow, name));
+        } else if let Some(name) = flag.strip_prefix("-D") {
+            return Some((Level::Deny, name));
+        }
+        return None;
+    }
+}
+
+// the levels of the lints; all of them warn unless told otherwise
+#[derive(Debug, Clone)]
+pub struct LintLevels {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintLevels {
+    pub fn new() -> Self {
+        return Self{levels: LINTS.into_iter().map(|lint| (lint, Level::Warn)).collect()};
+    }
+
+    // set the level of the named lint, or of all of them; returns false if there is no such lint
+    pub fn set(&mut self, name: &str, level: Level) -> bool {
+        if name == ALL_LINTS {
+            for lint in LINTS {
+                self.levels.insert(lint, level);
+            }
+            return true;
+        }
+        return match Lint::from_name(name) {
+            Some(lint) => {
+                self.levels.insert(lint, level);
+                true
+            },
+            None => false,
+        };
+    }
+
+    pub fn get(&self, lint: Lint) -> Level {
+        return self.levels[&lint];
+    }
+}
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// whether any of the diagnostics comes from a denied lint
+pub fn any_denied(diagnostics: &[Diagnostic]) -> bool {
+    return diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error);
+}
+
+// the names used by the commands, either as variables or as array indices
+#[derive(Default)]
+struct UsedNames(HashSet<Pidentifier>);
+
+impl Visitor for UsedNames {
+    fn visit_pidentifier(&mut self, pid: &Pidentifier) {
+        self.0.insert(pid.clone());
+    }
+}
+
+// the names of the procedures called by the commands
+#[derive(Default)]
+struct CalledProcedures(HashSet<Pidentifier>);
+
+impl Visitor for CalledProcedures {
+    fn visit_proc_call(&mut self, proc_call: &ProcCall, _location: Location) {
+        self.0.insert(proc_call.name.clone());
+    }
+}
+
+// the procedures of the imported modules live in namespaces and aren't the concern of the program's author
+fn is_imported(procedure: &Procedure) -> bool {
+    return procedure.proc_head.name.contains('.');
+}
+
+// the names starting with an underscore are meant to be unused
+fn is_silenced(name: &str) -> bool {
+    return name.starts_with('_');
+}
+
+struct Linter<'a> {
+    levels: &'a LintLevels,
+    constants: HashMap<&'a Pidentifier, &'a Value>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, lint: Lint, location: Location, message: String, help: Option<String>) {
+        let severity = match self.levels.get(lint) {
+            Level::Allow => return,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        self.diagnostics.push(Diagnostic::lint(lint.name(), severity, location, message, help));
+    }
+
+    // the value of a literal or a constant, as a signed number
+    fn constant_value(&self, value: &Value) -> Option<BigInt> {
+        return match value {
+            Value::Number(num) => Some(num.clone()),
+            Value::Negative(num) => Some(-num),
+            Value::Id(Identifier::Pid(pid)) => self.constants.get(pid).and_then(|value| self.constant_value(value)),
+            Value::Id(_) => None,
+        };
+    }
+
+    fn constant_condition(&self, condition: &Condition) -> Option<bool> {
+        let (Condition::Equal(lhs, rhs)
+        | Condition::NotEqual(lhs, rhs)
+        | Condition::Greater(lhs, rhs)
+        | Condition::Lesser(lhs, rhs)
+        | Condition::GreaterOrEqual(lhs, rhs)
+        | Condition::LesserOrEqual(lhs, rhs)) = condition;
+        let (lhs, rhs) = (self.constant_value(lhs)?, self.constant_value(rhs)?);
+        return Some(match condition {
+            Condition::Equal(_, _) => lhs == rhs,
+            Condition::NotEqual(_, _) => lhs != rhs,
+            Condition::Greater(_, _) => lhs > rhs,
+            Condition::Lesser(_, _) => lhs < rhs,
+            Condition::GreaterOrEqual(_, _) => lhs >= rhs,
+            Condition::LesserOrEqual(_, _) => lhs <= rhs,
+        });
+    }
+
+    fn check_condition(&mut self, condition: &Condition, location: Location) -> Option<bool> {
+        let value = self.constant_condition(condition);
+        if let Some(value) = value {
+            self.report(Lint::ConstantConditions, location, format!("The condition is always {}", value), None);
+        }
+        return value;
+    }
+
+    // whether the assignment leaves the variable as it was, e.g. x := x or x := x + 0
+    fn has_no_effect(&self, id: &Identifier, expr: &Expression) -> bool {
+        let is_target = |value: &Value| matches!(value, Value::Id(other) if other == id);
+        let is_number = |value: &Value, num: BigInt| self.constant_value(value) == Some(num);
+        return match expr {
+            Expression::Val(value) => is_target(value),
+            Expression::Add(lhs, rhs) => (is_target(lhs) && is_number(rhs, BigInt::zero())) || (is_number(lhs, BigInt::zero()) && is_target(rhs)),
+            Expression::Sub(lhs, rhs) => is_target(lhs) && is_number(rhs, BigInt::zero()),
+            Expression::Mul(lhs, rhs) => (is_target(lhs) && is_number(rhs, BigInt::one())) || (is_number(lhs, BigInt::one()) && is_target(rhs)),
+            Expression::Div(lhs, rhs) => is_target(lhs) && is_number(rhs, BigInt::one()),
+            Expression::Mod(_, _) => false,
+        };
+    }
+
+    fn check_commands(&mut self, commands: &Commands) {
+        let mut endless_loop = false;
+
+        for command in commands {
+
+            // everything after a loop that never ends is dead, it's enough to point at the first command
+
+            if endless_loop {
+                self.report(Lint::UnreachableCode, command.location(), "This command can never be reached".to_owned(), Some("the loop before it never ends".to_owned()));
+                break;
+            }
+
+            match command {
+                Command::Assignment(id, expr, location) if self.has_no_effect(id, expr) => {
+                    self.report(Lint::NoEffectAssignments, *location, "This assignment has no effect".to_owned(), Some("remove the assignment".to_owned()));
+                },
+                Command::IfElse(condition, if_commands, else_commands, location) => {
+                    self.check_condition(condition, *location);
+                    self.check_commands(if_commands);
+                    self.check_commands(else_commands);
+                },
+                Command::If(condition, commands, location) => {
+                    self.check_condition(condition, *location);
+                    self.check_commands(commands);
+                },
+                Command::While(condition, commands, location) => {
+                    endless_loop = self.check_condition(condition, *location) == Some(true);
+                    self.check_commands(commands);
+                },
+                Command::Repeat(commands, condition, location) => {
+                    self.check_commands(commands);
+                    endless_loop = self.check_condition(condition, *location) == Some(false);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // report the declared variables that the commands never use, each where it is declared
+    fn check_unused(&mut self, lint: Lint, names: &[(&Pidentifier, Location)], used: &HashSet<Pidentifier>) {
+        let kind = match lint {
+            Lint::UnusedParameters => "parameter",
+            _ => "variable",
+        };
+        for &(name, location) in names {
+            if !used.contains(name) && !is_silenced(name) {
+                self.report(lint, location, format!("The {} \"{}\" is never used", kind, name), Some(format!("remove it or rename it to \"_{}\"", name)));
+            }
+        }
+    }
+}
+
+fn declared_names(declarations: &Declarations) -> Vec<(&Pidentifier, Location)> {
+    return declarations.iter().map(|decl| {
+        let (Declaration::Var(pid, location) | Declaration::Arr(pid, _, location)) = decl;
+        (pid, *location)
+    }).collect();
+}
+
+// look for suspicious patterns in the program and report them at the levels set for the lints
+pub fn check(ast: &ProgramAll, levels: &LintLevels) -> Vec<Diagnostic> {
+    let mut linter = Linter{
+        levels,
+        constants: ast.constants.iter().map(|constant| (&constant.name, &constant.value)).collect(),
+        diagnostics: Vec::new(),
+    };
+    let procedures: Vec<&Procedure> = ast.procedures.iter().filter(|procedure| !is_imported(procedure)).collect();
+
+    // the procedures that are never called are dropped without being compiled
+
+    let mut calls = CalledProcedures::default();
+    calls.visit_program(ast);
+    for procedure in &procedures {
+        if !calls.0.contains(&procedure.proc_head.name) && !is_silenced(&procedure.proc_head.name) {
+            linter.report(Lint::UnusedProcedures, procedure.location, format!("The procedure \"{}\" is never called", procedure.proc_head.name), Some("remove the procedure".to_owned()));
+        }
+    }
+
+    // check the commands and the names used by each procedure and main
+
+    let mut all_used = HashSet::new();
+    for procedure in &procedures {
+        let mut used = UsedNames::default();
+        used.visit_commands(&procedure.commands);
+        let used = used.0;
+
+        let params: Vec<(&Pidentifier, Location)> = procedure.proc_head.args_decl.iter().map(|arg_decl| {
+            let (ArgumentDeclaration::Var(pid, _, location) | ArgumentDeclaration::Arr(pid, _, location)) = arg_decl;
+            (pid, *location)
+        }).collect();
+        linter.check_unused(Lint::UnusedParameters, &params, &used);
+        linter.check_unused(Lint::UnusedVariables, &declared_names(&procedure.declarations), &used);
+        linter.check_commands(&procedure.commands);
+
+        all_used.extend(used);
+    }
+
+    let mut used = UsedNames::default();
+    used.visit_commands(&ast.main.commands);
+    let used = used.0;
+    linter.check_unused(Lint::UnusedVariables, &declared_names(&ast.main.declarations), &used);
+    linter.check_commands(&ast.main.commands);
+    all_used.extend(used);
+
+    // the global variables may be used anywhere in the program
+
+    linter.check_unused(Lint::UnusedVariables, &declared_names(&ast.globals.declarations), &all_used);
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location);
+    return diagnostics;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modules::SourceMap;
+    use crate::test_support::load;
+    use super::*;
+
+    fn lint(source: &str, levels: &LintLevels) -> Vec<(String, Severity)> {
+        let ast = load(source, &mut SourceMap::new());
+        return check(&ast, levels).into_iter().map(|diagnostic| (diagnostic.kind, diagnostic.severity)).collect();
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        return lint(source, &LintLevels::new()).into_iter().map(|(kind, _)| kind).collect();
+    }
+
+    #[test]
+    fn unused_names() {
+        let source = "PROCEDURE p(a, b, _c) IS d, _e IN a := 1; END\nPROGRAM IS x, y, t[2] IN p(x, y, y); WRITE t[x]; END\n";
+        assert_eq!(warnings(source), ["unused_parameters", "unused_variables"]);
+        let locations: Vec<Option<Location>> = check(&load(source, &mut SourceMap::new()), &LintLevels::new()).into_iter().map(|diagnostic| diagnostic.location).collect();
+        assert_eq!(locations, [Some((15, 16)), Some((25, 26))]);
+        assert_eq!(warnings("PROCEDURE p(a) IS IN WRITE a; END\nPROGRAM IS IN WRITE 1; END\n"), ["unused_procedures"]);
+        assert_eq!(warnings("PROGRAM IS IN WRITE 1; END\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(warnings("PROGRAM IS x IN READ x; x := x; x := 0 + x; x := x * 1; x := x - 1; END\n"), ["no_effect_assignments"; 3]);
+        let source = "CONST n = 3;\nPROGRAM IS x IN IF n > 2 THEN READ x; ENDIF WHILE x = 0 DO READ x; ENDWHILE REPEAT WRITE x; UNTIL 1 = 0; WRITE x; END\n";
+        assert_eq!(warnings(source), ["constant_conditions", "constant_conditions", "unreachable_code"]);
+    }
+
+    #[test]
+    fn levels() {
+        let source = "PROGRAM IS x, y IN READ x; x := x; END\n";
+
+        let mut levels = LintLevels::new();
+        levels.set("unused_variables", Level::Allow);
+        levels.set("no_effect_assignments", Level::Deny);
+        assert_eq!(lint(source, &levels), [("no_effect_assignments".to_owned(), Severity::Error)]);
+
+        levels.set(ALL_LINTS, Level::Allow);
+        assert_eq!(lint(source, &levels), []);
+        assert!(!levels.set("no_such_lint", Level::Warn));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::modules::SourceMap;
+use crate::translation::translation_structures::AnnotatedLine;
+
+// the width of the instruction column, enough for the longest instructions with their arguments
+const INSTRUCTION_WIDTH: usize = 12;
+
+// where the line of code comes from: the line and column in the source, prefixed with the file if it's a module
+fn describe_origin(source_map: &SourceMap, start: usize) -> (String, String) {
+    let (file, (start, _)) = source_map.lookup((start, start));
+    let (line, column) = file.line_column(start);
+    let origin = match file.offset {
+        0 => format!("{}:{}", line, column),
+        _ => format!("{}:{}:{}", file.path.display(), line, column),
+    };
+    let text = file.source.lines().nth(line - 1).unwrap_or_default().trim().to_owned();
+    return (origin, text);
+}
+
+// lay out the annotated code side by side with the source it comes from; every labelled sequence of code
+// is preceded by its label and the source text is only repeated when the origin of the code changes
+pub fn listing(code: &[String], source_map: &SourceMap) -> String {
+    let number_width = code.len().saturating_sub(1).to_string().len();
+    let mut text = String::new();
+    let mut previous_location = None;
+
+    for (line_number, line) in code.iter().enumerate() {
+        let line = AnnotatedLine::parse(line);
+
+        for label in &line.labels {
+            text += &format!("{:number_width$}  <{}>\n", "", label, number_width = number_width);
+        }
+
+        let mut row = format!("{:>number_width$}  {:INSTRUCTION_WIDTH$}", line_number, line.instruction, number_width = number_width);
+        if let Some(location) = line.location.filter(|_| !source_map.files.is_empty()) {
+            let (origin, source_text) = describe_origin(source_map, location.0);
+            row += &format!("  {:>8}", origin);
+            if previous_location != Some(location) {
+                row += &format!("  {}", source_text);
+            }
+            previous_location = Some(location);
+        }
+
+        text += row.trim_end();
+        text += "\n";
+    }
+
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modules::SourceMap;
+    use crate::test_support::{compile_with, load};
+    use crate::translation::passes::PassManager;
+    use crate::translation::translation_structures::strip_annotations;
+    use super::*;
+
+    const PROGRAM: &str = "PROCEDURE inc(n) IS IN\n  n := n + 1;\nEND\nPROGRAM IS n IN\n  READ n;\n  WHILE n < 5 DO\n    inc(n);\n  ENDWHILE\n  inc(n);\n  WRITE n;\nEND\n";
+
+    fn compile(source_map: &mut SourceMap) -> Vec<String> {
+        return compile_with(load(PROGRAM, source_map), &PassManager::for_level(1));
+    }
+
+    #[test]
+    fn plain_code_has_no_annotations() {
+        let code = strip_annotations(&compile(&mut SourceMap::new()));
+        assert!(code.iter().all(|line| !line.contains('#')), "{:?}", code);
+    }
+
+    #[test]
+    fn labels_and_origins() {
+        let mut source_map = SourceMap::new();
+        let code = compile(&mut source_map);
+        let text = listing(&code, &source_map);
+        let lines: Vec<&str> = text.lines().collect();
+
+        // every line of code is listed, in order
+
+        let listed: Vec<&str> = lines.iter().filter(|line| !line.trim_start().starts_with('<')).copied().collect();
+        assert_eq!(listed.len(), code.len());
+        for (line_number, line) in listed.iter().enumerate() {
+            assert!(line.trim_start().starts_with(&format!("{} ", line_number)), "{}", line);
+        }
+
+        for label in ["<procedure inc>", "<main>", "<loop head>", "<spill n from ", "<reload n into "] {
+            assert!(lines.iter().any(|line| line.trim_start().starts_with(label)), "no {} in\n{}", label, text);
+        }
+        assert!(lines.iter().any(|line| line.ends_with("2:3  n := n + 1;")), "{}", text);
+        assert!(lines.iter().any(|line| line.ends_with("6:3  WHILE n < 5 DO")), "{}", text);
+    }
+}
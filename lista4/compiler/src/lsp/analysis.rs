@@ -0,0 +1,323 @@
+use std::path::Path;
+
+use crate::ast::*;
+use crate::comments;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::format::{format_proc_head, format_value};
+use crate::lints::{self, LintLevels};
+use crate::modules::{load_program_source, parse_program_partial, SourceMap};
+use crate::translation::passes::PassManager;
+use crate::translation::translate;
+use crate::translation::transformation::transform;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SymbolKind {
+    Constant,
+    Global,
+    Procedure,
+    Parameter,
+    Local,
+}
+
+// a name declared in the program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub definition: Location, // the name where it is declared
+    pub detail: String,       // what it is, e.g. "array t[10]"
+    pub is_array: bool,
+    pub region: Option<usize>, // the procedure or main declaring it, by the index of the region
+    pub doc: Option<String>,   // the description of a procedure
+}
+
+// a procedure or main: the part of the text where its names are visible
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Region {
+    pub name: String,
+    pub span: Location,
+    pub symbol: Option<usize>, // the procedure's own symbol, none for main
+}
+
+// the names of a single source file and every place they are used in
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub symbols: Vec<Symbol>,
+    pub regions: Vec<Region>,
+    pub occurrences: Vec<(Location, usize)>, // the spans of the names in the text, with the symbols they stand for
+}
+
+// a name in the text, together with whether it's followed by a parenthesis as in a procedure call
+struct Token {
+    name: String,
+    span: Location,
+    is_call: bool,
+}
+
+fn is_name_start(byte: u8) -> bool {
+    return byte == b'_' || byte.is_ascii_lowercase();
+}
+
+fn is_name_char(byte: u8) -> bool {
+    return is_name_start(byte) || byte.is_ascii_digit();
+}
+
+// the names in the text, skipping comments and strings; the names of imported procedures `module.name` make single tokens
+fn tokens(text: &str) -> Vec<Token> {
+    let source = &comments::blank(text, &comments::scan(text));
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += 1;
+                }
+                pos += 1;
+            },
+            byte if is_name_start(byte) => {
+                let start = pos;
+                while pos < bytes.len() && (is_name_char(bytes[pos]) || (bytes[pos] == b'.' && bytes.get(pos + 1).is_some_and(|next| is_name_start(*next)))) {
+                    pos += 1;
+                }
+                let rest = source[pos..].trim_start();
+                tokens.push(Token{name: source[start..pos].to_owned(), span: (start, pos), is_call: rest.starts_with('(')});
+            },
+            byte if byte.is_ascii_alphanumeric() => {
+
+                // the keywords, the numbers and the array marker `T`
+
+                while pos < bytes.len() && bytes[pos].is_ascii_alphanumeric() {
+                    pos += 1;
+                }
+            },
+            _ => pos += 1,
+        }
+    }
+    return tokens;
+}
+
+fn contains(span: Location, inner: Location) -> bool {
+    return span.0 <= inner.0 && inner.1 <= span.1;
+}
+
+fn declaration_detail(declaration: &Declaration, prefix: &str) -> (String, String, bool) {
+    return match declaration {
+        Declaration::Var(name, _) => (name.clone(), format!("{}variable {}", prefix, name), false),
+        Declaration::Arr(name, len, _) => (name.clone(), format!("{}array {}[{}]", prefix, name, len), true),
+    };
+}
+
+fn parameter_detail(argument: &ArgumentDeclaration) -> (String, String, bool) {
+    return match argument {
+        ArgumentDeclaration::Var(name, ParameterMode::In, _) => (name.clone(), format!("IN parameter {}: variable passed by value", name), false),
+        ArgumentDeclaration::Var(name, ParameterMode::Out, _) => (name.clone(), format!("OUT parameter {}: variable passed by reference", name), false),
+        ArgumentDeclaration::Var(name, ParameterMode::InOut, _) => (name.clone(), format!("parameter {}: variable passed by reference", name), false),
+        ArgumentDeclaration::Arr(name, _, _) => (name.clone(), format!("parameter {}: array passed by reference", name), true),
+    };
+}
+
+impl Analysis {
+
+    // find the names declared by the program and resolve every use of them
+    pub fn new(ast: &ProgramAll, source: &str) -> Self {
+        let tokens = tokens(source);
+        let mut analysis = Self::default();
+
+        // the declaration of a name is its first appearance in the part of the text declaring it
+
+        let declare = |analysis: &mut Self, name: &str, kind, area: Location, detail: String, is_array: bool, region: Option<usize>| {
+            let after = analysis.symbols.iter().filter(|symbol| contains(area, symbol.definition)).map(|symbol| symbol.definition.1).max().unwrap_or(area.0);
+            if let Some(token) = tokens.iter().find(|token| token.name == name && token.span.0 >= after && contains(area, token.span)) {
+                analysis.symbols.push(Symbol{name: name.to_owned(), kind, definition: token.span, detail, is_array, region, doc: None});
+                analysis.occurrences.push((token.span, analysis.symbols.len() - 1));
+            }
+        };
+
+        for constant in &ast.constants {
+            declare(&mut analysis, &constant.name, SymbolKind::Constant, constant.location, format!("CONST {} = {}", constant.name, format_value(&constant.value)), false, None);
+        }
+        for declaration in &ast.globals.declarations {
+            let (name, detail, is_array) = declaration_detail(declaration, "global ");
+            declare(&mut analysis, &name, SymbolKind::Global, ast.globals.location, detail, is_array, None);
+        }
+
+        // every procedure reaches up to the next one, the last one up to main
+
+        let starts: Vec<usize> = ast.procedures.iter().map(|procedure| procedure.location.0).chain([ast.main.location.0, source.len()]).collect();
+        for (i, procedure) in ast.procedures.iter().enumerate() {
+            let head = &procedure.proc_head;
+            declare(&mut analysis, &head.name, SymbolKind::Procedure, procedure.location, format!("PROCEDURE {}", format_proc_head(head)), false, None);
+            let symbol = analysis.symbols.iter().position(|symbol| symbol.kind == SymbolKind::Procedure && symbol.name == head.name);
+            if let Some(symbol) = symbol {
+                analysis.symbols[symbol].doc = procedure.doc.clone();
+            }
+            analysis.regions.push(Region{name: head.name.clone(), span: (starts[i], starts[i + 1]), symbol});
+
+            for argument in &head.args_decl {
+                let (name, detail, is_array) = parameter_detail(argument);
+                declare(&mut analysis, &name, SymbolKind::Parameter, procedure.location, detail, is_array, Some(i));
+            }
+            for declaration in &procedure.declarations {
+                let (name, detail, is_array) = declaration_detail(declaration, "");
+                declare(&mut analysis, &name, SymbolKind::Local, procedure.location, detail, is_array, Some(i));
+            }
+        }
+        let main_region = ast.procedures.len();
+        analysis.regions.push(Region{name: "PROGRAM".to_owned(), span: (ast.main.location.0, source.len()), symbol: None});
+        for declaration in &ast.main.declarations {
+            let (name, detail, is_array) = declaration_detail(declaration, "");
+            declare(&mut analysis, &name, SymbolKind::Local, ast.main.location, detail, is_array, Some(main_region));
+        }
+
+        // the uses of the names: the names of the procedure or main first, then the constants and the globals
+
+        for token in &tokens {
+            if analysis.occurrences.iter().any(|(span, _)| *span == token.span) {
+                continue;
+            }
+            let region = analysis.regions.iter().position(|region| contains(region.span, token.span));
+            let found = match token.is_call {
+                true => analysis.symbols.iter().position(|symbol| symbol.kind == SymbolKind::Procedure && symbol.name == token.name),
+                false => analysis.symbols.iter().position(|symbol| symbol.region.is_some() && symbol.region == region && symbol.name == token.name)
+                    .or_else(|| analysis.symbols.iter().position(|symbol| matches!(symbol.kind, SymbolKind::Constant | SymbolKind::Global) && symbol.name == token.name)),
+            };
+            if let Some(symbol) = found {
+                analysis.occurrences.push((token.span, symbol));
+            }
+        }
+        analysis.occurrences.sort();
+
+        return analysis;
+    }
+
+    // the symbol whose name is at the byte of the text
+    pub fn symbol_at(&self, offset: usize) -> Option<usize> {
+        return self.occurrences.iter().find(|(span, _)| span.0 <= offset && offset <= span.1).map(|(_, symbol)| *symbol);
+    }
+
+    pub fn references(&self, symbol: usize) -> Vec<Location> {
+        return self.occurrences.iter().filter(|(_, other)| *other == symbol).map(|(span, _)| *span).collect();
+    }
+}
+
+// whether the text can be used as the name of a variable or a procedure
+pub fn is_valid_name(name: &str) -> bool {
+    return name.bytes().next().is_some_and(is_name_start) && name.bytes().all(is_name_char);
+}
+
+// the structure of the text, without the parts that do not parse
+pub fn parse(path: &Path, source: &str) -> Option<ProgramAll> {
+    return parse_program_partial(path, source.to_owned(), &mut SourceMap::new()).0;
+}
+
+// the problems the compiler finds in the text, with their spans in the text; the problems within
+// the imported modules are shown at the beginning of the text
+pub fn check(path: &Path, source: &str) -> Vec<(Location, Diagnostic)> {
+    let mut source_map = SourceMap::new();
+    let mut diagnostics = Vec::new();
+
+    match load_program_source(path, source.to_owned(), &mut source_map) {
+        Ok(mut ast) => {
+            diagnostics.extend(lints::check(&ast, &LintLevels::new()));
+            let passes = PassManager::for_level(0);
+            if let Err(e) = transform(&mut ast, &passes).and_then(|_| translate(ast, &passes).map(|_| ())) {
+                diagnostics.push(Diagnostic::from_translation_error(&e));
+            }
+        },
+        Err(e) => diagnostics.extend(Diagnostic::from_module_error(&e)),
+    }
+
+    return diagnostics.into_iter().map(|mut diagnostic| {
+        let span = match diagnostic.location {
+            Some(location) if !source_map.files.is_empty() => {
+                let (file, span) = source_map.lookup(location);
+                match file.offset {
+                    0 => span,
+                    _ => {
+                        diagnostic.message = format!("{} (in \"{}\")", diagnostic.message, file.path.display());
+                        (0, 0)
+                    },
+                }
+            },
+            _ => (0, 0),
+        };
+        (span, diagnostic)
+    }).collect();
+}
+
+pub fn is_error(diagnostic: &Diagnostic) -> bool {
+    return diagnostic.severity == Severity::Error;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "\
+CONST k = 3;
+GLOBALS total, tab[4] END
+PROCEDURE add(IN v, t, T arr) IS x IN
+  x := v * k; # x is local
+  t := total + x;
+  arr[x] := t;
+END
+PROGRAM IS x, t[2] IN
+  READ x;
+  add(x, x, t);
+  WRITE total;
+END
+";
+
+    fn describe(analysis: &Analysis, offset: usize) -> String {
+        return analysis.symbol_at(offset).map(|symbol| analysis.symbols[symbol].detail.clone()).unwrap_or_default();
+    }
+
+    #[test]
+    fn resolution() {
+        let analysis = Analysis::new(&parse(Path::new("test.imp"), PROGRAM).unwrap(), PROGRAM);
+        let at = |text: &str, nth: usize| PROGRAM.match_indices(text).nth(nth).unwrap().0;
+
+        assert_eq!(describe(&analysis, at("k", 1)), "CONST k = 3");
+        assert_eq!(describe(&analysis, at("tab", 0)), "global array tab[4]");
+        assert_eq!(describe(&analysis, at("v", 1)), "IN parameter v: variable passed by value");
+        assert_eq!(describe(&analysis, at("t :=", 0)), "parameter t: variable passed by reference");
+        assert_eq!(describe(&analysis, at("arr[x]", 0)), "parameter arr: array passed by reference");
+        assert_eq!(describe(&analysis, at("add(x", 0)), "PROCEDURE add(IN v, t, T arr)");
+        assert_eq!(describe(&analysis, at("t);", 0)), "array t[2]");
+
+        // the same name in the procedure and in main, but not in the comment
+
+        let local = analysis.symbol_at(at("x :=", 0)).unwrap();
+        assert_eq!(analysis.references(local), [(at("x IN", 0), at("x IN", 0) + 1), (at("x :=", 0), at("x :=", 0) + 1), (at("x;", 0), at("x;", 0) + 1), (at("x]", 0), at("x]", 0) + 1)]);
+        let main_x = analysis.symbol_at(at("x;", 1)).unwrap();
+        assert_ne!(local, main_x);
+        assert_eq!(analysis.references(main_x).len(), 4);
+        assert_eq!(analysis.symbols[analysis.symbol_at(at("total", 2)).unwrap()].definition, (at("total", 0), at("total", 0) + 5));
+
+        // the names may have digits after their first character
+
+        let source = PROGRAM.replace("total", "total2");
+        let analysis = Analysis::new(&parse(Path::new("test.imp"), &source).unwrap(), &source);
+        let total = analysis.symbol_at(source.rfind("total2").unwrap()).unwrap();
+        assert_eq!(analysis.references(total).len(), 3);
+        assert!(is_valid_name("x_1") && !is_valid_name("1x") && !is_valid_name("xY") && !is_valid_name(""));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let source = PROGRAM.replace("WRITE total", "WRITE y");
+        let diagnostics = check(Path::new("test.imp"), &source);
+        let errors: Vec<_> = diagnostics.iter().filter(|(_, diagnostic)| is_error(diagnostic)).collect();
+        assert_eq!(errors.len(), 1, "{:?}", diagnostics);
+        assert_eq!(errors[0].1.kind, "NoSuchVariable");
+        assert_eq!(&source[errors[0].0.0..errors[0].0.1], "WRITE y;");
+
+        let diagnostics = check(Path::new("test.imp"), "PROGRAM IS IN x := ; END");
+        assert_eq!(diagnostics[0].1.kind, "Syntax");
+        assert_eq!(diagnostics[0].0, (19, 20));
+    }
+}
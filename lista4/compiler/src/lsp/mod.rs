@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::ast::Location;
+use crate::diagnostics::Diagnostic;
+use crate::json::{self, Json};
+use analysis::{Analysis, SymbolKind};
+
+pub mod analysis;
+
+// the error codes of JSON-RPC used by the server
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+// the kinds of the document symbols, as numbered by the protocol
+const FUNCTION_SYMBOL: i64 = 12;
+const VARIABLE_SYMBOL: i64 = 13;
+const CONSTANT_SYMBOL: i64 = 14;
+const ARRAY_SYMBOL: i64 = 18;
+
+// the 0-based line and character of the byte of the text
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    return Json::object(vec![
+        ("line", Json::Number(before.matches('\n').count() as i64)),
+        ("character", Json::Number(before[line_start..].encode_utf16().count() as i64)),
+    ]);
+}
+
+// the byte of the text at the 0-based line and character
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line").as_i64()? as usize;
+    let character = position.get("character").as_i64()? as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let line_text = text[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (byte, c) in line_text.char_indices() {
+        if units >= character {
+            return Some(line_start + byte);
+        }
+        units += c.len_utf16();
+    }
+    return Some(line_start + line_text.len());
+}
+
+fn range(text: &str, span: Location) -> Json {
+    return Json::object(vec![("start", position(text, span.0)), ("end", position(text, span.1))]);
+}
+
+// a document open in the editor, together with what's known about its names
+struct Document {
+    text: String,
+    analysis: Option<Analysis>,
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Self {
+        let analysis = analysis::parse(&uri_to_path(uri), &text).map(|ast| Analysis::new(&ast, &text));
+        return Self{text, analysis};
+    }
+}
+
+// the path of a local file; the percent-encoded bytes are decoded
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match byte {
+            b'%' => tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            },
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            },
+        }
+    }
+    return PathBuf::from(String::from_utf8_lossy(&bytes).into_owned());
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    return Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id.clone()), ("result", result)]);
+}
+
+fn error_response(id: &Json, code: i64, message: &str) -> Json {
+    let error = Json::object(vec![("code", Json::Number(code)), ("message", Json::string(message))]);
+    return Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id.clone()), ("error", error)]);
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    return Json::object(vec![("jsonrpc", Json::string("2.0")), ("method", Json::string(method)), ("params", params)]);
+}
+
+fn lsp_diagnostic(text: &str, span: Location, diagnostic: &Diagnostic) -> Json {
+    let message = match &diagnostic.help {
+        Some(help) => format!("{}\nhelp: {}", diagnostic.message, help),
+        None => diagnostic.message.clone(),
+    };
+    return Json::object(vec![
+        ("range", range(text, span)),
+        ("severity", Json::Number(if analysis::is_error(diagnostic) { 1 } else { 2 })),
+        ("code", Json::string(&diagnostic.kind)),
+        ("source", Json::string("imp")),
+        ("message", Json::string(&message)),
+    ]);
+}
+
+// the language server: the open documents and whether the client asked to shut down
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutting_down: bool,
+    pub exited: bool,
+}
+
+impl Server {
+
+    // the messages to send in reply to a message of the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or_default();
+        let id = message.get("id");
+        let params = message.get("params");
+
+        if self.shutting_down && method != "exit" {
+            return match id {
+                Json::Null => Vec::new(),
+                id => vec![error_response(id, REQUEST_FAILED, "The server is shutting down")],
+            };
+        }
+
+        let result = match method {
+            "initialize" => Some(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::Number(1)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("referencesProvider", Json::Bool(true)),
+                    ("documentSymbolProvider", Json::Bool(true)),
+                    ("renameProvider", Json::Bool(true)),
+                ])),
+                ("serverInfo", Json::object(vec![("name", Json::string("imp-lsp"))])),
+            ])),
+            "shutdown" => {
+                self.shutting_down = true;
+                Some(Json::Null)
+            },
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            },
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = params.path(&["textDocument", "uri"]).as_str().unwrap_or_default();
+                let text = match method {
+                    "textDocument/didOpen" => params.path(&["textDocument", "text"]).as_str(),
+                    _ => params.get("contentChanges").as_array().last().and_then(|change| change.get("text").as_str()),
+                };
+                let Some(text) = text else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.to_owned(), Document::new(uri, text.to_owned()));
+                return vec![self.publish_diagnostics(uri)];
+            },
+            "textDocument/didClose" => {
+                let uri = params.path(&["textDocument", "uri"]).as_str().unwrap_or_default();
+                self.documents.remove(uri);
+                return vec![notification("textDocument/publishDiagnostics", Json::object(vec![
+                    ("uri", Json::string(uri)),
+                    ("diagnostics", Json::Array(Vec::new())),
+                ]))];
+            },
+            "textDocument/hover" => self.at_position(params).map(|(document, symbol)| {
+                let symbol = &document.analysis.as_ref().unwrap().symbols[symbol];
+                let value = match &symbol.doc {
+                    Some(doc) => format!("```\n{}\n```\n\n{}", symbol.detail, doc),
+                    None => format!("```\n{}\n```", symbol.detail),
+                };
+                Json::object(vec![
+                    ("contents", Json::object(vec![("kind", Json::string("markdown")), ("value", Json::string(&value))])),
+                ])
+            }).or(Some(Json::Null)),
+            "textDocument/definition" => self.at_position(params).map(|(document, symbol)| {
+                let uri = params.path(&["textDocument", "uri"]).clone();
+                let definition = document.analysis.as_ref().unwrap().symbols[symbol].definition;
+                Json::object(vec![("uri", uri), ("range", range(&document.text, definition))])
+            }).or(Some(Json::Null)),
+            "textDocument/references" => self.at_position(params).map(|(document, symbol)| {
+                let uri = params.path(&["textDocument", "uri"]);
+                let analysis = document.analysis.as_ref().unwrap();
+                let include_declaration = params.path(&["context", "includeDeclaration"]) != &Json::Bool(false);
+                Json::Array(analysis.references(symbol).into_iter()
+                    .filter(|span| include_declaration || *span != analysis.symbols[symbol].definition)
+                    .map(|span| Json::object(vec![("uri", uri.clone()), ("range", range(&document.text, span))])).collect())
+            }).or(Some(Json::Null)),
+            "textDocument/documentSymbol" => {
+                let uri = params.path(&["textDocument", "uri"]).as_str().unwrap_or_default();
+                Some(self.documents.get(uri).map_or(Json::Null, document_symbols))
+            },
+            "textDocument/rename" => {
+                let new_name = params.get("newName").as_str().unwrap_or_default();
+                if !analysis::is_valid_name(new_name) {
+                    return vec![error_response(id, INVALID_PARAMS, &format!("\"{}\" is not a valid name", new_name))];
+                }
+                self.at_position(params).map(|(document, symbol)| {
+                    let uri = params.path(&["textDocument", "uri"]).as_str().unwrap_or_default();
+                    let edits = document.analysis.as_ref().unwrap().references(symbol).into_iter()
+                        .map(|span| Json::object(vec![("range", range(&document.text, span)), ("newText", Json::string(new_name))])).collect();
+                    Json::object(vec![("changes", Json::Object(vec![(uri.to_owned(), Json::Array(edits))]))])
+                }).or(Some(Json::Null))
+            },
+            _ => None,
+        };
+
+        // the notifications get no reply, the unknown requests get an error
+
+        return match (id, result) {
+            (Json::Null, _) => Vec::new(),
+            (id, Some(result)) => vec![response(id, result)],
+            (id, None) => vec![error_response(id, METHOD_NOT_FOUND, &format!("Unknown method \"{}\"", method))],
+        };
+    }
+
+    // the document and the symbol at the position given by the request
+    fn at_position(&self, params: &Json) -> Option<(&Document, usize)> {
+        let document = self.documents.get(params.path(&["textDocument", "uri"]).as_str()?)?;
+        let offset = offset(&document.text, params.get("position"))?;
+        let symbol = document.analysis.as_ref()?.symbol_at(offset)?;
+        return Some((document, symbol));
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Json {
+        let document = &self.documents[uri];
+        let diagnostics = analysis::check(&uri_to_path(uri), &document.text).iter()
+            .map(|(span, diagnostic)| lsp_diagnostic(&document.text, *span, diagnostic)).collect();
+        return notification("textDocument/publishDiagnostics", Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]));
+    }
+}
+
+// the procedures and main with the names they declare, then the constants and the globals
+fn document_symbols(document: &Document) -> Json {
+    let Some(analysis) = &document.analysis else {
+        return Json::Array(Vec::new());
+    };
+    let symbol_json = |symbol: &analysis::Symbol, span: Location, children: Vec<Json>| {
+        let kind = match symbol.kind {
+            SymbolKind::Procedure => FUNCTION_SYMBOL,
+            SymbolKind::Constant => CONSTANT_SYMBOL,
+            _ if symbol.is_array => ARRAY_SYMBOL,
+            _ => VARIABLE_SYMBOL,
+        };
+        return Json::object(vec![
+            ("name", Json::string(&symbol.name)),
+            ("detail", Json::string(&symbol.detail)),
+            ("kind", Json::Number(kind)),
+            ("range", range(&document.text, span)),
+            ("selectionRange", range(&document.text, symbol.definition)),
+            ("children", Json::Array(children)),
+        ]);
+    };
+
+    let mut items: Vec<Json> = analysis.symbols.iter().filter(|symbol| symbol.region.is_none() && symbol.kind != SymbolKind::Procedure)
+        .map(|symbol| symbol_json(symbol, symbol.definition, Vec::new())).collect();
+    for (i, region) in analysis.regions.iter().enumerate() {
+        let children = analysis.symbols.iter().filter(|symbol| symbol.region == Some(i))
+            .map(|symbol| symbol_json(symbol, symbol.definition, Vec::new())).collect();
+        let span = (region.span.0, region.span.0 + document.text[region.span.0..region.span.1].trim_end().len());
+        items.push(match region.symbol {
+            Some(symbol) => symbol_json(&analysis.symbols[symbol], span, children),
+            None => Json::object(vec![
+                ("name", Json::string(&region.name)),
+                ("kind", Json::Number(FUNCTION_SYMBOL)),
+                ("range", range(&document.text, span)),
+                ("selectionRange", range(&document.text, (span.0, span.0 + region.name.len()))),
+                ("children", Json::Array(children)),
+            ]),
+        });
+    }
+    return Json::Array(items);
+}
+
+// read a message framed by its Content-Length header, none at the end of the input
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?];
+    input.read_exact(&mut body)?;
+    return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+}
+
+fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    return output.flush();
+}
+
+// serve the client over the streams until it asks to exit or closes the input
+pub fn serve(input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(input)? {
+        let replies = match json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error_response(&Json::Null, PARSE_ERROR, &e.to_string())],
+        };
+        for reply in &replies {
+            write_message(output, reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///tmp/test%20dir/test.imp";
+    const PROGRAM: &str = "PROCEDURE inc(n) IS IN\n  n := n + 1;\nEND\nPROGRAM IS x IN\n  READ x;\n  inc(x);\n  WRITE x;\nEND\n";
+
+    fn request(id: i64, method: &str, params: Json) -> Json {
+        return Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", Json::Number(id)), ("method", Json::string(method)), ("params", params)]);
+    }
+
+    fn at(line: i64, character: i64) -> Json {
+        return Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::string(URI))])),
+            ("position", Json::object(vec![("line", Json::Number(line)), ("character", Json::Number(character))])),
+        ]);
+    }
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        let params = Json::object(vec![("textDocument", Json::object(vec![("uri", Json::string(URI)), ("text", Json::string(text))]))]);
+        return server.handle(&notification("textDocument/didOpen", params)).remove(0);
+    }
+
+    #[test]
+    fn framing_and_lifecycle() {
+        let messages: String = [request(1, "initialize", Json::object(vec![])), request(2, "shutdown", Json::Null), notification("exit", Json::Null)]
+            .iter().map(|message| { let body = message.to_string(); format!("Content-Length: {}\r\n\r\n{}", body.len(), body) }).collect();
+        let mut output = Vec::new();
+        serve(&mut messages.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Content-Length: "), "{}", output);
+        assert!(output.contains(r#""hoverProvider":true"#), "{}", output);
+        assert!(output.ends_with(r#"{"jsonrpc":"2.0","id":2,"result":null}"#), "{}", output);
+        assert_eq!(uri_to_path(URI), PathBuf::from("/tmp/test dir/test.imp"));
+    }
+
+    #[test]
+    fn diagnostics_as_you_type() {
+        let mut server = Server::default();
+        let published = open(&mut server, PROGRAM);
+        assert_eq!(published.path(&["params", "diagnostics"]), &Json::Array(Vec::new()));
+
+        let published = open(&mut server, &PROGRAM.replace("WRITE x", "WRITE y"));
+        let diagnostic = &published.path(&["params", "diagnostics"]).as_array()[0];
+        assert_eq!(diagnostic.get("code").as_str(), Some("NoSuchVariable"));
+        assert_eq!(diagnostic.path(&["range", "start"]), &Json::object(vec![("line", Json::Number(6)), ("character", Json::Number(2))]));
+    }
+
+    #[test]
+    fn navigation() {
+        let mut server = Server::default();
+        open(&mut server, PROGRAM);
+
+        let hover = server.handle(&request(1, "textDocument/hover", at(1, 2))).remove(0);
+        assert_eq!(hover.path(&["result", "contents", "value"]).as_str(), Some("```\nparameter n: variable passed by reference\n```"));
+
+        let definition = server.handle(&request(2, "textDocument/definition", at(5, 3))).remove(0);
+        assert_eq!(definition.path(&["result", "range", "start"]), &Json::object(vec![("line", Json::Number(0)), ("character", Json::Number(10))]));
+
+        let references = server.handle(&request(3, "textDocument/references", at(4, 7))).remove(0);
+        assert_eq!(references.get("result").as_array().len(), 4);
+
+        let symbols = server.handle(&request(4, "textDocument/documentSymbol", at(0, 0))).remove(0);
+        let names: Vec<&str> = symbols.get("result").as_array().iter().map(|symbol| symbol.get("name").as_str().unwrap()).collect();
+        assert_eq!(names, ["inc", "PROGRAM"]);
+
+        let mut params = at(1, 7);
+        if let Json::Object(members) = &mut params {
+            members.push(("newName".to_owned(), Json::string("counter")));
+        }
+        let rename = server.handle(&request(5, "textDocument/rename", params.clone())).remove(0);
+        assert_eq!(rename.path(&["result", "changes", URI]).as_array().len(), 3);
+
+        if let Json::Object(members) = &mut params {
+            members.last_mut().unwrap().1 = Json::string("Counter");
+        }
+        let rename = server.handle(&request(6, "textDocument/rename", params)).remove(0);
+        assert_eq!(rename.path(&["error", "code"]).as_i64(), Some(INVALID_PARAMS));
+
+        let unknown = server.handle(&request(7, "textDocument/foo", Json::Null)).remove(0);
+        assert_eq!(unknown.path(&["error", "code"]).as_i64(), Some(METHOD_NOT_FOUND));
+        // the description of a procedure comes from its doc comments
+
+        open(&mut server, &format!("## Adds one.\n(* not a (* description *) *)\n{}", PROGRAM.replace("inc(x)", "## not one either\ninc(x)")));
+        let hover = server.handle(&request(8, "textDocument/hover", at(2, 11))).remove(0);
+        assert_eq!(hover.path(&["result", "contents", "value"]).as_str(), Some("```\nPROCEDURE inc(n)\n```"));
+        open(&mut server, &format!("## Adds one.\n{}", PROGRAM));
+        let hover = server.handle(&request(9, "textDocument/hover", at(1, 11))).remove(0);
+        assert_eq!(hover.path(&["result", "contents", "value"]).as_str(), Some("```\nPROCEDURE inc(n)\n```\n\nAdds one."));
+    }
+}
@@ -1,82 +1,266 @@
+#![allow(clippy::needless_return, clippy::too_many_arguments, clippy::needless_range_loop)]
+
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::env;
+use std::path::Path;
+
+use compiler::{cli, debugger, emulator, lints, listing, lsp, repl, symbol_map, trace};
+use compiler::cli::{Emit, Options, Subcommand, EXIT_IO, EXIT_PARSE, EXIT_RUNTIME, EXIT_SEMANTIC, EXIT_UNFORMATTED, EXIT_USAGE, STDIO_PATH, USAGE};
+use compiler::diagnostics::{Diagnostic, ErrorFormat};
+use compiler::documentation::documentation;
+use compiler::debugger::{Debugger, PromptIo};
+use compiler::emulator::{ConsoleIo, Machine};
+use compiler::err::ModuleError;
+use compiler::interpreter::Interpreter;
+use compiler::format::{first_difference, format_program_source, format_transformed_program};
+use compiler::modules::{check_course_dialect, import_std_module, load_program, load_program_source, parse_module, parse_program, SourceMap};
+use compiler::repl::Repl;
+use compiler::translation::translate_with_layout;
+use compiler::translation::translation_structures::{strip_annotations, InlinedRegions, Layout};
+use compiler::translation::signed::{lower_signed, SignedIo, SIGNED_MODULE};
+use compiler::translation::transformation::transform_with_regions;
+use compiler::ast::{Procedure, ProgramAll};
+
+// the name of the standard input in the diagnostics
+const STDIN_NAME: &str = "<stdin>";
+
+// print the diagnostic and stop the compilation
+fn report_error(diagnostic: &Diagnostic, source_map: &SourceMap, format: ErrorFormat, exit_code: i32) -> ! {
+    eprintln!("{}", diagnostic.render(source_map, format));
+    std::process::exit(exit_code);
+}
 
-use lalrpop_util::lalrpop_mod;
-use grammar::ProgramAllParser;
-use translation::translate;
-use translation::translation_structures::TranslationError;
-use translation::transformation::transform;
-
-pub mod err;
-pub mod ast;
-pub mod translation;
-lalrpop_mod!(pub grammar);
-
-fn report_error(err: &TranslationError) {
-    match err {
-        TranslationError::NoSuchVariable(location, name) => {eprintln!("Error: No such variable: \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::NoSuchProcedure(location, name) => {eprintln!("Error: No such procedure: \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::RepeatedDeclaration(location, name) => {eprintln!("Error: Repeated declaration of \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::NotAnArray(location, name) => {eprintln!("Error: The variable \"{}\" at bytes ({}, {}) has not been declared as an array.\nHELP: remove the indexing {name}[...]", name, location.0, location.1)},
-        TranslationError::NoArrayIndex(location, name) => {eprintln!("Error: The variable: \"{}\" at bytes ({}, {}) has been declared as array, but no indexing was found.\nHELP: add indexing {name}[...]", name, location.0, location.1)},
-        TranslationError::ArrayExpected(location, name) => {eprintln!("Error: Expected an array variable, but got single variable \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::VariableExpected(location, name) => {eprintln!("Error: Expected a single variable, but got array variable \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::RecurrenceNotAllowed(location, name) => {eprintln!("Error: Recurrence in NOT allowed: invoking procedure \"{}\" inside itself at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::InvalidNumberOfArguments(location, name) => {eprintln!("Error: Invalid number of arguments found while trying to call \"{}\" at bytes ({}, {})", name, location.0, location.1)},
-        TranslationError::UninitialisedVariable(location, name) => {eprintln!("Error: The variable \"{}\" at bytes ({}, {}) in uninitialised", name, location.0, location.1)},
+fn report_module_error(err: &ModuleError, source_map: &SourceMap, format: ErrorFormat) -> ! {
+    let exit_code = match err {
+        ModuleError::Io(_, _, _) => EXIT_IO,
+        ModuleError::Syntax(_, _) => EXIT_PARSE,
+        _ => EXIT_SEMANTIC,
+    };
+    let diagnostics = Diagnostic::from_module_error(err);
+    let (last, others) = diagnostics.split_last().unwrap();
+    for diagnostic in others {
+        eprintln!("{}", diagnostic.render(source_map, format));
     }
-    std::process::exit(1);
+    report_error(last, source_map, format, exit_code);
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("usage: /path/to/programme <input-file> <output-file>");
-        std::process::exit(1);
+// write the text into the file, or onto the standard output for `-`
+fn write_output(path: &str, text: &str) {
+    let result = match path {
+        STDIO_PATH => io::stdout().write_all(text.as_bytes()),
+        _ => fs::write(path, text),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: Cannot write \"{}\": {}", path, e);
+        std::process::exit(EXIT_IO);
+    }
+}
+
+// the name and the text of the input program
+fn read_input(options: &Options, source_map: &SourceMap) -> (String, String) {
+    let result = match options.input.as_str() {
+        STDIO_PATH => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| (STDIN_NAME.to_owned(), source)).map_err(|e| ModuleError::Io(STDIN_NAME.to_owned(), e, None))
+        },
+        path => fs::read_to_string(path).map(|source| (path.to_owned(), source)).map_err(|e| ModuleError::Io(path.to_owned(), e, None)),
+    };
+    return match result {
+        Ok(input) => input,
+        Err(e) => report_module_error(&e, source_map, options.error_format),
+    };
+}
+
+// parse the input program, together with the modules it imports unless only its own text is needed
+fn parse(options: &Options, source_map: &mut SourceMap, with_imports: bool) -> ProgramAll {
+    let result = match options.input.as_str() {
+        path if with_imports && path != STDIO_PATH => load_program(Path::new(path), source_map),
+        _ => {
+            let (name, source) = read_input(options, source_map);
+            match with_imports {
+                true => load_program_source(Path::new(&name), source, source_map),
+                false => parse_program(Path::new(&name), source, source_map),
+            }
+        },
+    };
+    let ast = match result {
+        Ok(ast) => ast,
+        Err(e) => report_module_error(&e, source_map, options.error_format),
+    };
+
+    // the names allowed by the compiler go beyond the dialect of the course, which can be asked for
+
+    if options.strict {
+        if let Err(e) = check_course_dialect(source_map) {
+            report_module_error(&e, source_map, options.error_format);
+        }
+    }
+    return ast;
+}
+
+// the procedures of the input, which is either a program or a module
+fn parse_procedures(options: &Options, source_map: &mut SourceMap) -> (String, Vec<Procedure>) {
+    let (name, source) = read_input(options, source_map);
+    return match parse_program(Path::new(&name), source.clone(), source_map) {
+        Ok(ast) => (name, ast.procedures),
+        Err(e) => match parse_module(Path::new(&name), source, &mut SourceMap::new()) {
+            Ok(module) => (name, module.procedures),
+            Err(_) => report_module_error(&e, source_map, options.error_format),
+        },
+    };
+}
+
+// report the suspicious parts of the program, stopping if any of the lints is denied
+fn lint(ast: &ProgramAll, options: &Options, source_map: &SourceMap) {
+    let warnings = lints::check(ast, &options.lint_levels);
+    for warning in &warnings {
+        eprintln!("{}", warning.render(source_map, options.error_format));
+    }
+    if lints::any_denied(&warnings) {
+        std::process::exit(EXIT_SEMANTIC);
     }
+}
+
+// bring the program into the form taken by the code generation, telling where the procedures were expanded
+fn lower(ast: &mut ProgramAll, options: &Options, source_map: &mut SourceMap) -> InlinedRegions {
+
+    // in the signed mode, the arithmetic goes through the helper routines of the standard library
+
+    if options.signed {
+        if let Err(e) = import_std_module(ast, source_map, SIGNED_MODULE) {
+            report_module_error(&e, source_map, options.error_format);
+        }
+        lower_signed(ast);
+    }
+
+    return match transform_with_regions(ast, &options.passes) {
+        Ok(inlined) => inlined,
+        Err(e) => report_error(&Diagnostic::from_translation_error(&e), source_map, options.error_format, EXIT_SEMANTIC),
+    };
+}
 
-    // read the input file
+// compile the input program into vm code, annotated with its origin, together with the layout of its procedures
+fn compile(options: &Options, source_map: &mut SourceMap) -> (Vec<String>, Layout) {
+    let mut ast = parse(options, source_map, true);
+    lint(&ast, options, source_map);
+    lower(&mut ast, options, source_map);
+    return match translate_with_layout(ast, &options.passes) {
+        Ok(compiled) => compiled,
+        Err(e) => report_error(&Diagnostic::from_translation_error(&e), source_map, options.error_format, EXIT_SEMANTIC),
+    };
+}
 
-    let program = match fs::read_to_string(&args[1]) {
-        Ok(contents) => contents,
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match cli::parse_args(&args[1..]) {
+        Ok(options) => options,
         Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }    
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            std::process::exit(EXIT_USAGE);
+        }
     };
+    for category in &options.trace {
+        trace::enable(*category);
+    }
+    if options.print_passes {
+        print!("{}", options.passes.listing());
+        if options.input.is_empty() {
+            return;
+        }
+    }
+    let output = options.output.clone().unwrap_or(STDIO_PATH.to_owned());
 
-    // parse the file
+    match options.subcommand {
+        Subcommand::Compile if options.emit == Emit::TransformedSource => {
+            let mut source_map = SourceMap::new();
+            let mut ast = parse(&options, &mut source_map, true);
+            lint(&ast, &options, &source_map);
+            let inlined = lower(&mut ast, &options, &mut source_map);
+            write_output(&options.output_path(), &format_transformed_program(&ast, &inlined));
+        },
+        Subcommand::Compile => {
+            let mut source_map = SourceMap::new();
+            let (code, layout) = compile(&options, &mut source_map);
+            write_output(&options.output_path(), &strip_annotations(&code).iter().map(|line| line.clone() + "\n").collect::<String>());
+            if let Some(path) = &options.listing {
+                write_output(path, &listing::listing(&code, &source_map));
+            }
+            if let Some(path) = &options.map {
+                write_output(path, &symbol_map::symbol_map(&code, &layout, &source_map));
+            }
+        },
+        Subcommand::Check => {
+            compile(&options, &mut SourceMap::new());
+        },
+        Subcommand::Run => {
+            let (code, _) = compile(&options, &mut SourceMap::new());
+            let mut machine = Machine::new(emulator::parse_code(&code).unwrap());
 
-    match ProgramAllParser::new().parse(&program) {
-        Ok(mut ast) => {
+            // the signed numbers are printed the way the interpreter prints them, not as the pairs written by the code
 
-            // compile the program into vm code
+            let mut console = ConsoleIo::default();
+            let result = match options.signed {
+                true => machine.run(&mut SignedIo::new(&mut console), None),
+                false => machine.run(&mut console, None),
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                std::process::exit(EXIT_RUNTIME);
+            }
+            eprintln!("Cost: {}", machine.cost);
+        },
+        Subcommand::Debug => {
+            let mut source_map = SourceMap::new();
+            let (code, layout) = compile(&options, &mut source_map);
+            let mut debugger = Debugger::new(&code, layout, &source_map).unwrap();
+            debugger::session(&mut debugger, &mut PromptIo);
+        },
+        Subcommand::Interpret => {
+            let mut source_map = SourceMap::new();
+            let ast = parse(&options, &mut source_map, true);
+            let mut interpreter = Interpreter::new(&ast, options.signed, None);
+            if let Err(e) = interpreter.run(&mut ConsoleIo::default()) {
+                report_error(&Diagnostic::from_interpreter_error(&e), &source_map, options.error_format, EXIT_RUNTIME);
+            }
+        },
+        Subcommand::DumpAst => {
+            let ast = parse(&options, &mut SourceMap::new(), true);
+            write_output(&output, &format!("{:#?}\n", ast));
+        },
+        Subcommand::DumpIr => {
+            let mut source_map = SourceMap::new();
+            let mut ast = parse(&options, &mut source_map, true);
+            lower(&mut ast, &options, &mut source_map);
+            write_output(&output, &format!("{:#?}\n", ast));
+        },
+        Subcommand::Fmt => {
+            let mut source_map = SourceMap::new();
+            let ast = parse(&options, &mut source_map, false);
+            let source = &source_map.files[0].source;
+            let formatted = format_program_source(&ast, source);
 
-            //println!("Parsing succeeded!\nAST: {:?}", ast);
-            if let Err(e) = transform(&mut ast) {
-                report_error(&e);
+            // in the check mode, only tell where the layout starts to differ
+
+            if !options.check {
+                write_output(&output, &formatted);
+            } else if let Some((line, old, new)) = first_difference(source, &formatted) {
+                eprintln!("Error: \"{}\" is not formatted, starting at line {}:\n-{}\n+{}", source_map.files[0].path.display(), line, old, new);
+                std::process::exit(EXIT_UNFORMATTED);
             }
-            //println!("Transformed code.\nAST: {:?}", ast);
-            match translate(ast) {
-                Ok(code) => {
-                    let all_code = code
-                    .iter()
-                    .fold(Vec::new(), |mut all_code, line| {
-                        writeln!(&mut all_code, "{}", line).unwrap();
-                        all_code
-                    });
-                    if let Err(e) = fs::write(&args[2], all_code) {
-                        eprintln!("Error: {}", e);
-                        std::process::exit(1);
-                    };
-                }
-                Err(e) => report_error(&e),
+        },
+        Subcommand::Doc => {
+            let (name, procedures) = parse_procedures(&options, &mut SourceMap::new());
+            write_output(&output, &documentation(&name, &procedures));
+        },
+        Subcommand::Repl => {
+            repl::session(&mut Repl::new(), &mut PromptIo);
+        },
+        Subcommand::Lsp => {
+            if let Err(e) = lsp::serve(&mut io::stdin().lock(), &mut io::stdout().lock()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(EXIT_IO);
             }
         },
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }    
     }
 }
@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::*;
+use lalrpop_util::{ErrorRecovery, ParseError};
+
+use crate::comments::{self, Comment};
+use crate::err::ModuleError;
+use crate::grammar::{CommandsParser, DeclarationsParser, ModuleParser, ProgramAllParser};
+use crate::stdlib::{self, STD_PREFIX};
+use crate::visit::{walk_command_mut, VisitorMut};
+
+// a single source file; locations of its code are shifted by its offset
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+    pub offset: usize,
+}
+
+impl SourceFile {
+
+    // the 1-based line and column of the byte of the file
+    pub fn line_column(&self, byte: usize) -> (usize, usize) {
+        let before = &self.source[..byte.min(self.source.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        return (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1);
+    }
+}
+
+// all the source files making up the program, laid out one after another
+#[derive(Default)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        return Self{files: Vec::new()};
+    }
+
+    // add the file at the end of the map and return its offset
+    pub fn add(&mut self, path: PathBuf, source: String) -> usize {
+        let offset = self.files.last().map_or(0, |file| file.offset + file.source.len() + 1);
+        self.files.push(SourceFile{path, source, offset});
+        return offset;
+    }
+
+    // find the file the location belongs to and translate the location into the file's own bytes
+    pub fn lookup(&self, location: Location) -> (&SourceFile, Location) {
+        let file = self.files.iter().rev().find(|file| file.offset <= location.0).unwrap_or(&self.files[0]);
+        return (file, (location.0 - file.offset, location.1 - file.offset));
+    }
+
+    pub fn describe(&self, location: Location) -> String {
+        let (file, (start, end)) = self.lookup(location);
+        return format!("bytes ({}, {}) of \"{}\"", start, end, file.path.display());
+    }
+}
+
+fn shift_location(location: &mut Location, offset: usize) {
+    location.0 += offset;
+    location.1 += offset;
+}
+
+// moves the locations of all the commands into the file's place in the source map
+struct LocationShift(usize);
+
+impl VisitorMut for LocationShift {
+    fn visit_command_mut(&mut self, command: &mut Command) {
+        let (Command::Assignment(_, _, location)
+        | Command::IfElse(_, _, _, location)
+        | Command::If(_, _, location)
+        | Command::While(_, _, location)
+        | Command::Repeat(_, _, location)
+        | Command::ProcedureCall(_, location)
+        | Command::Read(_, location)
+        | Command::Write(_, location)) = command;
+        shift_location(location, self.0);
+        walk_command_mut(self, command);
+    }
+}
+
+// prefixes the calls to the module's own procedures with its namespace
+struct CallQualifier<'a> {
+    namespace: &'a str,
+    module_procedures: &'a HashSet<Pidentifier>,
+}
+
+impl VisitorMut for CallQualifier<'_> {
+    fn visit_proc_call_mut(&mut self, proc_call: &mut ProcCall, _location: Location) {
+        if self.module_procedures.contains(&proc_call.name) {
+            proc_call.name = self.namespace.to_owned() + "." + &proc_call.name;
+        }
+    }
+}
+
+fn read_source(path: &Path, import_location: Option<Location>) -> Result<String, ModuleError> {
+    return fs::read_to_string(path).map_err(|e| ModuleError::Io(path.display().to_string(), e, import_location));
+}
+
+fn canonical_path(path: &Path, import_location: Option<Location>) -> Result<PathBuf, ModuleError> {
+    return path.canonicalize().map_err(|e| ModuleError::Io(path.display().to_string(), e, import_location));
+}
+
+// the keywords of the language, recognised only when written in upper case
+const KEYWORDS: [&str; 22] = [
+    "IMPORT", "CONST", "GLOBALS", "PROCEDURE", "PROGRAM", "IS", "IN", "OUT", "INOUT", "END", "T",
+    "IF", "THEN", "ELSE", "ENDIF", "WHILE", "DO", "ENDWHILE", "REPEAT", "UNTIL", "READ", "WRITE",
+];
+
+// the tokens of the grammar named the way the programmer knows them
+const TOKEN_NAMES: [(&str, &str); 3] = [
+    (r##"r#"[_a-z][_a-z0-9]*"#"##, "identifier"),
+    (r##"r#"[0-9]+"#"##, "number"),
+    (r##"r#"\"[^\"]*\""#"##, "path"),
+];
+
+// whether the text is a name of a variable, a procedure or a module
+pub fn is_identifier(text: &str) -> bool {
+    return text.starts_with(|c: char| c == '_' || c.is_ascii_lowercase())
+        && text.chars().all(|c| c == '_' || c.is_ascii_lowercase() || c.is_ascii_digit());
+}
+
+// the span of the word of letters, digits and underscores the byte is a part of
+fn word_at(source: &str, byte: usize) -> Location {
+    let is_word_char = |c: char| c == '_' || c.is_alphanumeric();
+    let start = source[..byte].char_indices().rev().take_while(|(_, c)| is_word_char(*c)).last().map_or(byte, |(i, _)| i);
+    let end = source[byte..].find(|c: char| !is_word_char(c)).map_or(source.len(), |i| byte + i);
+    return (start, end);
+}
+
+// the mistake in writing the word, if it is a keyword in the wrong case or a name with characters not allowed in it
+fn word_error(word: &str) -> Option<String> {
+    let upper = word.to_uppercase();
+    if word != upper && KEYWORDS.contains(&upper.as_str()) {
+        return Some(format!("The keyword {} has to be written in upper case, not as `{}`", upper, word));
+    }
+    if word.is_empty() || KEYWORDS.contains(&word) || word.chars().all(|c| c.is_ascii_digit()) || is_identifier(word) {
+        return None;
+    }
+    return Some(format!("Invalid identifier `{}`: identifiers consist of the characters [_a-z0-9] and do not start with a digit", word));
+}
+
+// the wrong-case keyword written in lower case right before the byte, which the lexer takes for an identifier;
+// `t` is left out, being a common name of a variable
+fn keyword_before(source: &str, byte: usize) -> Option<(String, Location)> {
+    let end = source[..byte].trim_end().len();
+    let (start, end) = word_at(source, end.checked_sub(1)?);
+    let word = &source[start..end];
+    if word == "t" || !is_identifier(word) {
+        return None;
+    }
+    return word_error(word).map(|message| (message, (start, end)));
+}
+
+// describe the parser's error, with its location moved into the file's place in the source map;
+// the words the lexer stumbles over are described by what is wrong with them
+fn syntax_error<T: fmt::Display, E: fmt::Display>(source: &str, offset: usize, error: ParseError<usize, T, E>) -> (String, Location) {
+    let expected_list = |expected: &Vec<String>| match expected.len() {
+        0 => String::new(),
+        _ => {
+            let names: Vec<&str> = expected.iter()
+                .map(|token| TOKEN_NAMES.iter().find(|(grammar_name, _)| grammar_name == token).map_or(token.as_str(), |(_, name)| name))
+                .collect();
+            format!("; expected one of {}", names.join(", "))
+        },
+    };
+    let (message, (start, end)) = match error {
+        ParseError::InvalidToken{location} => {
+            let (start, end) = word_at(source, location);
+            match word_error(&source[start..end]) {
+                Some(message) => (message, (start, end)),
+                None => ("Invalid token".to_owned(), (location, location)),
+            }
+        },
+        ParseError::UnrecognizedEof{location, expected} => (format!("Unexpected end of file{}", expected_list(&expected)), (location, location)),
+        ParseError::UnrecognizedToken{token: (start, token, end), expected} => {
+            let (word_start, word_end) = word_at(source, start);
+            match (word_error(&source[word_start..word_end]), keyword_before(source, start)) {
+                (Some(message), _) => (message, (word_start, word_end)),
+                (None, Some((message, location))) => (message, location),
+                (None, None) => (format!("Unrecognized token `{}`{}", token, expected_list(&expected)), (start, end)),
+            }
+        },
+        ParseError::ExtraToken{token: (start, token, end)} => (format!("Extra token `{}`", token), (start, end)),
+        ParseError::User{error} => (error.to_string(), (0, 0)),
+    };
+    return (message, (start + offset, end + offset));
+}
+
+// the names with digits in them, which the strict dialect of the course does not allow, skipping comments and strings
+fn course_dialect_errors(source: &str, offset: usize) -> Vec<(String, Location)> {
+    let code = comments::blank(source, &comments::scan(source));
+    let bytes = code.as_bytes();
+    let mut errors = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += 1;
+                }
+                pos += 1;
+            },
+            byte if byte == b'_' || byte.is_ascii_alphanumeric() => {
+                let start = pos;
+                while pos < bytes.len() && (bytes[pos] == b'_' || bytes[pos].is_ascii_alphanumeric()) {
+                    pos += 1;
+                }
+                let word = &code[start..pos];
+                if is_identifier(word) && word.bytes().any(|byte| byte.is_ascii_digit()) {
+                    errors.push((format!("Identifier `{}` contains a digit, which the course dialect does not allow", word), (start + offset, pos + offset)));
+                }
+            },
+            _ => pos += 1,
+        }
+    }
+    return errors;
+}
+
+// check that the program and its modules keep to the dialect of the course, where the identifiers
+// consist of the characters [_a-z] only
+pub fn check_course_dialect(source_map: &SourceMap) -> Result<(), ModuleError> {
+    for file in &source_map.files {
+        let errors = course_dialect_errors(&file.source, file.offset);
+        if !errors.is_empty() {
+            return Err(ModuleError::Syntax(file.path.display().to_string(), errors));
+        }
+    }
+    return Ok(());
+}
+
+// the tree of a parse recovering from the syntax errors, unless the parser could not get to the end of the file,
+// together with all the errors found, in the order they appear
+fn recovered_parse<A, T: fmt::Display, E: fmt::Display>(path: &Path, code: &str, offset: usize, comments: &[Comment], result: Result<A, ParseError<usize, T, E>>, errors: Vec<ErrorRecovery<usize, T, E>>) -> (Option<A>, Option<ModuleError>) {
+    let mut messages: Vec<(String, Location)> = errors.into_iter().map(|recovery| syntax_error(code, offset, recovery.error)).collect();
+
+    // a block comment left open hides the rest of the file from the parser, which is what it then stumbles over
+
+    for comment in comments.iter().filter(|comment| !comment.closed) {
+        messages.push(("Unterminated block comment".to_owned(), (comment.span.0 + offset, comment.span.0 + offset + 2)));
+    }
+    messages.sort_by_key(|(_, location)| *location);
+
+    let ast = match result {
+        Ok(ast) => Some(ast),
+        Err(e) => {
+            messages.push(syntax_error(code, offset, e));
+            None
+        },
+    };
+    return match messages.is_empty() {
+        true => (ast, None),
+        false => (ast, Some(ModuleError::Syntax(path.display().to_string(), messages))),
+    };
+}
+
+// the doc comments right above the procedures describe them
+fn attach_docs(procedures: &mut [Procedure], source: &str, comments: &[Comment]) {
+    for procedure in procedures {
+        procedure.doc = comments::doc_before(source, comments, procedure.location.0);
+    }
+}
+
+struct ModuleLoader<'a> {
+    source_map: &'a mut SourceMap,
+    loaded: HashSet<PathBuf>,
+    namespaces: HashMap<String, PathBuf>,
+    procedures: Procedures,
+}
+
+impl<'a> ModuleLoader<'a> {
+    fn new(source_map: &'a mut SourceMap) -> Self {
+        return Self{source_map, loaded: HashSet::new(), namespaces: HashMap::new(), procedures: Vec::new()};
+    }
+
+    // load the modules imported by the file, resolving their paths relative to it
+    fn load_imports(&mut self, importer: &Path, importer_offset: usize, imports: &Imports) -> Result<(), ModuleError> {
+        let base_dir = importer.parent().unwrap_or(Path::new(""));
+        for import in imports {
+            let mut location = import.location;
+            shift_location(&mut location, importer_offset);
+            match import.path.strip_prefix(STD_PREFIX) {
+                Some(name) => self.load_std_module(&import.path, name, Some(location))?,
+                None => self.load_module(&base_dir.join(&import.path), location)?,
+            }
+        }
+        Ok(())
+    }
+
+    // load a module of the standard library embedded in the compiler
+    fn load_std_module(&mut self, import_path: &str, name: &str, import_location: Option<Location>) -> Result<(), ModuleError> {
+        let source = stdlib::module_source(name).ok_or_else(|| ModuleError::NoSuchStdModule(import_path.to_owned(), import_location))?;
+
+        let path = PathBuf::from(import_path);
+        if !self.loaded.insert(path.clone()) {
+            return Ok(());
+        }
+
+        return self.add_module(&path, name.to_owned(), source.to_owned(), import_location);
+    }
+
+    fn load_module(&mut self, path: &Path, import_location: Location) -> Result<(), ModuleError> {
+
+        // every module is only loaded once, no matter how many times it is imported
+
+        if !self.loaded.insert(canonical_path(path, Some(import_location))?) {
+            return Ok(());
+        }
+
+        // the module's procedures are put in a namespace named after the file
+
+        let namespace = file_namespace(path);
+        if !is_identifier(&namespace) {
+            return Err(ModuleError::InvalidModuleName(path.display().to_string(), namespace, import_location));
+        }
+
+        return self.add_module(path, namespace, read_source(path, Some(import_location))?, Some(import_location));
+    }
+
+    fn add_module(&mut self, path: &Path, namespace: String, source: String, import_location: Option<Location>) -> Result<(), ModuleError> {
+        if let Some(other_path) = self.namespaces.insert(namespace.clone(), path.to_path_buf()) {
+            return Err(ModuleError::NamespaceClash(namespace, other_path.display().to_string(), path.display().to_string(), import_location));
+        }
+
+        // parse the module
+
+        let module = parse_module(path, source, self.source_map)?;
+        let offset = self.source_map.files.last().unwrap().offset;
+
+        // the imported procedures have to be defined before the ones using them
+
+        self.load_imports(path, offset, &module.imports)?;
+
+        let module_procedures: HashSet<Pidentifier> = module.procedures.iter().map(|procedure| procedure.proc_head.name.clone()).collect();
+        for mut procedure in module.procedures {
+            shift_location(&mut procedure.location, offset);
+            for arg_decl in procedure.proc_head.args_decl.iter_mut() {
+                let (ArgumentDeclaration::Var(_, _, location) | ArgumentDeclaration::Arr(_, _, location)) = arg_decl;
+                shift_location(location, offset);
+            }
+            for decl in procedure.declarations.iter_mut() {
+                let (Declaration::Var(_, location) | Declaration::Arr(_, _, location)) = decl;
+                shift_location(location, offset);
+            }
+            LocationShift(offset).visit_commands_mut(&mut procedure.commands);
+            CallQualifier{namespace: &namespace, module_procedures: &module_procedures}.visit_commands_mut(&mut procedure.commands);
+            procedure.proc_head.name = namespace.clone() + "." + &procedure.proc_head.name;
+            self.procedures.push(procedure);
+        }
+
+        Ok(())
+    }
+}
+
+// parse the program together with all the modules it imports, adding their sources to the source map;
+// the imported procedures are placed before the program's own ones
+pub fn load_program(path: &Path, source_map: &mut SourceMap) -> Result<ProgramAll, ModuleError> {
+    return load_program_source(path, read_source(path, None)?, source_map);
+}
+
+// parse the program from the given source, resolving its imports relative to the path
+pub fn load_program_source(path: &Path, source: String, source_map: &mut SourceMap) -> Result<ProgramAll, ModuleError> {
+    let mut ast = parse_program(path, source, source_map)?;
+    let offset = source_map.files.last().unwrap().offset;
+
+    let mut loader = ModuleLoader::new(source_map);
+
+    // the program does not have to exist as a file, but if it does, it cannot import itself
+
+    if let Ok(canonical) = path.canonicalize() {
+        loader.loaded.insert(canonical);
+    }
+
+    loader.load_imports(path, offset, &ast.imports)?;
+
+    loader.procedures.append(&mut ast.procedures);
+    ast.procedures = loader.procedures;
+
+    return Ok(ast);
+}
+
+// parse the program on its own, leaving its imports unresolved
+pub fn parse_program(path: &Path, source: String, source_map: &mut SourceMap) -> Result<ProgramAll, ModuleError> {
+    return match parse_program_partial(path, source, source_map) {
+        (Some(ast), None) => Ok(ast),
+        (_, error) => Err(error.unwrap()),
+    };
+}
+
+// parse the program on its own, skipping the parts with syntax errors: the tree of the rest, if the parser
+// got to the end of the file, for the tools to show what they can, and the syntax errors, if there are any
+pub fn parse_program_partial(path: &Path, source: String, source_map: &mut SourceMap) -> (Option<ProgramAll>, Option<ModuleError>) {
+    let offset = source_map.add(path.to_path_buf(), source);
+    let source = &source_map.files.last().unwrap().source;
+    let comments = comments::scan(source);
+    let code = comments::blank(source, &comments);
+    let mut errors = Vec::new();
+    let result = ProgramAllParser::new().parse(&mut errors, &code);
+    let (mut ast, error) = recovered_parse(path, &code, offset, &comments, result, errors);
+    if let Some(ast) = &mut ast {
+        attach_docs(&mut ast.procedures, source, &comments);
+    }
+    return (ast, error);
+}
+
+// parse a module on its own, without loading its imports
+pub fn parse_module(path: &Path, source: String, source_map: &mut SourceMap) -> Result<Module, ModuleError> {
+    let offset = source_map.add(path.to_path_buf(), source);
+    let source = &source_map.files.last().unwrap().source;
+    let comments = comments::scan(source);
+    let code = comments::blank(source, &comments);
+    let mut errors = Vec::new();
+    let result = ModuleParser::new().parse(&mut errors, &code);
+    let mut module = match recovered_parse(path, &code, offset, &comments, result, errors) {
+        (Some(module), None) => module,
+        (_, error) => return Err(error.unwrap()),
+    };
+    attach_docs(&mut module.procedures, source, &comments);
+    return Ok(module);
+}
+
+// the commands typed into the REPL, or None while the text stops in the middle of them
+pub fn parse_commands(source: &str) -> Result<Option<Commands>, Vec<(String, Location)>> {
+    let comments = comments::scan(source);
+    if comments.iter().any(|comment| !comment.closed) {
+        return Ok(None);
+    }
+    let code = comments::blank(source, &comments);
+    let mut errors = Vec::new();
+    let result = CommandsParser::new().parse(&mut errors, &code);
+    if errors.is_empty() && matches!(result, Err(ParseError::UnrecognizedEof{..})) {
+        return Ok(None);
+    }
+    return match recovered_parse(Path::new(""), &code, 0, &comments, result, errors) {
+        (Some(commands), None) => Ok(Some(commands)),
+        (_, Some(ModuleError::Syntax(_, errors))) => Err(errors),
+        _ => Err(Vec::new()),
+    };
+}
+
+// the variables declared in the REPL, written as in the head of the program
+pub fn parse_declarations(source: &str) -> Result<Declarations, Vec<(String, Location)>> {
+    let comments = comments::scan(source);
+    let code = comments::blank(source, &comments);
+    let mut errors = Vec::new();
+    let result = DeclarationsParser::new().parse(&mut errors, &code);
+    return match recovered_parse(Path::new(""), &code, 0, &comments, result, errors) {
+        (Some(declarations), None) => Ok(declarations),
+        (_, Some(ModuleError::Syntax(_, errors))) => Err(errors),
+        _ => Err(Vec::new()),
+    };
+}
+
+// the namespace of the module read from the file
+fn file_namespace(path: &Path) -> String {
+    let path_string = path.display().to_string();
+    return match path_string.strip_prefix(STD_PREFIX) {
+        Some(name) => name.to_owned(),
+        None => path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+    };
+}
+
+// load a module of the standard library into an already loaded program, unless it has imported it itself
+pub fn import_std_module(ast: &mut ProgramAll, source_map: &mut SourceMap, name: &str) -> Result<(), ModuleError> {
+    let import_path = STD_PREFIX.to_owned() + name;
+
+    let mut loader = ModuleLoader::new(source_map);
+    let module_paths: Vec<PathBuf> = loader.source_map.files.iter().skip(1).map(|file| file.path.clone()).collect();
+    for path in module_paths {
+        loader.namespaces.insert(file_namespace(&path), path.clone());
+        if path.display().to_string().starts_with(STD_PREFIX) {
+            loader.loaded.insert(path);
+        }
+    }
+    loader.load_std_module(&import_path, name, None)?;
+
+    // the module's procedures don't depend on any of the program's own ones
+
+    loader.procedures.append(&mut ast.procedures);
+    ast.procedures = loader.procedures;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_error_recovery() {
+        let source = "\
+PROCEDURE p(a b) IS IN
+  a := 1;
+END
+PROGRAM IS x, y IN
+  x := 1
+  y := x +;
+  IF x >> 0 THEN
+    y := 2;
+  ENDIF
+  WHILE x > 0 DO
+    x := x - 1
+  ENDWHILE
+  WRITE y;
+END
+";
+        let (ast, error) = parse_program_partial(Path::new("test.imp"), source.to_owned(), &mut SourceMap::new());
+
+        // the broken procedure and commands are left out, the rest is kept
+
+        let ast = ast.unwrap();
+        assert!(ast.procedures.is_empty());
+        assert!(matches!(ast.main.commands.as_slice(), [Command::If(_, if_commands, _), Command::While(_, while_commands, _), Command::Write(..)]
+            if if_commands.len() == 1 && while_commands.is_empty()));
+
+        let Some(ModuleError::Syntax(path, errors)) = error else { panic!() };
+        assert_eq!(path, "test.imp");
+        let starts: Vec<usize> = errors.iter().map(|(_, (start, _))| *start).collect();
+        assert_eq!(starts, [source.find("b)").unwrap(), source.find("y :=").unwrap(), source.find("> 0").unwrap(), source.find("ENDWHILE").unwrap()]);
+        assert!(errors[3].0.starts_with("Unrecognized token `ENDWHILE`"));
+
+        // without an end the parser cannot recover
+
+        let (ast, error) = parse_program_partial(Path::new("test.imp"), "PROGRAM IS x IN x := 1;".to_owned(), &mut SourceMap::new());
+        assert!(ast.is_none());
+        assert_eq!(error.unwrap().to_string(), "Unexpected end of file; expected one of \"END\", \"IF\", \"READ\", \"REPEAT\", \"WHILE\", \"WRITE\", identifier in \"test.imp\"");
+    }
+
+    #[test]
+    fn identifiers_and_keywords() {
+        let source = "PROGRAM IS x1, tab_2[3] IN READ x1; tab_2[0] := x1 + 1; END";
+        let mut source_map = SourceMap::new();
+        assert!(parse_program(Path::new("test.imp"), source.to_owned(), &mut source_map).is_ok());
+
+        // the strict dialect of the course does not allow the digits
+
+        let Err(ModuleError::Syntax(_, errors)) = check_course_dialect(&source_map) else { panic!() };
+        assert_eq!(errors.len(), 5);
+        assert_eq!(errors[1], ("Identifier `tab_2` contains a digit, which the course dialect does not allow".to_owned(), (15, 20)));
+
+        let message = |source: &str| match parse_program(Path::new("test.imp"), source.to_owned(), &mut SourceMap::new()) {
+            Err(ModuleError::Syntax(_, errors)) => errors[0].clone(),
+            _ => panic!("{}", source),
+        };
+        assert_eq!(message("PROGRAM IS x IN While x > 0 DO x := 0; ENDWHILE END"), ("The keyword WHILE has to be written in upper case, not as `While`".to_owned(), (16, 21)));
+        assert_eq!(message("PROGRAM IS x IN read x; END"), ("The keyword READ has to be written in upper case, not as `read`".to_owned(), (16, 20)));
+        assert_eq!(message("PROGRAM IS xValue IN READ x; END").0, "Invalid identifier `xValue`: identifiers consist of the characters [_a-z0-9] and do not start with a digit");
+        assert_eq!(message("PROGRAM IS Tab IN READ x; END").1, (11, 14));
+        assert_eq!(message("PROGRAM IS x IN x := 2x; END").0, "Invalid identifier `2x`: identifiers consist of the characters [_a-z0-9] and do not start with a digit");
+        assert_eq!(message("PROGRAM IS x IN x := ; END").0, "Unrecognized token `;`; expected one of \"-\", number, identifier");
+    }
+}
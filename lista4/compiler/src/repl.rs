@@ -0,0 +1,238 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use num::BigInt;
+
+use crate::ast::Location;
+use crate::diagnostics::Diagnostic;
+use crate::emulator::{parse_code, EmulatorError, Machine, MachineIo};
+use crate::modules::{parse_commands, parse_declarations};
+use crate::translation::{declare, translate_statement};
+use crate::translation::translation_structures::{strip_annotations, SymbolTable, SymbolTableEntry, TranslationError};
+
+pub const HELP: &str = "\
+type the commands of the program to run them right away, e.g. `x := 2 * 3; WRITE x;`
+commands:
+  :declare <declarations>    declare variables and arrays as in the head of the program, e.g. `:declare x, t[10]`
+  :vars                      print the values of the variables, `?` standing for the ones not set yet
+  :code                      print the code generated for the last statement
+  :cost                      print the cost of executing the last statement
+  :help                      print this text
+  :quit                      stop";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplError {
+    Syntax(Vec<(String, Location)>),
+    Translation(TranslationError),
+    Emulator(EmulatorError),
+    UnknownCommand(String),
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplError::Syntax(errors) => {
+                let messages: Vec<&str> = errors.iter().map(|(message, _)| message.as_str()).collect();
+                write!(f, "{}", messages.join("\nError: "))
+            },
+            ReplError::Translation(e) => write!(f, "{}", Diagnostic::from_translation_error(e).message),
+            ReplError::Emulator(e) => write!(f, "{}", e),
+            ReplError::UnknownCommand(command) => write!(f, "Unknown command \"{}\", try \":help\"", command),
+        }
+    }
+}
+
+impl From<TranslationError> for ReplError {
+    fn from(e: TranslationError) -> Self {
+        return ReplError::Translation(e);
+    }
+}
+
+impl From<EmulatorError> for ReplError {
+    fn from(e: EmulatorError) -> Self {
+        return ReplError::Emulator(e);
+    }
+}
+
+// a session compiling every statement against the variables declared so far and running it at once
+// on a machine keeping its memory from one statement to the next
+pub struct Repl {
+    symbol_table: SymbolTable,
+    next_mem_byte: u64,
+    pub machine: Machine,
+    pending: String, // the lines of a statement not finished yet
+    last_code: Vec<String>,
+    last_cost: Option<u64>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        return Self{
+            symbol_table: SymbolTable::new(), next_mem_byte: 0, machine: Machine::new(Vec::new()),
+            pending: String::new(), last_code: Vec::new(), last_cost: None,
+        };
+    }
+
+    // whether the lines typed so far wait for the rest of the statement
+    pub fn is_pending(&self) -> bool {
+        return !self.pending.is_empty();
+    }
+
+    // handle a line typed in, telling what there is to print
+    pub fn execute(&mut self, line: &str, io: &mut dyn MachineIo) -> Result<String, ReplError> {
+        if !self.is_pending() {
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match command {
+                ":declare" => return self.declare(argument).map(|_| String::new()),
+                ":vars" => return Ok(self.variables()),
+                ":code" => return Ok(strip_annotations(&self.last_code).join("\n")),
+                ":cost" => return Ok(match self.last_cost {
+                    Some(cost) => format!("cost: {}", cost),
+                    None if self.last_code.is_empty() => "no statement has run yet".to_owned(),
+                    None => "the last statement did not run to the end".to_owned(),
+                }),
+                ":help" => return Ok(HELP.to_owned()),
+                _ if command.starts_with(':') => return Err(ReplError::UnknownCommand(command.to_owned())),
+                _ => {},
+            }
+        }
+
+        // the statement is run once all of its lines are there
+
+        self.pending += line;
+        self.pending += "\n";
+        let commands = match parse_commands(&self.pending) {
+            Ok(Some(commands)) => commands,
+            Ok(None) => return Ok(String::new()),
+            Err(errors) => {
+                self.pending.clear();
+                return Err(ReplError::Syntax(errors));
+            },
+        };
+        self.pending.clear();
+
+        // a statement that does not compile leaves the variables as they were
+
+        let mut symbol_table = self.symbol_table.clone();
+        let code = translate_statement(&commands, &mut symbol_table)?;
+        self.symbol_table = symbol_table;
+        self.run(code, io)?;
+        return Ok(String::new());
+    }
+
+    fn declare(&mut self, text: &str) -> Result<(), ReplError> {
+        let declarations = parse_declarations(text).map_err(ReplError::Syntax)?;
+        let mut symbol_table = self.symbol_table.clone();
+        self.next_mem_byte = declare(&declarations, &mut symbol_table, self.next_mem_byte)?;
+        self.symbol_table = symbol_table;
+        return Ok(());
+    }
+
+    fn run(&mut self, code: Vec<String>, io: &mut dyn MachineIo) -> Result<(), ReplError> {
+        self.machine.code = parse_code(&strip_annotations(&code))?;
+        self.machine.counter = 0;
+        self.machine.cost = 0;
+        self.machine.halted = false;
+        self.last_code = code;
+        self.last_cost = None;
+        self.machine.run(io, None)?;
+        self.last_cost = Some(self.machine.cost);
+        return Ok(());
+    }
+
+    // the value in the memory cell, unless nothing has been stored there
+    fn cell(&self, address: u64) -> String {
+        return self.machine.memory.get(&BigInt::from(address)).map_or("?".to_owned(), |value| value.to_string());
+    }
+
+    // the variables in the order they are declared
+    fn variables(&self) -> String {
+        let mut entries: Vec<(&String, &SymbolTableEntry)> = self.symbol_table.iter().collect();
+        entries.sort_by_key(|(_, entry)| match entry {
+            SymbolTableEntry::Var(var) => var.memloc,
+            SymbolTableEntry::Arr(arr) => arr.memloc,
+            SymbolTableEntry::Ret(ret) => ret.memloc,
+        });
+        let lines: Vec<String> = entries.into_iter().filter_map(|(name, entry)| match entry {
+            SymbolTableEntry::Var(var) => Some(format!("{} = {}", name, self.cell(var.memloc))),
+            SymbolTableEntry::Arr(arr) => {
+                let values: Vec<String> = (arr.memloc..arr.memloc + arr.len).map(|address| self.cell(address)).collect();
+                Some(format!("{} = [{}]", name, values.join(", ")))
+            },
+            SymbolTableEntry::Ret(_) => None,
+        }).collect();
+        return match lines.is_empty() {
+            true => "no variables, declare them with :declare".to_owned(),
+            false => lines.join("\n"),
+        };
+    }
+}
+
+// read the lines from the standard input until it ends or the user quits
+pub fn session(repl: &mut Repl, io: &mut dyn MachineIo) {
+    println!("type :help for the commands");
+    loop {
+        print!("{}", if repl.is_pending() { "... " } else { ">>> " });
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {},
+        }
+        match line.trim() {
+            "" if !repl.is_pending() => continue,
+            ":quit" | ":q" if !repl.is_pending() => break,
+            _ => match repl.execute(line.trim_end(), io) {
+                Ok(text) if text.is_empty() => {},
+                Ok(text) => println!("{}", text.trim_end()),
+                Err(e) => println!("Error: {}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::BufferedIo;
+    use super::*;
+
+    #[test]
+    fn statements_share_the_memory() {
+        let mut repl = Repl::new();
+        let mut io = BufferedIo::new(&[BigInt::from(6)]);
+
+        assert_eq!(repl.execute(":vars", &mut io).unwrap(), "no variables, declare them with :declare");
+        assert_eq!(repl.execute(":cost", &mut io).unwrap(), "no statement has run yet");
+        repl.execute(":declare x, i, t[3], u", &mut io).unwrap();
+        repl.execute("READ x; i := 0;", &mut io).unwrap();
+        assert_eq!(repl.execute(":cost", &mut io).unwrap(), format!("cost: {}", repl.machine.cost));
+
+        // a statement can span several lines
+
+        repl.execute("WHILE i < 3 DO", &mut io).unwrap();
+        assert!(repl.is_pending());
+        repl.execute("  t[i] := x; x := x + 1;", &mut io).unwrap();
+        repl.execute("  i := i + 1;", &mut io).unwrap();
+        repl.execute("ENDWHILE", &mut io).unwrap();
+        assert!(!repl.is_pending());
+        repl.execute("WRITE x;", &mut io).unwrap();
+        assert_eq!(io.output, [BigInt::from(9)]);
+        assert_eq!(repl.execute(":code", &mut io).unwrap().lines().last(), Some("HALT"));
+        assert_eq!(repl.execute(":vars", &mut io).unwrap(), "x = 9\ni = 3\nt = [6, 7, 8]\nu = ?");
+
+        // the errors leave the session as it was
+
+        assert_eq!(repl.execute("y := 1;", &mut io).unwrap_err().to_string(), "No such variable: \"y\"");
+        assert!(matches!(repl.execute("x := ;", &mut io), Err(ReplError::Syntax(_))));
+        assert_eq!(repl.execute(":declare x", &mut io).unwrap_err().to_string(), "Repeated declaration of \"x\"");
+        assert_eq!(repl.execute(":foo", &mut io), Err(ReplError::UnknownCommand(":foo".to_owned())));
+        assert!(matches!(repl.execute("READ x;", &mut io), Err(ReplError::Emulator(EmulatorError::MissingInput(_)))));
+        assert_eq!(repl.execute(":cost", &mut io).unwrap(), "the last statement did not run to the end");
+    }
+}
@@ -0,0 +1,152 @@
+// the prefix of the imports referring to the standard library, e.g. IMPORT "std:math";
+pub const STD_PREFIX: &str = "std:";
+
+// the modules of the standard library, embedded in the compiler
+const MODULES: [(&str, &str); 3] = [
+    ("math", include_str!("../std/math.imp")),
+    ("arrays", include_str!("../std/arrays.imp")),
+    ("signed", include_str!("../std/signed.imp")),
+];
+
+pub fn module_source(name: &str) -> Option<&'static str> {
+    return MODULES.iter().find(|(module_name, _)| *module_name == name).map(|(_, source)| *source);
+}
+
+pub fn module_names() -> impl Iterator<Item = &'static str> {
+    return MODULES.iter().map(|(name, _)| *name);
+}
+
+#[cfg(test)]
+mod tests {
+    use num::ToPrimitive;
+
+    use crate::test_support::{self, compile};
+
+    // the output of the code run on natural numbers
+    fn run(code: &[String], input: &[u64]) -> Vec<u64> {
+        let input: Vec<i64> = input.iter().map(|&value| value as i64).collect();
+        return test_support::run(code, &input).iter().map(|value| value.to_u64().unwrap()).collect();
+    }
+
+    fn gcd(a: u64, b: u64) -> u64 {
+        return if b == 0 { a } else { gcd(b, a % b) };
+    }
+
+    #[test]
+    fn all_modules_compile() {
+        for name in super::module_names() {
+            compile(&format!("IMPORT \"std:{}\";\nPROGRAM IS IN\n  WRITE 0;\nEND\n", name));
+        }
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        let code = compile("
+            IMPORT \"std:math\";
+            PROGRAM IS a, b, r IN
+              READ a; READ b;
+              math.gcd(a, b, r); WRITE r;
+              math.lcm(a, b, r); WRITE r;
+            END
+        ");
+        for (a, b) in [(0, 0), (0, 7), (7, 0), (1, 1), (12, 18), (84, 36), (17, 13), (1024, 96), (123456, 7890)] {
+            let g = gcd(a, b);
+            let l = a.checked_div(g).map_or(0, |q| q * b);
+            assert_eq!(run(&code, &[a, b]), vec![g, l], "gcd and lcm of {} and {}", a, b);
+        }
+    }
+
+    #[test]
+    fn powmod() {
+        let code = compile("
+            IMPORT \"std:math\";
+            PROGRAM IS b, e, m, r IN
+              READ b; READ e; READ m;
+              math.powmod(b, e, m, r); WRITE r;
+            END
+        ");
+        for (b, e, m) in [(2, 10, 1000), (84, 36, 1000), (3, 0, 7), (0, 0, 5), (5, 3, 1), (7, 5, 0), (123, 4567, 8191)] {
+            let expected = if m == 0 { 0 } else { (0..e).fold(1 % m, |r, _| r * b % m) };
+            assert_eq!(run(&code, &[b, e, m]), vec![expected], "{} ^ {} mod {}", b, e, m);
+        }
+    }
+
+    #[test]
+    fn isqrt() {
+        let code = compile("
+            IMPORT \"std:math\";
+            PROGRAM IS n, r IN
+              READ n;
+              math.isqrt(n, r); WRITE r;
+            END
+        ");
+        for n in (0..150).chain([1_000_000, 999_999, 123_456_789]) {
+            let expected = (n as f64).sqrt() as u64;
+            assert_eq!(run(&code, &[n]), vec![expected], "isqrt({})", n);
+        }
+    }
+
+    #[test]
+    fn is_prime() {
+        let code = compile("
+            IMPORT \"std:math\";
+            PROGRAM IS n, r IN
+              READ n;
+              math.is_prime(n, r); WRITE r;
+            END
+        ");
+        for n in (0..200).chain([7919, 7921, 65537]) {
+            let expected = (n > 1 && (2..n).take_while(|d| d * d <= n).all(|d| n % d != 0)) as u64;
+            assert_eq!(run(&code, &[n]), vec![expected], "is_prime({})", n);
+        }
+    }
+
+    #[test]
+    fn min_and_max() {
+        let code = compile("
+            IMPORT \"std:math\";
+            PROGRAM IS a, b, r IN
+              READ a; READ b;
+              math.min(a, b, r); WRITE r;
+              math.max(a, b, r); WRITE r;
+            END
+        ");
+        for (a, b) in [(0, 0), (1, 2), (2, 1), (5, 5), (100, 7)] {
+            assert_eq!(run(&code, &[a, b]), vec![a.min(b), a.max(b)]);
+        }
+    }
+
+    #[test]
+    fn sorting_arrays() {
+        let code = compile("
+            IMPORT \"std:arrays\";
+            PROGRAM IS n, i, r, t[10] IN
+              READ n;
+              i := 0;
+              WHILE i < n DO
+                READ t[i];
+                i := i + 1;
+              ENDWHILE
+              arrays.min(t, n, r); WRITE r;
+              arrays.max(t, n, r); WRITE r;
+              arrays.sort(t, n);
+              i := 0;
+              WHILE i < n DO
+                WRITE t[i];
+                i := i + 1;
+              ENDWHILE
+            END
+        ");
+        let inputs: [&[u64]; 6] = [&[], &[4], &[2, 1], &[5, 3, 9, 1, 7, 3], &[9, 8, 7, 6, 5, 4, 3, 2, 1, 0], &[1, 1, 1, 0, 0, 2, 2, 10, 0, 1]];
+        for values in inputs {
+            let mut sorted = values.to_vec();
+            sorted.sort();
+            let mut expected = vec![sorted.first().copied().unwrap_or(0), sorted.last().copied().unwrap_or(0)];
+            expected.extend(&sorted);
+
+            let mut input = vec![values.len() as u64];
+            input.extend(values);
+            assert_eq!(run(&code, &input), expected, "sorting {:?}", values);
+        }
+    }
+}
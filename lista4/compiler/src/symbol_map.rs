@@ -0,0 +1,133 @@
+use crate::diagnostics::json_string;
+use crate::modules::SourceMap;
+use crate::translation::translation_structures::{AnnotatedLine, Layout, SymbolTable, SymbolTableEntry};
+
+// the version of the format, raised whenever a field changes its meaning
+const FORMAT_VERSION: u32 = 1;
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    let members: Vec<String> = fields.iter().map(|(key, value)| format!("{}:{}", json_string(key), value)).collect();
+    return format!("{{{}}}", members.join(","));
+}
+
+// the memory cells of the variables, ordered by address; a reference holds the address of the value,
+// and the array passed to a procedure has no known length
+fn json_variables(symbols: &SymbolTable, skip: &SymbolTable) -> Vec<String> {
+    let mut entries: Vec<(&String, &SymbolTableEntry)> = symbols.iter().filter(|(name, _)| !skip.contains_key(*name)).collect();
+    entries.sort_by_key(|(name, entry)| match entry {
+        SymbolTableEntry::Var(var) => (var.memloc, (*name).clone()),
+        SymbolTableEntry::Arr(arr) => (arr.memloc, (*name).clone()),
+        SymbolTableEntry::Ret(ret) => (ret.memloc, (*name).clone()),
+    });
+
+    return entries.into_iter().filter_map(|(name, entry)| match entry {
+        SymbolTableEntry::Var(var) => Some(json_object(&[
+            ("name", json_string(name)),
+            ("kind", json_string("variable")),
+            ("address", var.memloc.to_string()),
+            ("reference", var.is_ref.to_string()),
+        ])),
+        SymbolTableEntry::Arr(arr) => Some(json_object(&[
+            ("name", json_string(name)),
+            ("kind", json_string("array")),
+            ("address", arr.memloc.to_string()),
+            ("length", if arr.is_ref { "null".to_owned() } else { arr.len.to_string() }),
+            ("reference", arr.is_ref.to_string()),
+        ])),
+        SymbolTableEntry::Ret(_) => None,
+    }).collect();
+}
+
+// the source span of every instruction, null for the ones not coming from any command
+fn json_spans(code: &[String], source_map: &SourceMap) -> Vec<String> {
+    return code.iter().map(|line| match AnnotatedLine::parse(line).location {
+        Some(location) if !source_map.files.is_empty() => {
+            let (file, (start, end)) = source_map.lookup(location);
+            let (line_start, column_start) = file.line_column(start);
+            let (line_end, column_end) = file.line_column(end);
+            json_object(&[
+                ("file", json_string(&file.path.display().to_string())),
+                ("line_start", line_start.to_string()),
+                ("column_start", column_start.to_string()),
+                ("line_end", line_end.to_string()),
+                ("column_end", column_end.to_string()),
+            ])
+        },
+        _ => "null".to_owned(),
+    }).collect();
+}
+
+fn json_list(items: &[String], indent: &str) -> String {
+    if items.is_empty() {
+        return "[]".to_owned();
+    }
+    return format!("[\n{}{}\n{}]", indent, items.join(&format!(",\n{}", indent)), &indent[2..]);
+}
+
+// the debug information of the compiled code as a JSON document: the code range and the variables
+// of every procedure, the global variables and the origin of every instruction
+pub fn symbol_map(code: &[String], layout: &Layout, source_map: &SourceMap) -> String {
+    let procedures: Vec<String> = layout.scopes.iter().map(|scope| {
+        let return_address = scope.symbols.values().find_map(|entry| match entry {
+            SymbolTableEntry::Ret(ret) => Some(ret.memloc.to_string()),
+            _ => None,
+        });
+        json_object(&[
+            ("name", json_string(&scope.name)),
+            ("start", scope.start.to_string()),
+            ("end", scope.end.to_string()),
+            ("entry", scope.entry.to_string()),
+            ("return_address", return_address.unwrap_or("null".to_owned())),
+            ("variables", format!("[{}]", json_variables(&scope.symbols, &layout.globals).join(","))),
+        ])
+    }).collect();
+
+    let source = source_map.files.first().map_or("null".to_owned(), |file| json_string(&file.path.display().to_string()));
+    let globals = json_variables(&layout.globals, &SymbolTable::new());
+
+    return format!(
+        "{{\n  \"version\": {},\n  \"source\": {},\n  \"globals\": {},\n  \"procedures\": {},\n  \"instructions\": {}\n}}\n",
+        FORMAT_VERSION,
+        source,
+        json_list(&globals, "    "),
+        json_list(&procedures, "    "),
+        json_list(&json_spans(code, source_map), "    "),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{compile_with_layout, load};
+    use crate::translation::passes::PassManager;
+    use super::*;
+
+    #[test]
+    fn layout_and_spans() {
+        let source = "GLOBALS g END\nPROCEDURE p(T t, IN n, s) IS x IN\n  x := n;\n  s := t[x];\nEND\nPROGRAM IS a, t[3] IN\n  p(t, 1, a);\n  g := a;\nEND\n";
+        let mut source_map = SourceMap::new();
+        let ast = load(source, &mut source_map);
+        let (code, layout) = compile_with_layout(ast, &PassManager::for_level(0));
+        let map = symbol_map(&code, &layout, &source_map);
+
+        assert!(map.contains(r#"{"name":"g","kind":"variable","address":0,"reference":false}"#), "{}", map);
+        assert!(map.contains(r#"{"name":"p","start":1,"end":"#), "{}", map);
+        assert!(map.contains(concat!(
+            r#""return_address":1,"variables":["#,
+            r#"{"name":"t","kind":"array","address":2,"length":null,"reference":true},"#,
+            r#"{"name":"n","kind":"variable","address":3,"reference":false},"#,
+            r#"{"name":"s","kind":"variable","address":4,"reference":true},"#,
+            r#"{"name":"x","kind":"variable","address":5,"reference":false}]}"#,
+        )), "{}", map);
+        assert!(map.contains(concat!(
+            r#"{"name":"a","kind":"variable","address":6,"reference":false},"#,
+            r#"{"name":"t","kind":"array","address":7,"length":3,"reference":false}]}"#,
+        )), "{}", map);
+
+        // one span for every instruction, the jump to main and the HALT coming from no command
+
+        let instructions = &map[map.find("\"instructions\"").unwrap()..];
+        assert_eq!(instructions.lines().filter(|line| line.trim_start().starts_with(['{', 'n'])).count(), code.len());
+        assert!(instructions.contains(r#"{"file":"test.imp","line_start":3,"column_start":3,"line_end":3,"column_end":10}"#), "{}", map);
+        assert!(map.ends_with("    null\n  ]\n}\n"), "{}", map);
+    }
+}
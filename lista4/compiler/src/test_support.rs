@@ -0,0 +1,72 @@
+// the setup shared by the unit tests: loading a program from its source, compiling and running it
+
+use std::path::Path;
+use num::BigInt;
+
+use crate::ast::ProgramAll;
+use crate::emulator::{self, BufferedIo, Machine};
+use crate::modules::{import_std_module, load_program_source, parse_program, SourceMap};
+use crate::translation::passes::PassManager;
+use crate::translation::signed::{lower_signed, SignedIo, SIGNED_MODULE};
+use crate::translation::transformation::transform;
+use crate::translation::translation_structures::Layout;
+use crate::translation::translate_with_layout;
+
+// the path the test programs are loaded from
+pub const TEST_PATH: &str = "test.imp";
+
+// the most instructions a test program may execute
+pub const STEP_LIMIT: u64 = 10_000_000;
+
+// the program alone, without its imports
+pub fn parse(source: &str) -> ProgramAll {
+    return parse_program(Path::new(TEST_PATH), source.to_owned(), &mut SourceMap::new()).unwrap();
+}
+
+// the program with its imports, the sources added to the source map
+pub fn load(source: &str, source_map: &mut SourceMap) -> ProgramAll {
+    return load_program_source(Path::new(TEST_PATH), source.to_owned(), source_map).unwrap();
+}
+
+// the loaded program lowered to the natural numbers, as with --signed
+pub fn load_signed(source: &str, source_map: &mut SourceMap) -> ProgramAll {
+    let mut ast = load(source, source_map);
+    import_std_module(&mut ast, source_map, SIGNED_MODULE).unwrap();
+    lower_signed(&mut ast);
+    return ast;
+}
+
+pub fn compile_with_layout(mut ast: ProgramAll, passes: &PassManager) -> (Vec<String>, Layout) {
+    transform(&mut ast, passes).unwrap();
+    return translate_with_layout(ast, passes).unwrap();
+}
+
+pub fn compile_with(ast: ProgramAll, passes: &PassManager) -> Vec<String> {
+    return compile_with_layout(ast, passes).0;
+}
+
+// the code of the program compiled with the default passes
+pub fn compile(source: &str) -> Vec<String> {
+    return compile_with(load(source, &mut SourceMap::new()), &PassManager::default());
+}
+
+pub fn compile_signed(source: &str) -> Vec<String> {
+    return compile_with(load_signed(source, &mut SourceMap::new()), &PassManager::default());
+}
+
+pub fn numbers(values: &[i64]) -> Vec<BigInt> {
+    return values.iter().map(|&value| BigInt::from(value)).collect();
+}
+
+// the output of the code run on the input
+pub fn run(code: &[String], input: &[i64]) -> Vec<BigInt> {
+    return emulator::run(code, &numbers(input), Some(STEP_LIMIT)).unwrap().output;
+}
+
+// the output of the code compiled with --signed, decoded into the numbers it stands for
+pub fn run_signed(code: &[String], input: &[i64]) -> Vec<BigInt> {
+    let mut machine = Machine::new(emulator::parse_code(code).unwrap());
+    let mut io = BufferedIo::new(&numbers(input));
+    machine.run(&mut SignedIo::new(&mut io), Some(STEP_LIMIT)).unwrap();
+    return io.output;
+}
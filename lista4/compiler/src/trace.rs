@@ -0,0 +1,56 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// the parts of the compiler that can explain what they are doing
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Category {
+    Regalloc,
+    Codegen,
+    Inline,
+}
+
+pub const CATEGORIES: [Category; 3] = [Category::Regalloc, Category::Codegen, Category::Inline];
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Category::Regalloc => "regalloc",
+            Category::Codegen => "codegen",
+            Category::Inline => "inline",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return CATEGORIES.into_iter().find(|category| category.name() == name);
+    }
+
+    fn bit(&self) -> u8 {
+        return 1 << (*self as u8);
+    }
+}
+
+// the categories being traced; set once by the command line, nothing is traced by default
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+
+pub fn enable(category: Category) {
+    ENABLED.fetch_or(category.bit(), Ordering::Relaxed);
+}
+
+pub fn is_enabled(category: Category) -> bool {
+    return ENABLED.load(Ordering::Relaxed) & category.bit() != 0;
+}
+
+// print the message onto the standard error, so that it never mixes with the compiled code
+pub fn emit(category: Category, message: fmt::Arguments) {
+    eprintln!("[{}] {}", category.name(), message);
+}
+
+// log the formatted message if its category is being traced; the arguments aren't evaluated otherwise
+#[macro_export]
+macro_rules! trace {
+    ($category:expr, $($arg:tt)*) => {
+        if $crate::trace::is_enabled($category) {
+            $crate::trace::emit($category, format_args!($($arg)*));
+        }
+    };
+}
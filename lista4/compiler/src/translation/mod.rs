@@ -1,10 +1,16 @@
 use std::iter::zip;
-use num::{BigInt, FromPrimitive, ToPrimitive, Signed};
+use num::{BigInt, FromPrimitive, ToPrimitive, Signed, Zero};
 use crate::ast::*;
+use crate::format::{format_command_head, format_id, format_value};
+use crate::trace;
+use crate::trace::Category;
 use translation_structures::*;
+use passes::{Pass, PassManager};
 
 pub mod translation_structures;
+pub mod passes;
 pub mod transformation;
+pub mod signed;
 
 // create an entry in the function table for the proc_head
 fn malloc_proc(proc_head: &ProcHead, function_table: &mut FunctionTable, code_line_number: usize, mem_addr: u64, location: Location) -> Result<(), TranslationError> {
@@ -23,19 +29,25 @@ fn malloc_proc(proc_head: &ProcHead, function_table: &mut FunctionTable, code_li
     Ok(())
 }
 
-// create an entry in the symbol table for each variable and array reference
+// create an entry in the symbol table for each parameter
+// IN variables hold a copy of the argument, all the others hold a reference to it
 fn malloc_args(mut curr_mem_byte: u64, decls: &ArgumentDeclarations, symbol_table: &mut SymbolTable, location: Location) -> Result<u64, TranslationError> {
     for decl in decls {
         match decl {
-            ArgumentDeclaration::Var(pid) => {
+            ArgumentDeclaration::Var(pid, mode, _) => {
                 if symbol_table.contains_key(pid) {
                     return Err(TranslationError::RepeatedDeclaration(location, pid.clone()));
                 } else {
-                    symbol_table.insert(pid.to_owned(), SymbolTableEntry::Var(Variable::new(curr_mem_byte, ValueHeld::Dynamic, true)));
+                    let var = match mode {
+                        ParameterMode::In => Variable::new(curr_mem_byte, ValueHeld::Dynamic, false),
+                        ParameterMode::Out => Variable::new(curr_mem_byte, ValueHeld::Uninitialised, true),
+                        ParameterMode::InOut => Variable::new(curr_mem_byte, ValueHeld::Dynamic, true),
+                    };
+                    symbol_table.insert(pid.to_owned(), SymbolTableEntry::Var(var));
                     curr_mem_byte += 1;
                 }
             },
-            ArgumentDeclaration::Arr(pid) => {
+            ArgumentDeclaration::Arr(pid, _, _) => {
                 if symbol_table.contains_key(pid) {
                     return Err(TranslationError::RepeatedDeclaration(location, pid.clone()));
                 } else {
@@ -52,7 +64,7 @@ fn malloc_args(mut curr_mem_byte: u64, decls: &ArgumentDeclarations, symbol_tabl
 fn malloc(mut curr_mem_byte: u64, decls: &Declarations, symbol_table: &mut SymbolTable, location: Location) -> Result<u64, TranslationError> {
     for decl in decls {
         match decl {
-            Declaration::Var(pid) => {
+            Declaration::Var(pid, _) => {
                 if symbol_table.contains_key(pid) {
                     return Err(TranslationError::RepeatedDeclaration(location, pid.clone()));
                 } else {
@@ -60,12 +72,13 @@ fn malloc(mut curr_mem_byte: u64, decls: &Declarations, symbol_table: &mut Symbo
                     curr_mem_byte += 1;
                 }
             },
-            Declaration::Arr(pid, len) => {
+            Declaration::Arr(pid, len, _) => {
                 if symbol_table.contains_key(pid) {
                     return Err(TranslationError::RepeatedDeclaration(location, pid.clone()));
                 } else {
-                    symbol_table.insert(pid.to_owned(), SymbolTableEntry::Arr(Array::new(curr_mem_byte, *len, ValueHeld::Uninitialised, false)));
-                    curr_mem_byte += *len;
+                    let len = len.to_u64().ok_or_else(|| TranslationError::ArrayTooBig(location, pid.clone()))?;
+                    symbol_table.insert(pid.to_owned(), SymbolTableEntry::Arr(Array::new(curr_mem_byte, len, ValueHeld::Uninitialised, false)));
+                    curr_mem_byte += len;
                 }
             },
         }
@@ -78,11 +91,11 @@ fn malloc(mut curr_mem_byte: u64, decls: &Declarations, symbol_table: &mut Symbo
 fn store_variable_code(id: &Identifier, register: &Register, symbol_table: &mut SymbolTable, register_states: &mut RegisterStates) -> Vec<String> {
     let mut code = Vec::new();
 
-    println!("storing variable {:?} under register {}", id, register_to_string(&register));
+    trace!(Category::Regalloc, "spill {} from register {}", format_id(id), register_to_string(register));
 
     // load the variable's address
 
-    let mut var_fetch_code = translate_fetch(id, &Register::B, symbol_table, false, None, register_states, (0, 0)).unwrap();
+    let mut var_fetch_code = translate_fetch(id, &Register::B, symbol_table, None, register_states, (0, 0)).unwrap();
     code.append(&mut var_fetch_code);
 
     // store the value under the address
@@ -90,6 +103,8 @@ fn store_variable_code(id: &Identifier, register: &Register, symbol_table: &mut
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(register));
     add_command(&mut code, "STORE b");
 
+    add_label(&mut code, &format!("spill {} from {}", format_id(id), register_to_string(register)));
+
     return code;
 }
 
@@ -97,7 +112,7 @@ fn store_variable_code(id: &Identifier, register: &Register, symbol_table: &mut
 fn reset_register_memory(symbol_table: &mut SymbolTable, register_states: &mut RegisterStates) -> Vec<String> {
     let mut code = Vec::new();
 
-    println!("RESETTING REGISTERS");
+    trace!(Category::Regalloc, "flush registers: {}", register_states);
 
     for register in [Register::C, Register::D, Register::E, Register::F, Register::G, Register::H] {
         if let RegisterState::Variable(id) = register_states.registers.get(&register).unwrap() {
@@ -109,7 +124,39 @@ fn reset_register_memory(symbol_table: &mut SymbolTable, register_states: &mut R
     register_states.registers.entry(Register::A).and_modify(|state| *state = RegisterState::Noise);
     register_states.registers.entry(Register::B).and_modify(|state| *state = RegisterState::Noise);
 
-    println!("RESET COMPLETE: {:?}", register_states);
+    return code;
+}
+
+// store the values of all elements of the array held within registers and change their states to noise
+fn flush_array_code(arrname: &Pidentifier, symbol_table: &mut SymbolTable, register_states: &mut RegisterStates) -> Vec<String> {
+    let mut code = Vec::new();
+
+    for register in [Register::C, Register::D, Register::E, Register::F, Register::G, Register::H] {
+        if let RegisterState::Variable(id @ Identifier::ArrNum(name, _)) = register_states.registers.get(&register).unwrap() {
+            if name == arrname {
+                let mut var_store_code = store_variable_code(&id.clone(), &register, symbol_table, register_states);
+                code.append(&mut var_store_code);
+                register_states.registers.entry(register).and_modify(|state| *state = RegisterState::Noise);
+            }
+        }
+    }
+
+    return code;
+}
+
+// array elements indexed by a variable can't be kept in registers, as the index might change,
+// and neither can the variables sharing their memory with others, as the others might change;
+// store the value of such a variable immediately after it has been modified
+// NOTICE: erases the contents of registers A, B and C
+fn store_uncached_code(id: &Identifier, symbol_table: &mut SymbolTable, register_states: &mut RegisterStates) -> Vec<String> {
+    let mut code = Vec::new();
+
+    if matches!(id, Identifier::ArrPid(..)) || register_states.is_aliased(id) {
+        if let Some(register) = register_states.scan(id) {
+            code.append(&mut store_variable_code(id, &register, symbol_table, register_states));
+            register_states.registers.entry(register).and_modify(|state| *state = RegisterState::Noise);
+        }
+    }
 
     return code;
 }
@@ -157,7 +204,7 @@ fn move_value_code(register: &Register, current_id: Option<&Identifier>, registe
 }
 
 // TODO: create the new value based on the old one in more cases with shifts and DECs
-// create the specified Num (u64) value and store it in the register of choice
+// create the specified Num value and store it in the register of choice
 fn translate_load_const(value: Num, register: &Register, register_states: &mut RegisterStates, symbol_table: &mut SymbolTable) -> Vec<String> {
     let mut code = Vec::new();
 
@@ -168,15 +215,15 @@ fn translate_load_const(value: Num, register: &Register, register_states: &mut R
     // if the current value of the register is "close", increment/decrement it to get the result
 
     if let RegisterState::Constant(curr) = modified_register_state {
-        let difference = BigInt::from_u64(value).unwrap() - curr.clone();
-        *modified_register_state = RegisterState::Constant(BigInt::from_u64(value).unwrap());
+        let difference = &value - curr.clone();
+        *modified_register_state = RegisterState::Constant(value.clone());
 
         if difference == BigInt::from_i64(0).unwrap() {
             return Vec::new();
         } else if difference > BigInt::from_i64(0).unwrap() && difference <= BigInt::from_i64(3).unwrap() {
-            return vec![String::from("INC ".to_owned() + register_str)].iter().cycle().take(difference.abs().to_usize().unwrap()).cloned().collect();
+            return [("INC ".to_owned() + register_str)].iter().cycle().take(difference.abs().to_usize().unwrap()).cloned().collect();
         } else if difference < BigInt::from_i64(0).unwrap() && difference >= BigInt::from_i64(-3).unwrap() {
-            return vec![String::from("DEC ".to_owned() + register_str)].iter().cycle().take(difference.abs().to_usize().unwrap()).cloned().collect();
+            return [("DEC ".to_owned() + register_str)].iter().cycle().take(difference.abs().to_usize().unwrap()).cloned().collect();
         } // else: continue on
     }
 
@@ -189,13 +236,13 @@ fn translate_load_const(value: Num, register: &Register, register_states: &mut R
 
     // modify the register's state
 
-    register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Constant(BigInt::from_u64(value).unwrap()));
+    register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Constant(value.clone()));
 
     // reset the chosen register
 
     add_command_string(&mut code, "RST ".to_owned() + register_str);
 
-    if value == 0 {
+    if value.is_zero() {
         return code;
     }
 
@@ -222,7 +269,7 @@ fn translate_load_const(value: Num, register: &Register, register_states: &mut R
 
 // fetch the address of a Pidentifier into the register of choice
 // NOTICE: erases the contents of registers A and B
-fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table: &mut SymbolTable, check_initialisation: bool, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
+fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table: &mut SymbolTable, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
 
     // check if the varname exists in the symbol table...
@@ -233,16 +280,6 @@ fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table:
 
         if let SymbolTableEntry::Var(var) = entry {
 
-            // check initialisation if needed
-
-            /*
-            if check_initialisation {
-                if matches!(var.value, ValueHeld::Uninitialised) {
-                    return Err(TranslationError::UninitialisedVariable(location, varname.clone()));
-                }
-            }
-            */
-
             // update the value in the symbol table if required
 
             if let Some(valtype) = update_value {
@@ -255,7 +292,7 @@ fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table:
 
                 // ...if so, load the reference's address into register B
 
-                let mut ref_address_code = translate_load_const(var.memloc, &Register::B, register_states, symbol_table);
+                let mut ref_address_code = translate_load_const(Num::from(var.memloc), &Register::B, register_states, symbol_table);
                 code.append(&mut ref_address_code);
 
                 let comment = varname.to_owned() + " IS ref; indirectly fetching address into register " + register_to_string(register);
@@ -269,11 +306,13 @@ fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table:
                 // if the resulting address is to be stored in a register other than A, move it
 
                 code.append(&mut move_value_code(register, None, register_states, symbol_table));
+                register_states.registers.entry(Register::A).and_modify(|state| *state = RegisterState::Noise);
+                register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Noise);
             } else {
 
                 // ..otherwise, load the address directly into the specified register
 
-                let mut pid_address_code = translate_load_const(var.memloc, register, register_states, symbol_table);
+                let mut pid_address_code = translate_load_const(Num::from(var.memloc), register, register_states, symbol_table);
                 code.append(&mut pid_address_code);
 
                 let comment = varname.to_owned() + " is NOT ref; directly fetching address into register " + register_to_string(register);
@@ -291,7 +330,7 @@ fn translate_fetch_pid(varname: &Pidentifier, register: &Register, symbol_table:
 // fetch the address of a specified array element into the register of choice
 // NOTICE: erases the contents of registers A and B
 // TODO: array bound checking
-fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register, symbol_table: &mut SymbolTable, check_initialisation: bool, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
+fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register, symbol_table: &mut SymbolTable, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
     
     // check if the arrname exists in the symbol table...
@@ -302,16 +341,6 @@ fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register,
 
         if let SymbolTableEntry::Arr(arr) = entry {
 
-            // check initialisation if needed
-
-            /*
-            if check_initialisation {
-                if matches!(arr.value, ValueHeld::Uninitialised) {
-                    return Err(TranslationError::UninitialisedVariable(location, arrname.clone()));
-                }
-            }
-            */
-            
             // update the value in the symbol table if required
 
             if let Some(valtype) = update_value {
@@ -324,7 +353,7 @@ fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register,
 
                 // if so, load the reference's address into register B
 
-                let mut ref_address_code = translate_load_const(arr.memloc, &Register::B, register_states, symbol_table);
+                let mut ref_address_code = translate_load_const(Num::from(arr.memloc), &Register::B, register_states, symbol_table);
                 code.append(&mut ref_address_code);
 
                 let comment = arrname.to_owned() + " IS array ref; indirectly fetching address into register " + register_to_string(register);
@@ -347,11 +376,13 @@ fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register,
                 // if the resulting address is to be stored in a register other than A, move it
 
                 code.append(&mut move_value_code(register, None, register_states, symbol_table));
+                register_states.registers.entry(Register::A).and_modify(|state| *state = RegisterState::Noise);
+                register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Noise);
             } else {
                 
                 // ..otherwise, load the address directly into the specified register
 
-                let mut arrnum_address_code = translate_load_const(arr.memloc + idx, register, register_states, symbol_table);
+                let mut arrnum_address_code = translate_load_const(Num::from(arr.memloc) + idx, register, register_states, symbol_table);
                 code.append(&mut arrnum_address_code);
 
                 let comment = arrname.to_owned() + " is NOT array ref; directly fetching address into register " + register_to_string(register);
@@ -372,25 +403,38 @@ fn translate_fetch_arrnum(arrname: &Pidentifier, idx: Num, register: &Register,
 fn translate_fetch_arrpid(arrname: &Pidentifier, idx_varname: &Pidentifier, register: &Register, symbol_table: &mut SymbolTable, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
     
-    // fetch the address of the indexing variable into register B...
+    // the registers can't hold any elements of the array, as one of them might be the one indexed
+
+    code.append(&mut flush_array_code(arrname, symbol_table, register_states));
+
+    if let Some(idx_register) = register_states.scan(&Identifier::Pid(idx_varname.clone())) {
+
+        // if the indexing variable is held in a register, copy its value into register A...
 
-    let mut fetch_idx_var_code = translate_fetch_pid(idx_varname, &Register::B, symbol_table, true, None, register_states, location)?;
-    code.append(&mut fetch_idx_var_code);
+        add_command_string(&mut code, "GET ".to_owned() + register_to_string(&idx_register));
+    } else {
+
+        // ...otherwise fetch the address of the indexing variable into register B...
+
+        let mut fetch_idx_var_code = translate_fetch_pid(idx_varname, &Register::B, symbol_table, None, register_states, location)?;
+        code.append(&mut fetch_idx_var_code);
+
+        // ...and then load its value into register A
+
+        add_command(&mut code, "LOAD b");
+    }
     
     let comment = "fetching ".to_owned() + arrname + "[" + idx_varname + "]'s address into register " + register_to_string(register);
     add_comment(&mut code, &comment);
 
-    // ...then load its value into register A...
-
-    add_command(&mut code, "LOAD b");
-
-    // ...and temporarily store it in register C
+    // temporarily store the index in register C
 
     code.append(&mut move_value_code(&Register::C, None, register_states, symbol_table));
+    register_states.registers.entry(Register::C).and_modify(|state| *state = RegisterState::Noise);
 
     // next, load the array address into register A
    
-    let mut fetch_arr_code = translate_fetch_arrnum(arrname, 0, &Register::A, symbol_table, false, None, register_states, location)?;
+    let mut fetch_arr_code = translate_fetch_arrnum(arrname, Num::zero(), &Register::A, symbol_table, None, register_states, location)?;
     code.append(&mut fetch_arr_code);
 
     // finally, add the address of the array in register A to the value of the
@@ -407,21 +451,23 @@ fn translate_fetch_arrpid(arrname: &Pidentifier, idx_varname: &Pidentifier, regi
     // if the resulting address is to be stored in a register other than A, move it
 
     code.append(&mut move_value_code(register, None, register_states, symbol_table));
+    register_states.registers.entry(Register::A).and_modify(|state| *state = RegisterState::Noise);
+    register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Noise);
 
     return Ok(code);
 }
 
 // fetch the address of the specified Identifier into the register of choice
 // NOTICE: erases the contents of registers A, B and E
-fn translate_fetch(id: &Identifier, register: &Register, symbol_table: &mut SymbolTable, check_initialisation: bool, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
+fn translate_fetch(id: &Identifier, register: &Register, symbol_table: &mut SymbolTable, update_value: Option<ValueHeld>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
 
     // execute the appropriate fetch code based on the Identifier type
 
     match id {
         Identifier::Pid(varname) => 
-            return translate_fetch_pid(varname, register, symbol_table, check_initialisation, update_value, register_states, location),
+            return translate_fetch_pid(varname, register, symbol_table, update_value, register_states, location),
         Identifier::ArrNum(arrname, idx) =>
-            return translate_fetch_arrnum(arrname, *idx, register, symbol_table, check_initialisation, update_value, register_states, location),
+            return translate_fetch_arrnum(arrname, idx.clone(), register, symbol_table, update_value, register_states, location),
         Identifier::ArrPid(arrname, idx_varname) =>
             return translate_fetch_arrpid(arrname, idx_varname, register, symbol_table, register_states, location),
     }
@@ -437,12 +483,13 @@ fn translate_val(value: &Value, register: &Register, symbol_table: &mut SymbolTa
 
             // generate the const value in the chosen register
 
-            code.append(&mut translate_load_const(*num, register, register_states, symbol_table));
+            code.append(&mut translate_load_const(num.clone(), register, register_states, symbol_table));
 
-            let comment = "generating constant ".to_owned() + &num.to_string() + " into register " + &register_to_string(register);
+            let comment = "generating constant ".to_owned() + &num.to_string() + " into register " + register_to_string(register);
             add_comment(&mut code, &comment);
 
         },
+        Value::Negative(num) => return Err(TranslationError::NegativeNumber(location, num.clone())),
         Value::Id(id) => {
             if let Some(val_register) = register_states.scan(id) {
 
@@ -457,7 +504,7 @@ fn translate_val(value: &Value, register: &Register, symbol_table: &mut SymbolTa
 
                 // fetch the address of the Identifier into register B...
 
-                let mut fetch_id_code = translate_fetch(id, &Register::B, symbol_table, true, None, register_states, location)?;
+                let mut fetch_id_code = translate_fetch(id, &Register::B, symbol_table, None, register_states, location)?;
                 code.append(&mut fetch_id_code);
 
                 // ...and load its value into the specified register
@@ -465,8 +512,7 @@ fn translate_val(value: &Value, register: &Register, symbol_table: &mut SymbolTa
                 let mut load_code = Vec::new();
                 add_command(&mut load_code, "LOAD b");
 
-                let comment = "loading ".to_owned() + &format!("{:?}", id) + "'s value into register " + &register_to_string(register);
-                add_comment(&mut load_code, &comment);
+                add_label(&mut load_code, &format!("reload {} into {}", format_id(id), register_to_string(register)));
 
                 code.append(&mut load_code);
 
@@ -474,8 +520,8 @@ fn translate_val(value: &Value, register: &Register, symbol_table: &mut SymbolTa
                 
                 // update the register's state
 
-                if (matches!(id, Identifier::Pid{..}) || matches!(id, Identifier::ArrNum{..})) && register != &Register::A && register != &Register::B {
-                    println!("saving");
+                if (matches!(id, Identifier::Pid{..}) || matches!(id, Identifier::ArrNum{..})) && !register_states.is_aliased(id) && register != &Register::A && register != &Register::B {
+                    trace!(Category::Regalloc, "cache {} in register {}", format_id(id), register_to_string(register));
                     register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Variable(id.clone()));
                 } else {
                     register_states.registers.entry(register.clone()).and_modify(|state| *state = RegisterState::Noise);
@@ -541,7 +587,7 @@ fn translate_add_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -550,7 +596,7 @@ fn translate_add_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
@@ -566,7 +612,7 @@ fn translate_add_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         result_register = register_states.get_next();
     }
 
-    let comment = format!("{:?}", lhs) + " + " + &format!("{:?}", rhs);
+    let comment = format_value(lhs) + " + " + &format_value(rhs);
     add_comment(&mut code, &comment);
 
     // add the values 
@@ -575,7 +621,7 @@ fn translate_add_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
     add_command_string(&mut addition_code, "GET ".to_owned() + register_to_string(&lhs_register));
     add_command_string(&mut addition_code, "ADD ".to_owned() + register_to_string(&rhs_register));
 
-    let comment = "performing addition; storing in register ".to_owned() + &register_to_string(&result_register);
+    let comment = "performing addition; storing in register ".to_owned() + register_to_string(&result_register);
     add_comment(&mut addition_code, &comment);
 
     code.append(&mut addition_code);
@@ -644,7 +690,7 @@ fn translate_sub_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -653,7 +699,7 @@ fn translate_sub_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
@@ -669,7 +715,7 @@ fn translate_sub_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         result_register = register_states.get_next();
     }
 
-    let comment = format!("{:?}", lhs) + " - " + &format!("{:?}", rhs);
+    let comment = format_value(lhs) + " - " + &format_value(rhs);
     add_comment(&mut code, &comment);
 
     // subtract the values
@@ -678,7 +724,7 @@ fn translate_sub_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
     add_command_string(&mut subtraction_code, "GET ".to_owned() + register_to_string(&lhs_register));
     add_command_string(&mut subtraction_code, "SUB ".to_owned() + register_to_string(&rhs_register));
 
-    let comment = "performing subtraction; storing in register ".to_owned() + &register_to_string(&result_register);
+    let comment = "performing subtraction; storing in register ".to_owned() + register_to_string(&result_register);
     add_comment(&mut subtraction_code, &comment);
 
     code.append(&mut subtraction_code);
@@ -792,7 +838,7 @@ fn translate_mul_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -801,7 +847,7 @@ fn translate_mul_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
@@ -814,10 +860,10 @@ fn translate_mul_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
     if let Some(register) = register_states.scan(id) {
         result_register = register;
     } else {
-        result_register = register_states.get_next();
+        result_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register]);
     }
 
-    let comment = format!("{:?}", lhs) + " * " + &format!("{:?}", rhs);
+    let comment = format_value(lhs) + " * " + &format_value(rhs);
     add_comment(&mut code, &comment);
     
     // store the variable held by the result register, if any
@@ -831,13 +877,13 @@ fn translate_mul_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
 
     // copy the lhs into first auxiliary register
 
-    let aux1_register = register_states.get_next();
+    let aux1_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&lhs_register));
     code.append(&mut move_value_code(&aux1_register, None, register_states, symbol_table));
     
     // copy the rhs into second auxiliary register
 
-    let aux2_register = register_states.get_next();
+    let aux2_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register, &aux1_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&rhs_register));
     code.append(&mut move_value_code(&aux2_register, None, register_states, symbol_table));
 
@@ -865,9 +911,7 @@ fn divide_code(curr_line: usize, lhs_register: &Register, rhs_register: &Registe
 
         // swap the quotient and modulo registers in case of modulo operation
 
-        let temp_reg = rem_register;
-        rem_register = result_register;
-        result_register = temp_reg;
+        std::mem::swap(&mut rem_register, &mut result_register);
     }
 
     // register B will hold the quotient, register E the remainder
@@ -988,7 +1032,7 @@ fn translate_div_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -997,7 +1041,7 @@ fn translate_div_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
@@ -1010,10 +1054,10 @@ fn translate_div_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
     if let Some(register) = register_states.scan(id) {
         result_register = register;
     } else {
-        result_register = register_states.get_next();
+        result_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register]);
     }
 
-    let comment = format!("{:?}", lhs) + " / " + &format!("{:?}", rhs);
+    let comment = format_value(lhs) + " / " + &format_value(rhs);
     add_comment(&mut code, &comment);
     
     // store the variable held by the result register, if any
@@ -1027,13 +1071,13 @@ fn translate_div_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
 
     // copy the lhs into first auxiliary register
 
-    let aux1_register = register_states.get_next();
+    let aux1_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&lhs_register));
     code.append(&mut move_value_code(&aux1_register, None, register_states, symbol_table));
     
     // copy the rhs into second auxiliary register
 
-    let aux2_register = register_states.get_next();
+    let aux2_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register, &aux1_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&rhs_register));
     code.append(&mut move_value_code(&aux2_register, None, register_states, symbol_table));
 
@@ -1105,7 +1149,7 @@ fn translate_mod_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
             
             // if any of the conditions fail, load its value into register A
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1114,7 +1158,7 @@ fn translate_mod_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
         
         // if any of the conditions fail, load its value into register A
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
@@ -1127,10 +1171,10 @@ fn translate_mod_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
     if let Some(register) = register_states.scan(id) {
         result_register = register;
     } else {
-        result_register = register_states.get_next();
+        result_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register]);
     }
     
-    let comment = format!("{:?}", lhs) + " % " + &format!("{:?}", rhs);
+    let comment = format_value(lhs) + " % " + &format_value(rhs);
     add_comment(&mut code, &comment);
     
     // store the variable held by the result register, if any
@@ -1144,13 +1188,13 @@ fn translate_mod_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
 
     // copy the lhs into first auxiliary register
 
-    let aux1_register = register_states.get_next();
+    let aux1_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&lhs_register));
     code.append(&mut move_value_code(&aux1_register, None, register_states, symbol_table));
     
     // copy the rhs into second auxiliary register
 
-    let aux2_register = register_states.get_next();
+    let aux2_register = register_states.get_next_excluding(&[&lhs_register, &rhs_register, &result_register, &aux1_register]);
     add_command_string(&mut code, "GET ".to_owned() + register_to_string(&rhs_register));
     code.append(&mut move_value_code(&aux2_register, None, register_states, symbol_table));
 
@@ -1173,7 +1217,7 @@ fn translate_mod_expr(id: &Identifier, lhs: &Value, rhs: &Value, symbol_table: &
 // calculate the value of the specified Expression and store the result in the register of choice
 // NOTICE: erases the contents of registers A, B, C, D and E
 fn translate_expr(id: &Identifier, expr: &Expression, symbol_table: &mut SymbolTable, curr_line: usize, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
-    println!("{:?} = {:?}, {:?}", id, expr, register_states);
+    trace!(Category::Regalloc, "evaluate into {} with {}", format_id(id), register_states);
     match expr {
         Expression::Val(value) => {
 
@@ -1184,7 +1228,17 @@ fn translate_expr(id: &Identifier, expr: &Expression, symbol_table: &mut SymbolT
             if let Some(register) = register_states.scan(id) {
                 result_register = register;
             } else {
-                result_register = register_states.get_next();
+
+                // do not overwrite the register already holding the value
+
+                let value_register = match value {
+                    Value::Id(value_id) => register_states.scan(value_id),
+                    Value::Number(_) | Value::Negative(_) => None,
+                };
+                result_register = match value_register {
+                    Some(value_register) => register_states.get_next_excluding(&[&value_register]),
+                    None => register_states.get_next(),
+                };
             }
 
             let val_code = translate_val(value, &result_register, symbol_table, register_states, location);
@@ -1205,13 +1259,40 @@ fn translate_expr(id: &Identifier, expr: &Expression, symbol_table: &mut SymbolT
     }
 }
 
+// check that the modified Identifier has been declared as the right kind of variable,
+// as the new value might never leave the register
+fn check_target(id: &Identifier, symbol_table: &SymbolTable, location: Location) -> Result<(), TranslationError> {
+    match id {
+        Identifier::Pid(pid) => match symbol_table.get(pid) {
+            Some(SymbolTableEntry::Var(_)) => Ok(()),
+            Some(SymbolTableEntry::Arr(_)) => Err(TranslationError::NoArrayIndex(location, pid.clone())),
+            _ => Err(TranslationError::NoSuchVariable(location, pid.clone())),
+        },
+        Identifier::ArrNum(arrpid, _) | Identifier::ArrPid(arrpid, _) => {
+            match symbol_table.get(arrpid) {
+                Some(SymbolTableEntry::Arr(_)) => {},
+                Some(SymbolTableEntry::Var(_)) => return Err(TranslationError::NotAnArray(location, arrpid.clone())),
+                _ => return Err(TranslationError::NoSuchVariable(location, arrpid.clone())),
+            }
+            if let Identifier::ArrPid(_, idxpid) = id {
+                return check_target(&Identifier::Pid(idxpid.clone()), symbol_table, location);
+            }
+            Ok(())
+        },
+    }
+}
+
 // store the value of the rhs Expression at the address of the lhs Identifier
 fn translate_assignment(id: &Identifier, expr: &Expression, symbol_table: &mut SymbolTable, curr_line: usize, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
 
+    check_target(id, symbol_table, location)?;
+
     let mut expr_code = translate_expr(id, expr, symbol_table, curr_line, register_states, location)?;
     code.append(&mut expr_code);
 
+    code.append(&mut store_uncached_code(id, symbol_table, register_states));
+
     return Ok(code);
 }
 
@@ -1226,7 +1307,7 @@ fn translate_return(symbol_table: &mut SymbolTable, register_states: &mut Regist
         // ...and that it is of a correct type
 
         if let SymbolTableEntry::Ret(return_location) = ret {
-            let ret_mem_loc = return_location.memloc.clone();
+            let ret_mem_loc = return_location.memloc;
 
             // store all variables currently kept in registers
 
@@ -1242,7 +1323,7 @@ fn translate_return(symbol_table: &mut SymbolTable, register_states: &mut Regist
             
             // load the return address
 
-            let mut ret_addr_code = translate_load_const(ret_mem_loc, &Register::B, register_states, symbol_table);
+            let mut ret_addr_code = translate_load_const(Num::from(ret_mem_loc), &Register::B, register_states, symbol_table);
             code.append(&mut ret_addr_code);
             add_command(&mut code, "LOAD b");
 
@@ -1271,7 +1352,7 @@ fn translate_store_var_reference(arg_memloc: u64, is_ref: bool, store_memloc: u6
 
         // if the variable is a reference, first load the reference's address into register B...
 
-        let mut fetch_ref_code = translate_load_const(arg_memloc, &Register::B, register_states, symbol_table);
+        let mut fetch_ref_code = translate_load_const(Num::from(arg_memloc), &Register::B, register_states, symbol_table);
         code.append(&mut fetch_ref_code);
 
         // ...and then fetch the value stored under it (original var's address) into register A
@@ -1280,7 +1361,7 @@ fn translate_store_var_reference(arg_memloc: u64, is_ref: bool, store_memloc: u6
 
         // load the store memory location into register B
 
-        let mut fetch_store_code = translate_load_const(store_memloc, &Register::B, register_states, symbol_table);
+        let mut fetch_store_code = translate_load_const(Num::from(store_memloc), &Register::B, register_states, symbol_table);
         code.append(&mut fetch_store_code);
 
         // store the original variable's address
@@ -1290,12 +1371,12 @@ fn translate_store_var_reference(arg_memloc: u64, is_ref: bool, store_memloc: u6
 
         // if the variable isn't a reference, load the variable's address into register A
 
-        let mut fetch_var_code = translate_load_const(arg_memloc, &Register::A, register_states, symbol_table);
+        let mut fetch_var_code = translate_load_const(Num::from(arg_memloc), &Register::A, register_states, symbol_table);
         code.append(&mut fetch_var_code);
 
         // load the store memory location into register B
 
-        let mut fetch_store_code = translate_load_const(store_memloc, &Register::B, register_states, symbol_table);
+        let mut fetch_store_code = translate_load_const(Num::from(store_memloc), &Register::B, register_states, symbol_table);
         code.append(&mut fetch_store_code);
 
         // store the variable's address
@@ -1331,7 +1412,32 @@ fn translate_proc_call(name: &Pidentifier, args: &Arguments, symbol_table: &mut
         // check if the type of each argument matches
         // the type of the destination procedure parameter
 
-        for (arg_no, (arg_name, arg_decl)) in zip(args, &proc_info.args_decl).enumerate() {
+        for (arg_no, (arg_value, arg_decl)) in zip(args, &proc_info.args_decl).enumerate() {
+            let param_mem_addr = proc_info.mem_addr + 1 + arg_no as u64;
+
+            // IN variables are passed by value - copy the argument's value into the parameter
+
+            if let ArgumentDeclaration::Var(_, ParameterMode::In, _) = arg_decl {
+                let mut val_code = translate_val(arg_value, &Register::A, symbol_table, register_states, location)?;
+                code.append(&mut val_code);
+
+                let mut fetch_store_code = translate_load_const(Num::from(param_mem_addr), &Register::B, register_states, symbol_table);
+                code.append(&mut fetch_store_code);
+
+                add_command(&mut code, "STORE b");
+                continue;
+            }
+
+            // all the other parameters are passed by reference, which requires a variable name
+
+            let arg_name = match arg_value {
+                Value::Id(Identifier::Pid(pid)) => pid,
+                _ => {
+                    let (ArgumentDeclaration::Var(param_name, _, _) | ArgumentDeclaration::Arr(param_name, _, _)) = arg_decl;
+                    return Err(TranslationError::ReferenceExpected(location, param_name.clone()));
+                },
+            };
+
             if let Some(arg_entry) = symbol_table.get_mut(arg_name) {
 
                 // check type equality
@@ -1345,7 +1451,7 @@ fn translate_proc_call(name: &Pidentifier, args: &Arguments, symbol_table: &mut
 
                         // store the variable reference
 
-                        let mut store_addr_code = translate_store_var_reference(arg.memloc, arg.is_ref, proc_info.mem_addr + 1 + arg_no as u64, symbol_table, register_states);
+                        let mut store_addr_code = translate_store_var_reference(arg.memloc, arg.is_ref, param_mem_addr, symbol_table, register_states);
                         code.append(&mut store_addr_code);
                     } else {
                         return Err(TranslationError::VariableExpected(location, arg_name.clone()));
@@ -1359,7 +1465,7 @@ fn translate_proc_call(name: &Pidentifier, args: &Arguments, symbol_table: &mut
                         
                         // store the variable reference
 
-                        let mut store_addr_code = translate_store_var_reference(arg.memloc, arg.is_ref, proc_info.mem_addr + 1 + arg_no as u64, symbol_table, register_states);
+                        let mut store_addr_code = translate_store_var_reference(arg.memloc, arg.is_ref, param_mem_addr, symbol_table, register_states);
                         code.append(&mut store_addr_code);
                     } else {
                         return Err(TranslationError::ArrayExpected(location, arg_name.clone()));
@@ -1386,12 +1492,12 @@ fn translate_proc_call(name: &Pidentifier, args: &Arguments, symbol_table: &mut
 
         // load the return's storage address...
 
-        let mut store_addr = translate_load_const(proc_info.mem_addr, &Register::B, register_states, symbol_table);
+        let mut store_addr = translate_load_const(Num::from(proc_info.mem_addr), &Register::B, register_states, symbol_table);
         code.append(&mut store_addr);
 
         // ...and store the return address there
 
-        let mut return_addr_offset = translate_load_const(4, &Register::C, register_states, symbol_table);
+        let mut return_addr_offset = translate_load_const(Num::from(4), &Register::C, register_states, symbol_table);
         code.append(&mut return_addr_offset);
 
         add_command(&mut code, "STRK a");
@@ -1402,6 +1508,10 @@ fn translate_proc_call(name: &Pidentifier, args: &Arguments, symbol_table: &mut
 
         add_command_string(&mut code, "JUMP ".to_owned() + &(proc_info.code_line_number).to_string());
 
+        // the procedure may have modified any of the registers
+
+        register_states.clear();
+
     } else {
         return Err(TranslationError::NoSuchProcedure(location, name.clone()));
     }
@@ -1460,7 +1570,7 @@ fn translate_equal(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, reg
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1469,13 +1579,13 @@ fn translate_equal(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, reg
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " = " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " = " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check for equality of the two values
@@ -1549,7 +1659,7 @@ fn translate_not_equal(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable,
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1558,13 +1668,13 @@ fn translate_not_equal(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable,
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " != " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " != " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check for difference of the two values
@@ -1639,7 +1749,7 @@ fn translate_greater(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, r
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1648,13 +1758,13 @@ fn translate_greater(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, r
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " > " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " > " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check if lhs > rhs
@@ -1723,7 +1833,7 @@ fn translate_lesser(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, re
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1732,13 +1842,13 @@ fn translate_lesser(lhs: &Value, rhs: &Value, symbol_table: &mut SymbolTable, re
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " < " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " < " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check if lhs < rhs
@@ -1807,7 +1917,7 @@ fn translate_greater_or_equal(lhs: &Value, rhs: &Value, symbol_table: &mut Symbo
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1816,13 +1926,13 @@ fn translate_greater_or_equal(lhs: &Value, rhs: &Value, symbol_table: &mut Symbo
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " >= " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " >= " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check if lhs >= rhs
@@ -1891,7 +2001,7 @@ fn translate_lesser_or_equal(lhs: &Value, rhs: &Value, symbol_table: &mut Symbol
             
             // if any of the conditions fail, load its value into the next register
             
-            lhs_register = register_states.get_next();
+            lhs_register = register_states.get_next_excluding(&[&rhs_register]);
             let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
             code.append(&mut lhs_code);
 
@@ -1900,13 +2010,13 @@ fn translate_lesser_or_equal(lhs: &Value, rhs: &Value, symbol_table: &mut Symbol
         
         // if any of the conditions fail, load its value into the next register
         
-        lhs_register = register_states.get_next();
+        lhs_register = register_states.get_next_excluding(&[&rhs_register]);
         let mut lhs_code = translate_val(lhs, &lhs_register, symbol_table, register_states, location)?;
         code.append(&mut lhs_code);
 
     }
 
-    let comment = "condition".to_owned() + &format!("{:?}", lhs) + " <= " + &format!("{:?}", rhs) + "";
+    let comment = "condition ".to_owned() + &format_value(lhs) + " <= " + &format_value(rhs) + "";
     add_comment(&mut code, &comment);
 
     // then check if lhs <= rhs
@@ -1959,10 +2069,16 @@ fn translate_condition(condition: &Condition, symbol_table: &mut SymbolTable, re
     return Ok(code);
 }
 
-fn translate_if(condition: &Condition, commands: &Commands, symbol_table: &mut SymbolTable, function_table: &FunctionTable, curr_line: usize, curr_proc: Option<&Pidentifier>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
+fn translate_if(condition: &Condition, commands: &Commands, symbol_table: &mut SymbolTable, function_table: &FunctionTable, mut curr_line: usize, curr_proc: Option<&Pidentifier>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
 
-    println!("{:?}", register_states);
+    // RESET REGISTER STATES, so that skipping the commands leaves no variable unstored
+
+    let mut begin_reset_code = reset_register_memory(symbol_table, register_states);
+
+    code.append(&mut begin_reset_code);
+
+    curr_line += code.len();
 
     // translate the condition code
 
@@ -1972,6 +2088,11 @@ fn translate_if(condition: &Condition, commands: &Commands, symbol_table: &mut S
 
     let mut commands_code = translate_commands(commands, symbol_table, function_table, curr_line + condition_code.len(), curr_proc, register_states)?;
 
+    // reset registers at the end of the commands, before both paths meet
+
+    let mut end_reset_code = reset_register_memory(symbol_table, register_states);
+    commands_code.append(&mut end_reset_code);
+
     // fill the blank jumps in condition code
 
     let end_jump_line = curr_line + condition_code.len() + commands_code.len();
@@ -1992,28 +2113,36 @@ fn translate_if(condition: &Condition, commands: &Commands, symbol_table: &mut S
     code.append(&mut condition_code);
     code.append(&mut commands_code);
 
-    // RESET REGISTER STATES
-
-    let mut reset_code = reset_register_memory(symbol_table, register_states);
-    code.append(&mut reset_code);
-
     return Ok(code);
 }
 
-fn translate_if_else(condition: &Condition, if_commands: &Commands, else_commands: &Commands, symbol_table: &mut SymbolTable, function_table: &FunctionTable, curr_line: usize, curr_proc: Option<&Pidentifier>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
+fn translate_if_else(condition: &Condition, if_commands: &Commands, else_commands: &Commands, symbol_table: &mut SymbolTable, function_table: &FunctionTable, mut curr_line: usize, curr_proc: Option<&Pidentifier>, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
 
+    // RESET REGISTER STATES
+
+    let mut begin_reset_code = reset_register_memory(symbol_table, register_states);
+
+    code.append(&mut begin_reset_code);
+
+    curr_line += code.len();
+
     // translate the condition code
 
     let mut condition_code = translate_condition(condition, symbol_table, register_states, location)?;
 
-    // translate the if commands
+    // translate the if commands; both branches start with the register states after the condition
 
-    let mut if_commands_code = translate_commands(if_commands, symbol_table, function_table, curr_line + condition_code.len(), curr_proc, &mut register_states.clone())?;
+    let mut if_register_states = register_states.clone();
+    let mut if_commands_code = translate_commands(if_commands, symbol_table, function_table, curr_line + condition_code.len(), curr_proc, &mut if_register_states)?;
+    let mut if_reset_code = reset_register_memory(symbol_table, &mut if_register_states);
+    if_commands_code.append(&mut if_reset_code);
 
     // translate the else commands
 
     let mut else_commands_code = translate_commands(else_commands, symbol_table, function_table, curr_line + condition_code.len() + if_commands_code.len() + 1, curr_proc, register_states)?;
+    let mut else_reset_code = reset_register_memory(symbol_table, register_states);
+    else_commands_code.append(&mut else_reset_code);
 
     // jump at the end of the if_commands block
 
@@ -2041,11 +2170,6 @@ fn translate_if_else(condition: &Condition, if_commands: &Commands, else_command
     code.append(&mut if_commands_code);
     code.append(&mut else_commands_code);
 
-    // RESET REGISTER STATES
-
-    let mut reset_code = reset_register_memory(symbol_table, register_states);
-    code.append(&mut reset_code);
-
     return Ok(code);
 }
 
@@ -2092,6 +2216,10 @@ fn translate_while(condition: &Condition, commands: &Commands, symbol_table: &mu
     let blank_jump_idx = condition_code.len() - 1;
     condition_code[blank_jump_idx] += &(end_jump_line.to_string());
 
+    // the loop starts over from the condition
+
+    add_label(&mut condition_code, "loop head");
+
     // join the partial codes
 
     code.append(&mut condition_code);
@@ -2137,6 +2265,10 @@ fn translate_repeat(commands: &Commands, condition: &Condition, symbol_table: &m
     let blank_jump_idx = condition_code.len() - 1;
     condition_code[blank_jump_idx] += &((curr_line).to_string());
 
+    // the loop starts over from the first of its commands
+
+    add_label(&mut commands_code, "loop head");
+
     // join the partial codes
 
     code.append(&mut commands_code);
@@ -2149,15 +2281,19 @@ fn translate_repeat(commands: &Commands, condition: &Condition, symbol_table: &m
 fn translate_read(id: &Identifier, symbol_table: &mut SymbolTable, register_states: &mut RegisterStates, location: Location) -> Result<Vec<String>, TranslationError> {
     let mut code = Vec::new();
 
+    check_target(id, symbol_table, location)?;
+
     // read an input value into register A
 
     add_command(&mut code, "READ");
 
-    // move the value into the next register
+    // move the value into the register already holding the variable, or the next one
 
-    let register = register_states.get_next();
+    let register = register_states.scan(id).unwrap_or_else(|| register_states.get_next());
+    code.append(&mut move_value_code(&register, Some(id), register_states, symbol_table));
     register_states.registers.entry(register.clone()).and_modify(|e| *e = RegisterState::Variable(id.clone()));
-    add_command_string(&mut code, "PUT ".to_owned() + register_to_string(&register));
+
+    code.append(&mut store_uncached_code(id, symbol_table, register_states));
 
     return Ok(code);
 }
@@ -2201,73 +2337,82 @@ fn translate_commands(commands: &Commands, symbol_table: &mut SymbolTable, funct
     let mut code = Vec::new();
 
     for command in commands {
+        let command_start = code.len();
+        let assumed_registers = register_states.clone();
 
         // translate the command
 
         match command {
             Command::Assignment(id, expr, location) => {
                 let mut command_code = translate_assignment(id, expr, symbol_table, curr_line + code.len(), register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             },
             Command::If(condition, commands, location) => {
                 let mut command_code = translate_if(condition, commands, symbol_table, function_table, curr_line + code.len(), curr_proc, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::IfElse(condition, if_commands, else_commands, location) => {
                 let mut command_code = translate_if_else(condition, if_commands, else_commands, symbol_table, function_table, curr_line + code.len(), curr_proc, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::While(condition, commands, location) => {
                 let mut command_code = translate_while(condition, commands, symbol_table, function_table, curr_line + code.len(), curr_proc, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::Repeat(commands, condition, location) => {
                 let mut command_code = translate_repeat(commands, condition, symbol_table, function_table, curr_line + code.len(), curr_proc, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::ProcedureCall(proc_call, location) => {
                 let mut command_code = translate_proc_call(&proc_call.name, &proc_call.args, symbol_table, function_table, curr_proc, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                if proc_call.halts {
+                    add_command(&mut command_code, "HALT");
+                }
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::Read(id, location) => {
                 let mut command_code = translate_read(id, symbol_table, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
             Command::Write(value, location) => {
                 let mut command_code = translate_write(value, symbol_table, register_states, *location)?;
-                let comment = "--- ".to_owned() + &format!("{:?}", command) + " ---";
-                add_comment(&mut command_code, &comment);
+                add_location(&mut command_code, *location);
                 code.append(&mut command_code);
             }
         }
+
+        // record what the command's code assumes about the registers, for the debugger
+
+        add_assumed_registers(&mut code[command_start..], &assumed_registers);
+
+        // without the caching, every command starts with nothing known about the registers
+
+        if !register_states.caching {
+            code.append(&mut reset_register_memory(symbol_table, register_states));
+        }
+
+        trace!(Category::Codegen, "lines {}..{}: {}", curr_line + command_start, curr_line + code.len(), format_command_head(command));
     }
     return Ok(code);
 }
 
-fn translate_procedure(procedure: &Procedure, function_table: &mut FunctionTable, mut curr_mem_byte: u64, curr_line: usize, register_states: &mut RegisterStates) -> Result<(Vec<String>, u64), TranslationError> {
+fn translate_procedure(procedure: &Procedure, global_symbol_table: &SymbolTable, function_table: &mut FunctionTable, mut curr_mem_byte: u64, curr_line: usize, register_states: &mut RegisterStates) -> Result<(Vec<String>, u64, SymbolTable), TranslationError> {
     let mut code = Vec::new();
 
     // add the procedure to the function table
 
     malloc_proc(&procedure.proc_head, function_table, curr_line, curr_mem_byte, procedure.location)?;
 
-    // create the procedure's symbol table
+    // create the procedure's symbol table, starting with the global variables
 
-    let mut symbol_table = SymbolTable::new();
+    let mut symbol_table = global_symbol_table.clone();
 
     // insert the return address object into the symbol table
 
@@ -2278,11 +2423,23 @@ fn translate_procedure(procedure: &Procedure, function_table: &mut FunctionTable
 
     let curr_mem_byte = malloc_args(curr_mem_byte, &procedure.proc_head.args_decl, &mut symbol_table, procedure.location)?;
     let next_mem_byte = malloc(curr_mem_byte, &procedure.declarations, &mut symbol_table, procedure.location)?;
-    //println!("{} Symbol table: {:?}", &procedure.proc_head.name, symbol_table);
+    trace!(Category::Codegen, "procedure {} at line {}", procedure.proc_head.name, curr_line);
+
+    // the procedure can be called from anywhere, so nothing is known about the registers
+
+    register_states.clear();
+
+    // a global variable may also be passed by reference, and the same variable may be passed
+    // for many parameters, so none of them can be kept in registers
+
+    register_states.aliased = symbol_table.iter().filter_map(|(name, entry)| match entry {
+        SymbolTableEntry::Var(Variable{is_ref, ..}) | SymbolTableEntry::Arr(Array{is_ref, ..}) if *is_ref || global_symbol_table.contains_key(name) => Some(name.clone()),
+        _ => None,
+    }).collect();
 
     // translate the procedure commands
 
-    let mut proc_code = translate_commands(&procedure.commands, &mut symbol_table, &function_table, curr_line, Some(&procedure.proc_head.name), register_states)?;
+    let mut proc_code = translate_commands(&procedure.commands, &mut symbol_table, function_table, curr_line, Some(&procedure.proc_head.name), register_states)?;
     code.append(&mut proc_code);
 
     // attach return code
@@ -2290,36 +2447,67 @@ fn translate_procedure(procedure: &Procedure, function_table: &mut FunctionTable
     let mut ret_code = translate_return(&mut symbol_table, register_states);
     code.append(&mut ret_code);
 
-
-    return Ok((code, next_mem_byte));
+    return Ok((code, next_mem_byte, symbol_table));
 }
 
-fn translate_main(main: &Main, function_table: &FunctionTable, curr_mem_byte: u64, curr_line: usize, register_states: &mut RegisterStates) -> Result<Vec<String>, TranslationError> {
+fn translate_main(main: &Main, global_symbol_table: &SymbolTable, function_table: &FunctionTable, curr_mem_byte: u64, curr_line: usize, register_states: &mut RegisterStates) -> Result<(Vec<String>, SymbolTable), TranslationError> {
     let mut code = Vec::new();
 
-    // create main's symbol table
+    // create main's symbol table, starting with the global variables
 
-    let mut symbol_table = SymbolTable::new();
+    let mut symbol_table = global_symbol_table.clone();
 
     // allocate memory for the declarations
     
     let _next_mem_byte = malloc(curr_mem_byte, &main.declarations, &mut symbol_table, main.location)?;
-    //println!("Main Symbol table: {:?}", symbol_table);
+    trace!(Category::Codegen, "main at line {}", curr_line);
+
+    // the last procedure leaves nothing known about the registers, and the variables of main
+    // are never passed by reference to main itself, so they can all be kept in registers
+
+    register_states.clear();
+    register_states.aliased.clear();
 
     // translate the Main commands
 
     let mut main_code = translate_commands(&main.commands, &mut symbol_table, function_table, curr_line, None, register_states)?;
     code.append(&mut main_code);
 
-    return Ok(code);
+    return Ok((code, symbol_table));
+
+}
 
+// allocate the variables declared in the REPL after the ones declared before, returning the next free memory byte
+pub fn declare(decls: &Declarations, symbol_table: &mut SymbolTable, curr_mem_byte: u64) -> Result<u64, TranslationError> {
+    return malloc(curr_mem_byte, decls, symbol_table, (0, 0));
+}
+
+// translate the commands typed into the REPL on their own, against the variables declared so far;
+// nothing is known about the registers left by the commands before, and the code ends with HALT
+pub fn translate_statement(commands: &Commands, symbol_table: &mut SymbolTable) -> Result<Vec<String>, TranslationError> {
+    let mut register_states = RegisterStates::new();
+    let mut code = translate_commands(commands, symbol_table, &FunctionTable::new(), 0, None, &mut register_states)?;
+
+    // the variables kept in the registers go back into the memory, where the next statement looks for them
+
+    code.append(&mut reset_register_memory(symbol_table, &mut register_states));
+    add_command(&mut code, "HALT");
+    return Ok(code);
 }
 
 // TODO: check variable initialisation
-pub fn translate(ast: ProgramAll) -> Result<Vec<String>, TranslationError> {
+pub fn translate(ast: ProgramAll, passes: &PassManager) -> Result<Vec<String>, TranslationError> {
+    let (code, _) = translate_with_layout(ast, passes)?;
+    return Ok(code);
+}
+
+// translate the program, also telling which lines of code come from which procedure and where their variables are
+pub fn translate_with_layout(ast: ProgramAll, passes: &PassManager) -> Result<(Vec<String>, Layout), TranslationError> {
     let mut register_states = RegisterStates::new();
+    register_states.caching = passes.is_enabled(Pass::RegisterCaching);
 
     let mut code = Vec::new();
+    let mut scopes = Vec::new();
 
     // add blank JUMP (jump to main) if there are any procedures
     
@@ -2330,7 +2518,11 @@ pub fn translate(ast: ProgramAll) -> Result<Vec<String>, TranslationError> {
     // translate the code
 
     let mut function_table = FunctionTable::new();
-    let mut curr_mem_byte = 0;
+
+    // global variables occupy the beginning of the memory
+
+    let mut global_symbol_table = SymbolTable::new();
+    let mut curr_mem_byte = malloc(0, &ast.globals.declarations, &mut global_symbol_table, ast.globals.location)?;
 
     // translate the procedures into code
 
@@ -2338,8 +2530,10 @@ pub fn translate(ast: ProgramAll) -> Result<Vec<String>, TranslationError> {
 
         // translate the the procedure
 
-        let (mut proc_code, next_mem_byte) = translate_procedure(&procedure, &mut function_table, curr_mem_byte, code.len(), &mut register_states)?;
-        add_comment(&mut proc_code, &procedure.proc_head.name);
+        let (mut proc_code, next_mem_byte, symbols) = translate_procedure(procedure, &global_symbol_table, &mut function_table, curr_mem_byte, code.len(), &mut register_states)?;
+        add_label(&mut proc_code, &format!("procedure {}", procedure.proc_head.name));
+        let entry = function_table[&procedure.proc_head.name].code_line_number;
+        scopes.push(Scope{name: procedure.proc_head.name.clone(), start: code.len(), end: code.len() + proc_code.len(), entry, symbols});
         code.append(&mut proc_code);
 
         // update the location of the next free memory byte
@@ -2356,8 +2550,9 @@ pub fn translate(ast: ProgramAll) -> Result<Vec<String>, TranslationError> {
 
     // translate main into code
 
-    let mut main_code = translate_main(&ast.main, &function_table, curr_mem_byte, code.len(), &mut register_states)?;
-    add_comment(&mut main_code, ">>> Main <<<");
+    let (mut main_code, symbols) = translate_main(&ast.main, &global_symbol_table, &function_table, curr_mem_byte, code.len(), &mut register_states)?;
+    add_label(&mut main_code, "main");
+    scopes.push(Scope{name: "main".to_owned(), start: code.len(), end: code.len() + main_code.len(), entry: code.len(), symbols});
     code.append(&mut main_code);
 
     // some simple verifications of the code
@@ -2375,5 +2570,25 @@ pub fn translate(ast: ProgramAll) -> Result<Vec<String>, TranslationError> {
 
     add_command(&mut code, "HALT");
 
-    return Ok(code);
+    return Ok((code, Layout{globals: global_symbol_table, scopes}));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{compile, numbers, run};
+
+    // the operands already held in registers must not be overwritten by the result or by each other
+    #[test]
+    fn operands_keep_their_registers() {
+        let source = "
+            PROGRAM IS a, b, c, d, e, f IN
+              READ a; READ b;
+              c := a + b; d := a - b; e := a * b; f := a / b;
+              WRITE c; WRITE d; WRITE e; WRITE f;
+              c := b + a; d := b * a; e := a % b;
+              WRITE c; WRITE d; WRITE e;
+            END
+        ";
+        assert_eq!(run(&compile(source), &[7, 3]), numbers(&[10, 4, 21, 2, 10, 21, 1]));
+    }
 }
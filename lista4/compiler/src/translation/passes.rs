@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+// the optional optimisations of the compiler; the program is correct without any of them
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Pass {
+    ConstantFolding,
+    DeadProcedures,
+    Inline,
+    RegisterCaching,
+}
+
+// all the passes in the order they are run
+pub const PASSES: [Pass; 4] = [Pass::ConstantFolding, Pass::DeadProcedures, Pass::Inline, Pass::RegisterCaching];
+
+pub const MAX_LEVEL: u8 = 2;
+pub const DEFAULT_LEVEL: u8 = 2;
+
+impl Pass {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Pass::ConstantFolding => "constant-folding",
+            Pass::DeadProcedures => "dead-procedures",
+            Pass::Inline => "inline",
+            Pass::RegisterCaching => "register-caching",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return PASSES.into_iter().find(|pass| pass.name() == name);
+    }
+
+    pub fn description(&self) -> &'static str {
+        return match self {
+            Pass::ConstantFolding => "evaluate the expressions of numbers and drop the branches that never run",
+            Pass::DeadProcedures => "drop the procedures that are never called",
+            Pass::Inline => "expand the procedures that are short or called once at the place of the call",
+            Pass::RegisterCaching => "keep the values of variables in registers between the commands",
+        };
+    }
+
+    // the lowest optimisation level running the pass
+    pub fn level(&self) -> u8 {
+        return match self {
+            Pass::ConstantFolding | Pass::DeadProcedures | Pass::RegisterCaching => 1,
+            Pass::Inline => 2,
+        };
+    }
+}
+
+// the set of passes to run
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PassManager {
+    enabled: HashSet<Pass>,
+}
+
+impl PassManager {
+    pub fn for_level(level: u8) -> Self {
+        return Self{enabled: PASSES.into_iter().filter(|pass| pass.level() <= level).collect()};
+    }
+
+    pub fn enable(&mut self, pass: Pass) {
+        self.enabled.insert(pass);
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.enabled.remove(&pass);
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        return self.enabled.contains(&pass);
+    }
+
+    // the enabled passes in the order they are run
+    pub fn enabled(&self) -> Vec<Pass> {
+        return PASSES.into_iter().filter(|pass| self.is_enabled(*pass)).collect();
+    }
+
+    // a table of all the passes, telling which of them are enabled
+    pub fn listing(&self) -> String {
+        let width = PASSES.iter().map(|pass| pass.name().len()).max().unwrap();
+        return PASSES.iter().map(|pass| {
+            let state = if self.is_enabled(*pass) { "on" } else { "off" };
+            format!("{:width$}  -O{}  {:3}  {}\n", pass.name(), pass.level(), state, pass.description(), width = width)
+        }).collect();
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        return Self::for_level(DEFAULT_LEVEL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::BigInt;
+
+    use crate::modules::SourceMap;
+    use crate::test_support::{compile_with, load, numbers, run};
+    use super::*;
+
+    const PROGRAM: &str = "
+        IMPORT \"std:math\";
+        CONST k = 3;
+        GLOBALS total END
+        PROCEDURE add(IN v) IS x IN
+          x := v * k;
+          total := total + x;
+        END
+        PROCEDURE fill(T t, IN n) IS i IN
+          i := 0;
+          WHILE i < n DO
+            t[i] := i * i;
+            i := i + 1;
+          ENDWHILE
+        END
+        PROCEDURE unused(a) IS IN
+          a := 1;
+        END
+        PROGRAM IS a, b, t[5] IN
+          READ a; READ b;
+          total := 0;
+          add(a); add(b); add(k);
+          WRITE total;
+          fill(t, 5);
+          WRITE t[a]; WRITE t[4];
+          math.gcd(a, b, total); WRITE total;
+          IF k > 2 THEN WRITE 1; ELSE WRITE 0; ENDIF
+          REPEAT a := a - 1; UNTIL a = 0;
+          WRITE a;
+        END
+    ";
+
+    fn run_with(passes: &PassManager) -> Vec<BigInt> {
+        let code = compile_with(load(PROGRAM, &mut SourceMap::new()), passes);
+        return run(&code, &[2, 4]);
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(PassManager::for_level(0).enabled(), []);
+        assert_eq!(PassManager::for_level(1).enabled(), [Pass::ConstantFolding, Pass::DeadProcedures, Pass::RegisterCaching]);
+        assert_eq!(PassManager::for_level(2).enabled(), PASSES);
+    }
+
+    #[test]
+    fn passes_keep_the_behaviour() {
+        let expected = numbers(&[27, 4, 16, 2, 1, 0]);
+        for level in 0..=MAX_LEVEL {
+            assert_eq!(run_with(&PassManager::for_level(level)), expected, "-O{}", level);
+        }
+        for pass in PASSES {
+            let mut passes = PassManager::for_level(MAX_LEVEL);
+            passes.disable(pass);
+            assert_eq!(run_with(&passes), expected, "without {}", pass.name());
+
+            let mut passes = PassManager::for_level(0);
+            passes.enable(pass);
+            assert_eq!(run_with(&passes), expected, "only {}", pass.name());
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use crate::ast::*;
+use crate::emulator::MachineIo;
+use num::{BigInt, One, Zero};
+
+// the module of the standard library with the helper routines of the signed mode
+pub const SIGNED_MODULE: &str = "signed";
+
+// the temporary variables introduced into the procedures and main
+const RESULT_TEMP: &str = "_signed_result";
+const INDEX_TEMPS: [&str; 3] = ["_signed_index_a", "_signed_index_b", "_signed_index_c"];
+
+// the temporaries used by the lowered procedure or main
+#[derive(Default)]
+struct Temporaries {
+    result: bool,
+    indices: usize,
+    next_index: usize,
+}
+
+impl Temporaries {
+    fn result(&mut self) -> Identifier {
+        self.result = true;
+        return Identifier::Pid(RESULT_TEMP.to_owned());
+    }
+
+    // every command gets its own indices, as it is done using them before the next one starts
+    fn next_command(&mut self) {
+        self.next_index = 0;
+    }
+
+    fn index(&mut self) -> Pidentifier {
+        let index = INDEX_TEMPS[self.next_index].to_owned();
+        self.next_index += 1;
+        self.indices = self.indices.max(self.next_index);
+        return index;
+    }
+
+    // the temporaries are not written anywhere in the source, so they take the location of the block declaring them
+    fn declarations(&self, location: Location) -> Declarations {
+        let mut declarations: Declarations = INDEX_TEMPS[..self.indices].iter().map(|&index| Declaration::Var(index.to_owned(), location)).collect();
+        if self.result {
+            declarations.push(Declaration::Var(RESULT_TEMP.to_owned(), location));
+        }
+        return declarations;
+    }
+}
+
+// the signed integer v is stored as the natural number 2|v| + s, where s is 1 if v is negative and 0 otherwise
+fn encode(num: &Num, negative: bool) -> Num {
+    if num.is_zero() {
+        return Num::zero();
+    }
+    return num * 2 + negative as u8;
+}
+
+fn encode_literal(value: &Value) -> Value {
+    return match value {
+        Value::Number(num) => Value::Number(encode(num, false)),
+        Value::Negative(num) => Value::Number(encode(num, true)),
+        Value::Id(_) => value.clone(),
+    };
+}
+
+fn helper_call(name: &str, args: Arguments, location: Location) -> Command {
+    return Command::ProcedureCall(ProcCall::new(SIGNED_MODULE.to_owned() + "." + name, args), location);
+}
+
+// the helper writing the offending value, after which the program stops
+fn trap_call(value: Value, location: Location) -> Command {
+    return Command::ProcedureCall(ProcCall::halting(SIGNED_MODULE.to_owned() + ".trap", vec![value]), location);
+}
+
+// arrays are indexed with natural numbers, so the index variable is decoded into a temporary first;
+// a negative index stops the program instead of aliasing the element of its absolute value
+fn lower_id(id: &Identifier, prelude: &mut Commands, temporaries: &mut Temporaries, location: Location) -> Identifier {
+    if let Identifier::ArrPid(arrpid, idxpid) = id {
+        let index = temporaries.index();
+        let encoded = Value::Id(Identifier::Pid(idxpid.clone()));
+        prelude.push(Command::Assignment(Identifier::Pid(index.clone()), Expression::Mod(encoded.clone(), Value::Number(Num::from(2))), location));
+        prelude.push(Command::If(Condition::Greater(Value::Id(Identifier::Pid(index.clone())), Value::Number(Num::zero())), vec![trap_call(encoded.clone(), location)], location));
+        prelude.push(Command::Assignment(Identifier::Pid(index.clone()), Expression::Div(encoded, Value::Number(Num::from(2))), location));
+        return Identifier::ArrPid(arrpid.clone(), index);
+    }
+    return id.clone();
+}
+
+fn lower_value(value: &Value, prelude: &mut Commands, temporaries: &mut Temporaries, location: Location) -> Value {
+    if let Value::Id(id) = value {
+        return Value::Id(lower_id(id, prelude, temporaries, location));
+    }
+    return encode_literal(value);
+}
+
+// equality of signed integers is the equality of their representations, the ordering goes through the helper routine
+fn lower_condition(condition: &Condition, prelude: &mut Commands, temporaries: &mut Temporaries, location: Location) -> Condition {
+    let (Condition::Equal(lhs, rhs) | Condition::NotEqual(lhs, rhs) | Condition::Greater(lhs, rhs) | Condition::Lesser(lhs, rhs) | Condition::GreaterOrEqual(lhs, rhs) | Condition::LesserOrEqual(lhs, rhs)) = condition;
+    let lhs = lower_value(lhs, prelude, temporaries, location);
+    let rhs = lower_value(rhs, prelude, temporaries, location);
+
+    let (args, holds) = match condition {
+        Condition::Equal(..) => return Condition::Equal(lhs, rhs),
+        Condition::NotEqual(..) => return Condition::NotEqual(lhs, rhs),
+        Condition::Lesser(..) => (vec![lhs, rhs], true),
+        Condition::Greater(..) => (vec![rhs, lhs], true),
+        Condition::GreaterOrEqual(..) => (vec![lhs, rhs], false),
+        Condition::LesserOrEqual(..) => (vec![rhs, lhs], false),
+    };
+    let result = temporaries.result();
+    prelude.push(helper_call("less", [args, vec![Value::Id(result.clone())]].concat(), location));
+
+    if holds {
+        return Condition::Greater(Value::Id(result), Value::Number(Num::zero()));
+    }
+    return Condition::Equal(Value::Id(result), Value::Number(Num::zero()));
+}
+
+fn lower_commands(commands: &Commands, temporaries: &mut Temporaries) -> Commands {
+    let mut lowered = Vec::new();
+
+    for command in commands {
+        temporaries.next_command();
+        match command {
+            Command::Assignment(id, expr, location) => {
+                let target = lower_id(id, &mut lowered, temporaries, *location);
+                let (name, lhs, rhs) = match expr {
+                    Expression::Val(value) => {
+                        let value = lower_value(value, &mut lowered, temporaries, *location);
+                        lowered.push(Command::Assignment(target, Expression::Val(value), *location));
+                        continue;
+                    },
+                    Expression::Add(lhs, rhs) => ("add", lhs, rhs),
+                    Expression::Sub(lhs, rhs) => ("sub", lhs, rhs),
+                    Expression::Mul(lhs, rhs) => ("mul", lhs, rhs),
+                    Expression::Div(lhs, rhs) => ("div", lhs, rhs),
+                    Expression::Mod(lhs, rhs) => ("mod", lhs, rhs),
+                };
+                let lhs = lower_value(lhs, &mut lowered, temporaries, *location);
+                let rhs = lower_value(rhs, &mut lowered, temporaries, *location);
+
+                // only a variable can be passed by reference, so array elements receive the result through a temporary
+
+                if let Identifier::Pid(_) = target {
+                    lowered.push(helper_call(name, vec![lhs, rhs, Value::Id(target)], *location));
+                } else {
+                    let result = temporaries.result();
+                    lowered.push(helper_call(name, vec![lhs, rhs, Value::Id(result.clone())], *location));
+                    lowered.push(Command::Assignment(target, Expression::Val(Value::Id(result)), *location));
+                }
+            },
+            Command::IfElse(condition, if_commands, else_commands, location) => {
+                let condition = lower_condition(condition, &mut lowered, temporaries, *location);
+                let if_commands = lower_commands(if_commands, temporaries);
+                let else_commands = lower_commands(else_commands, temporaries);
+                lowered.push(Command::IfElse(condition, if_commands, else_commands, *location));
+            },
+            Command::If(condition, commands, location) => {
+                let condition = lower_condition(condition, &mut lowered, temporaries, *location);
+                let commands = lower_commands(commands, temporaries);
+                lowered.push(Command::If(condition, commands, *location));
+            },
+            Command::While(condition, commands, location) => {
+
+                // the condition is evaluated before the loop and again at the end of each iteration
+
+                let mut prelude = Vec::new();
+                let condition = lower_condition(condition, &mut prelude, temporaries, *location);
+                let mut commands = lower_commands(commands, temporaries);
+                commands.extend(prelude.iter().cloned());
+                lowered.append(&mut prelude);
+                lowered.push(Command::While(condition, commands, *location));
+            },
+            Command::Repeat(commands, condition, location) => {
+                let mut commands = lower_commands(commands, temporaries);
+                temporaries.next_command();
+                let condition = lower_condition(condition, &mut commands, temporaries, *location);
+                lowered.push(Command::Repeat(commands, condition, *location));
+            },
+            Command::ProcedureCall(proc_call, location) => {
+                let args = proc_call.args.iter()
+                    .map(|arg| lower_value(arg, &mut lowered, temporaries, *location))
+                    .collect();
+                lowered.push(Command::ProcedureCall(ProcCall{args, ..proc_call.clone()}, *location));
+            },
+            Command::Read(id, location) => {
+
+                // the input is a natural number
+
+                let target = lower_id(id, &mut lowered, temporaries, *location);
+                lowered.push(Command::Read(target.clone(), *location));
+                lowered.push(Command::Assignment(target.clone(), Expression::Add(Value::Id(target.clone()), Value::Id(target)), *location));
+            },
+            Command::Write(value, location) => {
+
+                // the output is the sign (1 for the negative numbers, 0 otherwise) followed by the absolute value
+
+                match value {
+                    Value::Number(num) => {
+                        lowered.push(Command::Write(Value::Number(Num::zero()), *location));
+                        lowered.push(Command::Write(Value::Number(num.clone()), *location));
+                    },
+                    Value::Negative(num) => {
+                        let sign = if num.is_zero() { Num::zero() } else { Num::one() };
+                        lowered.push(Command::Write(Value::Number(sign), *location));
+                        lowered.push(Command::Write(Value::Number(num.clone()), *location));
+                    },
+                    Value::Id(_) => {
+                        let value = lower_value(value, &mut lowered, temporaries, *location);
+                        let result = temporaries.result();
+                        lowered.push(Command::Assignment(result.clone(), Expression::Mod(value.clone(), Value::Number(Num::from(2))), *location));
+                        lowered.push(Command::Write(Value::Id(result.clone()), *location));
+                        lowered.push(Command::Assignment(result.clone(), Expression::Div(value, Value::Number(Num::from(2))), *location));
+                        lowered.push(Command::Write(Value::Id(result), *location));
+                    },
+                }
+            },
+        }
+    }
+
+    return lowered;
+}
+
+// rewrite the program working on signed integers into one working on their natural number representations;
+// the helper routines of the signed module have to be loaded already and are left as they are
+pub fn lower_signed(ast: &mut ProgramAll) {
+    for constant in ast.constants.iter_mut() {
+        constant.value = encode_literal(&constant.value);
+    }
+
+    let helper_prefix = SIGNED_MODULE.to_owned() + ".";
+    for procedure in ast.procedures.iter_mut().filter(|procedure| !procedure.proc_head.name.starts_with(&helper_prefix)) {
+        let mut temporaries = Temporaries::default();
+        procedure.commands = lower_commands(&procedure.commands, &mut temporaries);
+        procedure.declarations.append(&mut temporaries.declarations(procedure.location));
+    }
+
+    let mut temporaries = Temporaries::default();
+    ast.main.commands = lower_commands(&ast.main.commands, &mut temporaries);
+    ast.main.declarations.append(&mut temporaries.declarations(ast.main.location));
+}
+
+// the number written by the compiled code as the pair of its sign and its absolute value
+pub fn decode(sign: &BigInt, magnitude: &BigInt) -> BigInt {
+    return if sign.is_one() { -magnitude } else { magnitude.clone() };
+}
+
+// the input and the output of the lowered program running in the emulator, every two numbers it writes
+// passed on as the one number they stand for, so that it writes what the interpreter writes
+pub struct SignedIo<'a> {
+    io: &'a mut dyn MachineIo,
+    sign: Option<BigInt>, // the sign of the number whose absolute value comes next
+}
+
+impl<'a> SignedIo<'a> {
+    pub fn new(io: &'a mut dyn MachineIo) -> Self {
+        return Self{io, sign: None};
+    }
+}
+
+impl MachineIo for SignedIo<'_> {
+    fn read(&mut self) -> Option<BigInt> {
+        return self.io.read();
+    }
+
+    fn write(&mut self, value: &BigInt) {
+        match self.sign.take() {
+            Some(sign) => self.io.write(&decode(&sign, value)),
+            None => self.sign = Some(value.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::ToPrimitive;
+
+    use crate::test_support::{compile_signed, run_signed};
+
+    fn run(code: &[String], input: &[i64]) -> Vec<i64> {
+        return run_signed(code, input).iter().map(|value| value.to_i64().unwrap()).collect();
+    }
+
+    #[test]
+    fn arithmetic_and_comparisons() {
+        let code = compile_signed("
+            CONST offset = -10;
+            PROGRAM IS a, b, c IN
+              READ a; READ b;
+              a := a + offset; b := b + offset;
+              c := a + b; WRITE c;
+              c := a - b; WRITE c;
+              c := a * b; WRITE c;
+              c := a / b; WRITE c;
+              c := a % b; WRITE c;
+              IF a < b THEN WRITE 1; ELSE WRITE 0; ENDIF
+              IF a >= b THEN WRITE 1; ELSE WRITE 0; ENDIF
+              IF a = b THEN WRITE 1; ELSE WRITE 0; ENDIF
+            END
+        ");
+        for a in [0, 3, 7, 10, 13, 20] {
+            for b in [0, 4, 9, 10, 11, 17] {
+                let (x, y) = (a - 10, b - 10);
+                let quotient = if y == 0 { 0 } else { (x as f64 / y as f64).floor() as i64 };
+                let remainder = if y == 0 { 0 } else { x - y * quotient };
+                let expected = vec![x + y, x - y, x * y, quotient, remainder, (x < y) as i64, (x >= y) as i64, (x == y) as i64];
+                assert_eq!(run(&code, &[a, b]), expected, "{} and {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn arrays_with_signed_indices_and_values() {
+        let code = compile_signed("
+            PROGRAM IS t[5], i IN
+              i := 0;
+              WHILE i < 5 DO
+                t[i] := i - 2;
+                i := i + 1;
+              ENDWHILE
+              REPEAT
+                i := i - 1;
+                WRITE t[i];
+              UNTIL i = 0;
+            END
+        ");
+        assert_eq!(run(&code, &[]), vec![2, 1, 0, -1, -2]);
+    }
+
+    #[test]
+    fn negative_index_stops_the_program() {
+        let code = compile_signed("
+            PROGRAM IS t[3], i IN
+              t[1] := 5;
+              READ i;
+              i := i - 4;
+              t[i] := 7;
+              WRITE t[1];
+            END
+        ");
+        assert_eq!(run(&code, &[5]), vec![7]);
+        assert_eq!(run(&code, &[3]), vec![-1]);
+    }
+}
@@ -1,275 +1,548 @@
 use crate::ast::*;
+use crate::trace;
+use crate::trace::Category;
+use crate::visit::{walk_command, walk_command_mut, walk_value_mut, Visitor, VisitorMut};
+use super::passes::{Pass, PassManager};
 use super::translation_structures::*;
 use std::collections::HashMap;
 use std::iter::zip;
+use num::Zero;
 
 type FunctionCallTable = HashMap<String, usize>;
 
+type ConstantTable = HashMap<Pidentifier, Value>;
+
+// declaration indices of the localised IN parameters together with their arguments
+type ByValueArguments = Vec<(usize, Value)>;
+
+// counts the calls of every procedure, failing on a call to a procedure that does not exist
+struct CallCounter<'a> {
+    function_calls: &'a mut FunctionCallTable,
+    result: Result<(), TranslationError>,
+}
+
+impl Visitor for CallCounter<'_> {
+    fn visit_proc_call(&mut self, proc_call: &ProcCall, location: Location) {
+
+        // check if the call is assigned to an allowed procedure
+
+        match self.function_calls.get_mut(&proc_call.name) {
+            Some(no_proc_calls) => *no_proc_calls += 1, // increase the count of calls for the appropriate procedure
+            None => if self.result.is_ok() {
+                self.result = Err(TranslationError::NoSuchProcedure(location, proc_call.name.clone()));
+            },
+        }
+    }
+}
+
 // search the list of commands for a call to a procedure and count these
 fn check_calls(commands: &Commands, function_calls: &mut FunctionCallTable) -> Result<(), TranslationError> {
+    let mut counter = CallCounter{function_calls, result: Ok(())};
+    counter.visit_commands(commands);
+    return counter.result;
+}
+
+// searches the commands for any modification of the IN parameters
+struct InParameterCheck<'a> {
+    in_params: &'a [&'a Pidentifier],
+    procedures: &'a [Procedure],
+    result: Result<(), TranslationError>,
+}
+
+impl InParameterCheck<'_> {
+    fn fail(&mut self, location: Location, pid: &Pidentifier) {
+        if self.result.is_ok() {
+            self.result = Err(TranslationError::InParameterModified(location, pid.clone()));
+        }
+    }
+}
+
+impl Visitor for InParameterCheck<'_> {
+    fn visit_command(&mut self, command: &Command) {
+
+        // the modified variable can't be an IN parameter
+
+        if let Command::Assignment(id, _, location) | Command::Read(id, location) = command {
+            let (Identifier::Pid(pid) | Identifier::ArrNum(pid, _) | Identifier::ArrPid(pid, _)) = id;
+            if self.in_params.contains(&pid) {
+                self.fail(*location, pid);
+            }
+        }
+        walk_command(self, command);
+    }
+
+    fn visit_proc_call(&mut self, proc_call: &ProcCall, location: Location) {
+
+        // an IN parameter can't be passed to a procedure that could modify it
+
+        if let Some(procedure) = self.procedures.iter().find(|&procedure| procedure.proc_head.name == proc_call.name) {
+            for (arg, arg_decl) in zip(&proc_call.args, &procedure.proc_head.args_decl) {
+                if let Value::Id(Identifier::Pid(pid)) = arg {
+                    if self.in_params.contains(&pid) && !matches!(arg_decl, ArgumentDeclaration::Var(_, ParameterMode::In, _) | ArgumentDeclaration::Arr(_, ParameterMode::In, _)) {
+                        self.fail(location, pid);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// search the list of commands for any modification of the IN parameters
+fn check_in_parameters(commands: &Commands, in_params: &[&Pidentifier], procedures: &[Procedure]) -> Result<(), TranslationError> {
+    let mut check = InParameterCheck{in_params, procedures, result: Ok(())};
+    check.visit_commands(commands);
+    return check.result;
+}
+
+// finds the first of the given variables read by an expression, condition or value
+struct FirstRead<'a, 'b> {
+    names: &'b [&'a Pidentifier],
+    read: Option<Pidentifier>,
+}
+
+impl Visitor for FirstRead<'_, '_> {
+    fn visit_pidentifier(&mut self, pid: &Pidentifier) {
+        if self.read.is_none() && self.names.contains(&pid) {
+            self.read = Some(pid.clone());
+        }
+    }
+}
+
+// fail if any of the unassigned OUT parameters is read by the visited part of a command
+fn check_unassigned_read(unassigned: &[&Pidentifier], location: Location, visit: impl FnOnce(&mut FirstRead)) -> Result<(), TranslationError> {
+    let mut first_read = FirstRead{names: unassigned, read: None};
+    visit(&mut first_read);
+    return match first_read.read {
+        Some(pid) => Err(TranslationError::UninitialisedVariable(location, pid)),
+        None => Ok(()),
+    };
+}
+
+// follow the commands in the order of execution, making sure no OUT parameter is read before it
+// is assigned on every path leading to the read; unassigned holds the ones not assigned yet
+fn check_out_parameters<'a>(commands: &'a Commands, unassigned: &mut Vec<&'a Pidentifier>, procedures: &[Procedure]) -> Result<(), TranslationError> {
     for command in commands {
         match command {
-            Command::ProcedureCall(proc_call, location) => {
+            Command::Assignment(id, _, location) | Command::Read(id, location) => {
 
-                // check if the call is assigned to an allowed procedure
+                // the index of the target and the assigned expression are read first
 
-                match function_calls.get_mut(&proc_call.name) {
-                    Some(no_proc_calls) => *no_proc_calls += 1, // increase the count of calls for the appropriate procedure
-                    None => return Err(TranslationError::NoSuchProcedure(*location, proc_call.name.clone())),
+                if let Identifier::ArrPid(_, idx) = id {
+                    check_unassigned_read(unassigned, *location, |reads| reads.visit_pidentifier(idx))?;
+                }
+                if let Command::Assignment(_, expr, _) = command {
+                    check_unassigned_read(unassigned, *location, |reads| reads.visit_expression(expr))?;
+                }
+                if let Identifier::Pid(pid) = id {
+                    unassigned.retain(|&param| param != pid);
                 }
             },
-            Command::IfElse(_, if_commands, else_commands, _) => {
-
-                // recursively check the commands inside the if block
-                
-                check_calls(if_commands, function_calls)?;
-                
-                // recursively check the commands inside the else block
-                
-                check_calls(else_commands, function_calls)?;
+            Command::Write(value, location) => {
+                check_unassigned_read(unassigned, *location, |reads| reads.visit_value(value))?;
+            },
+            Command::ProcedureCall(proc_call, location) => {
+
+                // the arguments of OUT parameters are assigned by the call, all the others are read by it
+
+                let Some(procedure) = procedures.iter().find(|&procedure| procedure.proc_head.name == proc_call.name) else {
+                    continue;
+                };
+                let mut assigned = Vec::new();
+                for (arg, arg_decl) in zip(&proc_call.args, &procedure.proc_head.args_decl) {
+                    match (arg, arg_decl) {
+                        (Value::Id(Identifier::Pid(pid)), ArgumentDeclaration::Var(_, ParameterMode::Out, _)) => assigned.push(pid),
+                        _ => check_unassigned_read(unassigned, *location, |reads| reads.visit_value(arg))?,
+                    }
+                }
+                unassigned.retain(|param| !assigned.contains(param));
             },
-            Command::If(_, commands, _) => {
-                
-                // recursively check the commands inside the if block
-                
-                check_calls(commands, function_calls)?;
+            Command::If(condition, commands, location) | Command::While(condition, commands, location) => {
+
+                // the commands might not be executed, so whatever they assign stays unassigned afterwards
+
+                check_unassigned_read(unassigned, *location, |reads| reads.visit_condition(condition))?;
+                check_out_parameters(commands, &mut unassigned.clone(), procedures)?;
             },
-            Command::While(_, commands, _) => {
-                
-                // recursively check the commands inside the while block
-                
-                check_calls(commands, function_calls)?;
+            Command::IfElse(condition, if_commands, else_commands, location) => {
+
+                // a parameter is assigned after the branches only if both of them assign it
+
+                check_unassigned_read(unassigned, *location, |reads| reads.visit_condition(condition))?;
+                let mut if_unassigned = unassigned.clone();
+                check_out_parameters(if_commands, &mut if_unassigned, procedures)?;
+                check_out_parameters(else_commands, unassigned, procedures)?;
+                for param in if_unassigned {
+                    if !unassigned.contains(&param) {
+                        unassigned.push(param);
+                    }
+                }
             },
-            Command::Repeat(commands, _, _) => {
-                
-                // recursively check the commands inside the repeat block
-                
-                check_calls(commands, function_calls)?;
+            Command::Repeat(commands, condition, location) => {
+
+                // the commands are executed at least once, before the condition
+
+                check_out_parameters(commands, unassigned, procedures)?;
+                check_unassigned_read(unassigned, *location, |reads| reads.visit_condition(condition))?;
             },
-            _ => {}, // ignore any other commands
         }
     }
 
     Ok(())
 }
 
-// count the number of times each procedure is called in the source code
-fn count_calls(ast: &ProgramAll) -> Result<FunctionCallTable, TranslationError> {
+// gather the constants declared in the source code
+fn constant_table(constants: &Constants) -> Result<ConstantTable, TranslationError> {
+    let mut constant_table = ConstantTable::new();
 
-    let mut function_calls = FunctionCallTable::new();
+    for constant in constants {
+        if constant_table.insert(constant.name.clone(), constant.value.clone()).is_some() {
+            return Err(TranslationError::RepeatedDeclaration(constant.location, constant.name.clone()));
+        }
+    }
 
-    // scan all procedures for calls
+    return Ok(constant_table);
+}
 
-    for procedure in &ast.procedures {
+// check that the names of constants and global variables aren't declared again by the procedures or main
+fn check_global_names(ast: &ProgramAll, constants: &ConstantTable) -> Result<(), TranslationError> {
+    let global_names: Vec<&Pidentifier> = ast.globals.declarations.iter().map(|decl| {
+        let (Declaration::Var(pid, _) | Declaration::Arr(pid, _, _)) = decl;
+        pid
+    }).collect();
 
-        // add the procedure to the function call table
+    // the global variables themselves can't be constants
 
-        function_calls.insert(procedure.proc_head.name.clone(), 0);
+    for pid in &global_names {
+        if constants.contains_key(*pid) {
+            return Err(TranslationError::RepeatedDeclaration(ast.globals.location, (*pid).clone()));
+        }
+    }
 
-        // search its commands list for procedure calls
+    // check the parameters and declarations of each procedure...
 
-        check_calls(&procedure.commands, &mut function_calls)?;
+    for procedure in &ast.procedures {
+        let args_names = procedure.proc_head.args_decl.iter().map(|arg_decl| {
+            let (ArgumentDeclaration::Var(pid, _, _) | ArgumentDeclaration::Arr(pid, _, _)) = arg_decl;
+            pid
+        });
+        let decls_names = procedure.declarations.iter().map(|decl| {
+            let (Declaration::Var(pid, _) | Declaration::Arr(pid, _, _)) = decl;
+            pid
+        });
+        for pid in args_names.chain(decls_names) {
+            if constants.contains_key(pid) || global_names.contains(&pid) {
+                return Err(TranslationError::RepeatedDeclaration(procedure.location, pid.clone()));
+            }
+        }
     }
 
-    // scan main for calls
-    
-    check_calls(&ast.main.commands, &mut function_calls)?;
+    // ...and of main
 
-    return Ok(function_calls);
+    for decl in &ast.main.declarations {
+        let (Declaration::Var(pid, _) | Declaration::Arr(pid, _, _)) = decl;
+        if constants.contains_key(pid) || global_names.contains(&pid) {
+            return Err(TranslationError::RepeatedDeclaration(ast.main.location, pid.clone()));
+        }
+    }
+
+    Ok(())
 }
 
-fn total_commands_count(commands: &Commands) -> usize {
-    let mut commands_remaining = commands.clone();
-    let mut total_commands = 0;
+// replaces all uses of the constants with their values
+struct ConstantSubstitution<'a> {
+    constants: &'a ConstantTable,
+    location: Location, // of the command being visited
+    result: Result<(), TranslationError>,
+}
 
-    while !commands_remaining.is_empty() {
-        total_commands += 1;
-        let mut curr_command = commands_remaining.pop().unwrap();
-        match curr_command {
-            Command::IfElse(_, ref mut if_commands, ref mut else_commands, _) => {
-                commands_remaining.append(if_commands);
-                commands_remaining.append(else_commands);
-            },
-            Command::If(_, ref mut if_commands, _) => {
-                commands_remaining.append(if_commands);
+impl ConstantSubstitution<'_> {
+    fn fail(&mut self, e: TranslationError) {
+        if self.result.is_ok() {
+            self.result = Err(e);
+        }
+    }
+}
+
+impl VisitorMut for ConstantSubstitution<'_> {
+    fn visit_command_mut(&mut self, command: &mut Command) {
+        let outer_location = std::mem::replace(&mut self.location, command.location());
+        walk_command_mut(self, command);
+        self.location = outer_location;
+    }
+
+    // a variable naming a constant becomes its value
+    fn visit_value_mut(&mut self, value: &mut Value) {
+        if let Value::Id(Identifier::Pid(pid)) = value {
+            if let Some(constant) = self.constants.get(pid) {
+                *value = constant.clone();
+                return;
+            }
+        }
+        walk_value_mut(self, value);
+    }
+
+    // substitute constants used as array indices; the Identifier itself can't name a constant
+    fn visit_identifier_mut(&mut self, id: &mut Identifier) {
+        match id {
+            Identifier::Pid(pid) => {
+                if self.constants.contains_key(pid) {
+                    self.fail(TranslationError::ConstantModified(self.location, pid.clone()));
+                }
             },
-            Command::While(_, ref mut while_commands, _) => {
-                commands_remaining.append(while_commands);
+            Identifier::ArrNum(arrpid, _) => {
+                if self.constants.contains_key(arrpid) {
+                    self.fail(TranslationError::NotAnArray(self.location, arrpid.clone()));
+                }
             },
-            Command::Repeat(ref mut repeat_commands, _, _) => {
-                commands_remaining.append(repeat_commands);
+            Identifier::ArrPid(arrpid, idxpid) => {
+                if self.constants.contains_key(arrpid) {
+                    self.fail(TranslationError::NotAnArray(self.location, arrpid.clone()));
+                }
+                match self.constants.get(idxpid) {
+                    Some(Value::Number(num)) => *id = Identifier::ArrNum(arrpid.clone(), num.clone()),
+                    Some(Value::Negative(num)) => self.fail(TranslationError::NegativeNumber(self.location, num.clone())),
+                    _ => {},
+                }
             },
-            _ => {},
         }
     }
+}
 
-    return total_commands;
+// replace all uses of the constants with their values
+fn substitute_constants(commands: &mut Commands, constants: &ConstantTable) -> Result<(), TranslationError> {
+    let mut substitution = ConstantSubstitution{constants, location: (0, 0), result: Ok(())};
+    substitution.visit_commands_mut(commands);
+    return substitution.result;
 }
 
-fn replace_id(id: &mut Identifier, from: &Pidentifier, to: &Pidentifier) {
-    match id {
-        Identifier::Pid(pid) => {
-            if pid == from {
-                *id = Identifier::Pid(to.clone());
-            }
-        },
-        Identifier::ArrNum(pid, num) => {
-            if pid == from {
-                *id = Identifier::ArrNum(to.clone(), *num);
-            }
-        },
-        Identifier::ArrPid(arrpid, numpid) => {
-            let num_copy = numpid.clone();
-            let mut arr_copy = arrpid.clone();
-            if arr_copy == *from {
-                *id = Identifier::ArrPid(to.clone(), num_copy.clone());
-                arr_copy = to.clone();
-            }
-            if num_copy == *from {
-                *id = Identifier::ArrPid(arr_copy.clone(), to.clone());
-            }
-        }
+// calculate the value of the Expression at compile time if both of its operands are numbers
+fn fold_expr(expr: &mut Expression) {
+    let folded = match expr {
+        Expression::Add(Value::Number(lhs), Value::Number(rhs)) => Some(&*lhs + &*rhs),
+        Expression::Sub(Value::Number(lhs), Value::Number(rhs)) => Some(if lhs > rhs { &*lhs - &*rhs } else { Num::zero() }),
+        Expression::Mul(Value::Number(lhs), Value::Number(rhs)) => Some(&*lhs * &*rhs),
+        Expression::Div(Value::Number(lhs), Value::Number(rhs)) => Some(if rhs.is_zero() { Num::zero() } else { &*lhs / &*rhs }),
+        Expression::Mod(Value::Number(lhs), Value::Number(rhs)) => Some(if rhs.is_zero() { Num::zero() } else { &*lhs % &*rhs }),
+        _ => None,
+    };
+
+    if let Some(num) = folded {
+        *expr = Expression::Val(Value::Number(num));
     }
 }
 
-fn replace_value(value: &mut Value, from: &Pidentifier, to: &Pidentifier) {
-    if let Value::Id(id) = value {
-        replace_id(id, from, to);
+// calculate the value of the Condition at compile time if both of its operands are numbers
+fn fold_condition(condition: &Condition) -> Option<bool> {
+    match condition {
+        Condition::Equal(Value::Number(lhs), Value::Number(rhs)) => Some(lhs == rhs),
+        Condition::NotEqual(Value::Number(lhs), Value::Number(rhs)) => Some(lhs != rhs),
+        Condition::Greater(Value::Number(lhs), Value::Number(rhs)) => Some(lhs > rhs),
+        Condition::Lesser(Value::Number(lhs), Value::Number(rhs)) => Some(lhs < rhs),
+        Condition::GreaterOrEqual(Value::Number(lhs), Value::Number(rhs)) => Some(lhs >= rhs),
+        Condition::LesserOrEqual(Value::Number(lhs), Value::Number(rhs)) => Some(lhs <= rhs),
+        _ => None,
     }
 }
 
-fn replace_expr(expr: &mut Expression, from: &Pidentifier, to: &Pidentifier) {
-    match expr {
-        Expression::Val(value) => {
-            replace_value(value, from, to);
-        },
-        Expression::Add(lhs, rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        }
-        Expression::Sub(lhs, rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        }
-        Expression::Mul(lhs, rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        }
-        Expression::Div(lhs, rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        }
-        Expression::Mod(lhs, rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
+// evaluates the constant Expressions and removes the branches that can never be executed
+struct ConstantFolder;
+
+impl VisitorMut for ConstantFolder {
+    fn visit_commands_mut(&mut self, commands: &mut Commands) {
+        for mut command in std::mem::take(commands) {
+
+            // the commands inside are folded first, so that a removed branch takes them as they are
+
+            self.visit_command_mut(&mut command);
+            match command {
+                Command::IfElse(condition, mut if_commands, mut else_commands, location) => match fold_condition(&condition) {
+                    Some(true) => commands.append(&mut if_commands),
+                    Some(false) => commands.append(&mut else_commands),
+                    None => commands.push(Command::IfElse(condition, if_commands, else_commands, location)),
+                },
+                Command::If(condition, mut if_commands, location) => match fold_condition(&condition) {
+                    Some(true) => commands.append(&mut if_commands),
+                    Some(false) => {},
+                    None => commands.push(Command::If(condition, if_commands, location)),
+                },
+                Command::While(condition, while_commands, location) => {
+                    if fold_condition(&condition) != Some(false) {
+                        commands.push(Command::While(condition, while_commands, location));
+                    }
+                },
+                Command::Repeat(mut repeat_commands, condition, location) => {
+                    if fold_condition(&condition) == Some(true) {
+                        commands.append(&mut repeat_commands);
+                    } else {
+                        commands.push(Command::Repeat(repeat_commands, condition, location));
+                    }
+                },
+                _ => commands.push(command),
+            }
         }
     }
+
+    fn visit_expression_mut(&mut self, expr: &mut Expression) {
+        fold_expr(expr);
+    }
 }
 
-fn replace_condition(condition: &mut Condition, from: &Pidentifier, to: &Pidentifier) {
-    match condition {
-        Condition::Equal(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
-        Condition::NotEqual(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
-        Condition::Greater(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
-        Condition::Lesser(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
-        Condition::GreaterOrEqual(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
-        Condition::LesserOrEqual(ref mut lhs, ref mut rhs) => {
-            replace_value(lhs, from, to);
-            replace_value(rhs, from, to);
-        },
+// evaluate the constant Expressions and remove the branches that can never be executed
+fn fold_constants(commands: &mut Commands) {
+    ConstantFolder.visit_commands_mut(commands);
+}
+
+// count the number of times each procedure is called in the source code
+fn count_calls(ast: &ProgramAll) -> Result<FunctionCallTable, TranslationError> {
+
+    let mut function_calls = FunctionCallTable::new();
+
+    // scan all procedures for calls
+
+    for procedure in &ast.procedures {
+
+        // add the procedure to the function call table
+
+        function_calls.insert(procedure.proc_head.name.clone(), 0);
+
+        // search its commands list for procedure calls
+
+        check_calls(&procedure.commands, &mut function_calls)?;
     }
+
+    // scan main for calls
+    
+    check_calls(&ast.main.commands, &mut function_calls)?;
+
+    return Ok(function_calls);
 }
 
-fn replace_proc_call(proc_call: &mut ProcCall, from: &Pidentifier, to: &Pidentifier) {
-    for arg_name in proc_call.args.iter_mut() {
-        if arg_name == from {
-            *arg_name = to.clone();
+// counts the commands, the nested ones included
+struct CommandCounter(usize);
+
+impl Visitor for CommandCounter {
+    fn visit_command(&mut self, command: &Command) {
+        self.0 += 1;
+        walk_command(self, command);
+    }
+}
+
+fn total_commands_count(commands: &[Command]) -> usize {
+    let mut counter = CommandCounter(0);
+    counter.visit_commands(commands);
+    return counter.0;
+}
+
+// renames a variable wherever it is used
+struct Renamer<'a> {
+    from: &'a Pidentifier,
+    to: &'a Pidentifier,
+}
+
+impl VisitorMut for Renamer<'_> {
+    fn visit_pidentifier_mut(&mut self, pid: &mut Pidentifier) {
+        if pid == self.from {
+            *pid = self.to.clone();
         }
     }
 }
 
 // replace all procedure parameters with the arguments provided in the call 
-fn replace_parameters(dest_procedure: &mut Procedure, curr_proc_call_args: &Arguments) {
+fn replace_parameters(dest_procedure: &mut Procedure, curr_proc_call_args: &[Pidentifier]) {
     for (dest_args_decl, curr_arg) in zip(&mut dest_procedure.proc_head.args_decl, curr_proc_call_args) {
         match dest_args_decl {
-            ArgumentDeclaration::Var(dest_arg) => {
-                replace(&mut dest_procedure.commands, dest_arg, &curr_arg);
-                *dest_args_decl = ArgumentDeclaration::Var(curr_arg.clone());
+            ArgumentDeclaration::Var(dest_arg, mode, location) => {
+                replace(&mut dest_procedure.commands, dest_arg, curr_arg);
+                *dest_args_decl = ArgumentDeclaration::Var(curr_arg.clone(), *mode, *location);
             },
-            ArgumentDeclaration::Arr(dest_arg) => {
-                replace(&mut dest_procedure.commands, dest_arg, &curr_arg);
-                *dest_args_decl = ArgumentDeclaration::Arr(curr_arg.clone());
+            ArgumentDeclaration::Arr(dest_arg, mode, location) => {
+                replace(&mut dest_procedure.commands, dest_arg, curr_arg);
+                *dest_args_decl = ArgumentDeclaration::Arr(curr_arg.clone(), *mode, *location);
             },
         }
     }
 }
 
+// turn the IN variable parameters into declarations of the procedure, since they are passed by value;
+// returns the remaining (reference) arguments and the indices of the new declarations with their initial values
+fn localise_in_parameters(dest_procedure: &mut Procedure, curr_proc_call_args: &Arguments, location: Location) -> Result<(Vec<Pidentifier>, ByValueArguments), TranslationError> {
+    let mut ref_args = Vec::new();
+    let mut value_args = Vec::new();
+
+    for (dest_args_decl, curr_arg) in zip(std::mem::take(&mut dest_procedure.proc_head.args_decl), curr_proc_call_args) {
+        match (dest_args_decl, curr_arg) {
+            (ArgumentDeclaration::Var(dest_arg, ParameterMode::In, decl_location), _) => {
+                value_args.push((dest_procedure.declarations.len(), curr_arg.clone()));
+                dest_procedure.declarations.push(Declaration::Var(dest_arg, decl_location));
+            },
+            (dest_args_decl, Value::Id(Identifier::Pid(curr_arg))) => {
+                ref_args.push(curr_arg.clone());
+                dest_procedure.proc_head.args_decl.push(dest_args_decl);
+            },
+            (ArgumentDeclaration::Var(dest_arg, _, _) | ArgumentDeclaration::Arr(dest_arg, _, _), _) => {
+                return Err(TranslationError::ReferenceExpected(location, dest_arg));
+            },
+        }
+    }
+
+    return Ok((ref_args, value_args));
+}
+
 // rename all procedure declarations if they conflict with the caller's
-fn replace_declarations(dest_procedure: &mut Procedure, curr_proc_args_decls: Option<&ArgumentDeclarations>, curr_proc_decls: &mut Declarations) {
+fn replace_declarations(dest_procedure: &mut Procedure, curr_proc_args_decls: Option<&ArgumentDeclarations>, curr_proc_decls: &mut Declarations, global_decls: &Declarations) {
+
+    // the new names can't repeat any of the procedure's own names either
+
+    let mut dest_pids: Vec<Pidentifier> = dest_procedure.proc_head.args_decl.iter().map(|decl| {
+        let (ArgumentDeclaration::Var(pid, _, _) | ArgumentDeclaration::Arr(pid, _, _)) = decl;
+        pid.clone()
+    }).chain(dest_procedure.declarations.iter().map(|decl| {
+        let (Declaration::Var(pid, _) | Declaration::Arr(pid, _, _)) = decl;
+        pid.clone()
+    })).collect();
+
     for dest_arg in dest_procedure.declarations.iter_mut() {
 
         // extract the current declaration variable name
 
         let original_dest_pid = match dest_arg {
-            Declaration::Var(pid) => {
-                pid
+            Declaration::Var(pid, _) => {
+                pid.clone()
             },
-            Declaration::Arr(pid, _) => {
-                pid
+            Declaration::Arr(pid, _, _) => {
+                pid.clone()
             },
         };
         let mut new_dest_pid = original_dest_pid.clone();
 
-        // check caller's argument declarations, if any
-
-        if let Some(args_decls) = curr_proc_args_decls {
-            loop {
-                if args_decls.iter().find(|decl| {
-                    match decl {
-                        ArgumentDeclaration::Var(arg_pid) => {
-                            new_dest_pid == *arg_pid
-                        },
-                        ArgumentDeclaration::Arr(arg_pid) => {
-                            new_dest_pid == *arg_pid
-                        },
-                    }
-                }).is_some() {
-                    new_dest_pid.insert_str(0, "_");
-                } else {
-                    break;
-                }
-            }
-        }
-
-        // check the caller's declarations
+        // check the caller's argument declarations, if any, the caller's declarations,
+        // the global variables and the procedure's other names
 
         loop {
-            if curr_proc_decls.iter().find(|decl| {
+            let in_args_decls = curr_proc_args_decls.is_some_and(|args_decls| args_decls.iter().any(|decl| {
                 match decl {
-                    Declaration::Var(arg_pid) => {
+                    ArgumentDeclaration::Var(arg_pid, _, _) => {
                         new_dest_pid == *arg_pid
                     },
-                    Declaration::Arr(arg_pid, _) => {
+                    ArgumentDeclaration::Arr(arg_pid, _, _) => {
                         new_dest_pid == *arg_pid
                     },
                 }
-            }).is_some() {
-                new_dest_pid.insert_str(0, "_");
+            }));
+            let in_decls = curr_proc_decls.iter().chain(global_decls).any(|decl| {
+                match decl {
+                    Declaration::Var(arg_pid, _) => {
+                        new_dest_pid == *arg_pid
+                    },
+                    Declaration::Arr(arg_pid, _, _) => {
+                        new_dest_pid == *arg_pid
+                    },
+                }
+            });
+            let in_dest_pids = new_dest_pid != original_dest_pid && dest_pids.contains(&new_dest_pid);
+
+            if in_args_decls || in_decls || in_dest_pids {
+                new_dest_pid.insert(0, '_');
             } else {
                 break;
             }
@@ -277,16 +550,17 @@ fn replace_declarations(dest_procedure: &mut Procedure, curr_proc_args_decls: Op
 
         // replace the variable names in the procedure body and declarations if needed
 
-        if *original_dest_pid != new_dest_pid {
-            replace(&mut dest_procedure.commands, original_dest_pid, &new_dest_pid);
+        if original_dest_pid != new_dest_pid {
+            replace(&mut dest_procedure.commands, &original_dest_pid, &new_dest_pid);
             match dest_arg {
-                Declaration::Var(_) => {
-                    *dest_arg = Declaration::Var(new_dest_pid);
+                Declaration::Var(_, location) => {
+                    *dest_arg = Declaration::Var(new_dest_pid.clone(), *location);
                 },
-                Declaration::Arr(_, _) => {
-                    *dest_arg = Declaration::Var(new_dest_pid);
+                Declaration::Arr(_, len, location) => {
+                    *dest_arg = Declaration::Arr(new_dest_pid.clone(), len.clone(), *location);
                 },
             };
+            dest_pids.push(new_dest_pid);
         }
     }
 }
@@ -294,182 +568,257 @@ fn replace_declarations(dest_procedure: &mut Procedure, curr_proc_args_decls: Op
 // TODO: check repeated declarations
 // replace all variable name usages with the other variable name
 fn replace(commands: &mut Commands, from: &Pidentifier, to: &Pidentifier) {
-    for command in commands.iter_mut() {
-        match command {
-            Command::Assignment(ref mut id, ref mut expr, _) => {
-                replace_id(id, from, to);
-                replace_expr(expr, from, to);
-            },
-            Command::IfElse(ref mut condition, ref mut if_commands, ref mut else_commands, _) => {
-                replace_condition(condition, from, to);
-                replace(if_commands, from, to);
-                replace(else_commands, from, to);
-            },
-            Command::If(ref mut condition, ref mut if_commands, _) => {
-                replace_condition(condition, from, to);
-                replace(if_commands, from, to);
-            },
-            Command::While(ref mut condition, ref mut while_commands, _) => {
-                replace_condition(condition, from, to);
-                replace(while_commands, from, to);
-            },
-            Command::Repeat(ref mut repeat_commands, ref mut condition, _) => {
-                replace(repeat_commands, from, to);
-                replace_condition(condition, from, to);
-            },
-            Command::ProcedureCall(ref mut proc_call, _) => {
-                replace_proc_call(proc_call, from, to);
-            },
-            Command::Read(ref mut id, _) => {
-                replace_id(id, from, to);
-            },
-            Command::Write(ref mut value, _) => {
-                replace_value(value, from, to);
-            },
-        }
-    }
+    Renamer{from, to}.visit_commands_mut(commands);
+}
+
+// expands the proc calls in the commands of a procedure or main which meet the required criteria,
+// numbering the commands as they are written to tell where the expanded regions are
+struct Expander<'a> {
+    procedures: &'a [Procedure], // the procedures that can be expanded, already expanded themselves
+    curr_proc_head: Option<&'a ProcHead>,
+    curr_proc_declarations: &'a mut Declarations,
+    global_decls: &'a Declarations,
+    function_calls: &'a FunctionCallTable,
+    inlined: &'a InlinedRegions, // the regions expanded into the procedures before
+    regions: Vec<InlinedRegion>,
+    command_number: usize,
+    result: Result<(), TranslationError>,
 }
 
-// expand all proc calls in the commands list which meet the required criteria
-fn expand_procedures(procedures: &[Procedure], curr_proc_head: Option<&ProcHead>, curr_proc_declarations: &mut Declarations, commands: &mut Commands, function_calls: &FunctionCallTable) -> Result<(), TranslationError> {
-    let mut proc_calls_replacements = Vec::new();
+impl Expander<'_> {
 
-    // search the commands list for proc calls that meet the criteria
+    // the commands replacing the proc call, if it meets the expansion criteria
+    fn expand(&mut self, proc_call: &ProcCall, location: Location) -> Result<Option<Commands>, TranslationError> {
 
-    for (command_idx, command) in commands.iter_mut().enumerate() {
-        match command {
-            Command::ProcedureCall(proc_call, location) => {
+        // check for recurrence
 
-                // check for recurrence
+        if let Some(proc_head) = self.curr_proc_head {
+            if proc_call.name == proc_head.name {
+                return Err(TranslationError::RecurrenceNotAllowed(location, proc_call.name.clone()));
+            }
+        }
 
-                if let Some(proc_head) = curr_proc_head {
-                    if proc_call.name == proc_head.name {
-                        return Err(TranslationError::RecurrenceNotAllowed(*location, proc_call.name.clone()));
-                    }
-                }
+        // find the procedure the call refers to...
+
+        let Some(procedure) = self.procedures.iter().find(|&procedure| procedure.proc_head.name == proc_call.name) else {
+            return Err(TranslationError::NoSuchProcedure(location, proc_call.name.clone()));
+        };
+
+        // ...and check if it meets the expansion criteria; a call stopping the program stays a call,
+        // as the halt follows the call itself
+
+        if proc_call.halts {
+            return Ok(None);
+        }
+
+        let calls_count = *self.function_calls.get(&procedure.proc_head.name).unwrap();
+
+        if calls_count != 1 && total_commands_count(&procedure.commands) * calls_count >= 20 {
+            return Ok(None);
+        }
+
+        if proc_call.args.len() != procedure.proc_head.args_decl.len() {
+            return Err(TranslationError::InvalidNumberOfArguments(location, proc_call.name.clone()));
+        }
 
-                // find the procedure the call refers to...
+        trace!(Category::Inline, "inline {} ({} calls) into {}", proc_call.name, calls_count, self.curr_proc_head.map_or("main", |head| &head.name));
 
-                match procedures.iter().find(|&procedure| procedure.proc_head.name == proc_call.name) {
-                    Some(procedure) => {
+        // create a copy of the destination procedure and then modify its body
 
-                        // ...and check if it meets the expansion criteria
+        let mut dest_proc = procedure.clone();
 
-                        let calls_count = *function_calls.get(&procedure.proc_head.name).unwrap();
+        // IN parameters become ordinary declarations initialised with the arguments' values
 
-                        if calls_count == 1 || total_commands_count(&procedure.commands) * calls_count < 20 {
+        let (ref_args, value_args) = localise_in_parameters(&mut dest_proc, &proc_call.args, location)?;
 
-                            // create a copy of the destination procedure and then modify its body
+        // rename procedure declarations when needed to avoid conflicts
 
-                            let mut dest_proc = procedure.clone();
+        replace_declarations(&mut dest_proc, self.curr_proc_head.map(|head| &head.args_decl), self.curr_proc_declarations, self.global_decls);
 
-                            // rename procedure declarations when needed to avoid conflicts
+        // replace all uses of argument parameters with the call variables
 
-                            replace_declarations(&mut dest_proc, curr_proc_head.map_or(None, |head| Some(&head.args_decl)), curr_proc_declarations);
+        replace_parameters(&mut dest_proc, &ref_args.iter().map(|arg| arg.to_owned() + "'").collect::<Vec<_>>());
 
-                            // replace all uses of argument parameters with the call variables
+        // remove the chenge marks from the replaced names
 
-                            replace_parameters(&mut dest_proc, &proc_call.args.iter().map(|arg| arg.to_owned() + "'").collect());
+        replace_parameters(&mut dest_proc, &ref_args);
 
-                            // remove the chenge marks from the replaced names
+        // copy the values of the by-value arguments before the procedure body
 
-                            replace_parameters(&mut dest_proc, &proc_call.args);
+        let mut inlined_commands: Commands = value_args.into_iter().map(|(decl_idx, arg)| {
+            let (Declaration::Var(pid, _) | Declaration::Arr(pid, _, _)) = &dest_proc.declarations[decl_idx];
+            Command::Assignment(Identifier::Pid(pid.clone()), Expression::Val(arg), location)
+        }).collect();
+        let value_args_count = inlined_commands.len();
+        inlined_commands.append(&mut dest_proc.commands);
 
-                            // store the procedure body for later expansion
+        // the whole expansion is a region, and so are the ones expanded into the procedure before
 
-                            proc_calls_replacements.push((command_idx, dest_proc.commands));
+        self.regions.push(InlinedRegion{procedure: proc_call.name.clone(), start: self.command_number, len: total_commands_count(&inlined_commands)});
+        if let Some(proc_regions) = self.inlined.get(&Some(proc_call.name.clone())) {
+            let start = self.command_number + value_args_count;
+            self.regions.extend(proc_regions.iter().map(|region| InlinedRegion{procedure: region.procedure.clone(), start: start + region.start, len: region.len}));
+        }
+
+        // copy the procedure declarations into the caller
+
+        self.curr_proc_declarations.append(&mut dest_proc.declarations);
 
-                            // copy the procedure declarations into the caller
+        return Ok(Some(inlined_commands));
+    }
+}
 
-                            curr_proc_declarations.append(&mut dest_proc.declarations);
-                        }
+impl VisitorMut for Expander<'_> {
+    fn visit_commands_mut(&mut self, commands: &mut Commands) {
+        for mut command in std::mem::take(commands) {
+
+            // the expanded procedure bodies take the place of the calls, as they are
+
+            if let (Command::ProcedureCall(proc_call, location), Ok(())) = (&command, &self.result) {
+                match self.expand(proc_call, *location) {
+                    Ok(Some(mut inlined_commands)) => {
+                        self.command_number += total_commands_count(&inlined_commands);
+                        commands.append(&mut inlined_commands);
+                        continue;
                     },
-                    None => return Err(TranslationError::NoSuchProcedure(*location, proc_call.name.clone())),
+                    Ok(None) => {},
+                    Err(e) => self.result = Err(e),
                 }
-            },
-            Command::IfElse(_, ref mut if_commands, ref mut else_commands, _) => {
-                
-                // recursively check the commands inside the if block
-                
-                expand_procedures(procedures, curr_proc_head, curr_proc_declarations, if_commands, function_calls)?;
-                
-                // recursively check the commands inside the else block
-                
-                expand_procedures(procedures, curr_proc_head, curr_proc_declarations, else_commands, function_calls)?;
-            },
-            Command::If(_, ref mut commands, _) => {
-                
-                // recursively check the commands inside the if block
-                
-                expand_procedures(procedures, curr_proc_head, curr_proc_declarations, commands, function_calls)?;
-            },
-            Command::While(_, ref mut commands, _) => {
-                
-                // recursively check the commands inside the while block
-                
-                expand_procedures(procedures, curr_proc_head, curr_proc_declarations, commands, function_calls)?;
-            },
-            Command::Repeat(ref mut commands, _, _) => {
-                
-                // recursively check the commands inside the repeat block
-                
-                expand_procedures(procedures, curr_proc_head, curr_proc_declarations, commands, function_calls)?;
-            },
-            _ => {},
+            }
+
+            // the other commands are searched for the calls inside them
+
+            self.visit_command_mut(&mut command);
+            commands.push(command);
         }
     }
 
-    // expand the encountered proc calls' bodies
-    
-    let mut offset = 0;
-    for (command_idx, proc_commands) in proc_calls_replacements {
-        let next_offset = proc_commands.len() - 1;
-        commands.splice(command_idx+offset..=command_idx+offset, proc_commands);
-        offset += next_offset;
+    fn visit_command_mut(&mut self, command: &mut Command) {
+        self.command_number += 1;
+        walk_command_mut(self, command);
     }
-    //println!("PROCEDURE: {:?}", curr_proc_head.map_or("Main", |head| &head.name));
-    //println!("curr_proc_decl: {:?}", curr_proc_declarations);
-    //println!("curr_proc_commands: {:?}", commands);
+}
 
-    Ok(())
+// expand all proc calls in the commands list which meet the required criteria;
+// returns the expanded regions of the list, given the ones already found in the procedures
+fn expand_procedures(procedures: &[Procedure], curr_proc_head: Option<&ProcHead>, curr_proc_declarations: &mut Declarations, global_decls: &Declarations, commands: &mut Commands, function_calls: &FunctionCallTable, inlined: &InlinedRegions) -> Result<Vec<InlinedRegion>, TranslationError> {
+    let mut expander = Expander{procedures, curr_proc_head, curr_proc_declarations, global_decls, function_calls, inlined, regions: Vec::new(), command_number: 0, result: Ok(())};
+    expander.visit_commands_mut(commands);
+    expander.result?;
+    return Ok(expander.regions);
 }
 
-// expand all source code proc calls which meet the required criteria
-fn expand_procedures_all(ast: &mut ProgramAll, function_calls: &FunctionCallTable) -> Result<(), TranslationError> {
+fn remove_uncalled(ast: &mut ProgramAll, function_calls: &FunctionCallTable) {
+    ast.procedures.retain(|procedure| {
+        let called = *function_calls.get(&procedure.proc_head.name).unwrap() > 0;
+        if !called {
+            trace!(Category::Inline, "remove {}, it is no longer called", procedure.proc_head.name);
+        }
+        called
+    });
+}
+
+// expand all source code proc calls which meet the required criteria, noting where the expanded commands are
+fn expand_procedures_all(ast: &mut ProgramAll, function_calls: &FunctionCallTable, inlined: &mut InlinedRegions) -> Result<(), TranslationError> {
 
     // expand calls inside each procedure
 
     for idx in 0..ast.procedures.len() {
         let (prev_procedures, remaining_procedures) = ast.procedures.split_at_mut(idx);
-        let Procedure{proc_head: ref procedure_head, declarations: ref mut procedure_declarations, commands: ref mut procedure_commands, location: _} = &mut remaining_procedures[0];
+        let Procedure{proc_head: ref procedure_head, declarations: ref mut procedure_declarations, commands: ref mut procedure_commands, location: _, doc: _} = &mut remaining_procedures[0];
 
-        expand_procedures(&prev_procedures, Some(procedure_head), procedure_declarations, procedure_commands, &function_calls)?;
+        let regions = expand_procedures(prev_procedures, Some(procedure_head), procedure_declarations, &ast.globals.declarations, procedure_commands, function_calls, inlined)?;
+        inlined.insert(Some(procedure_head.name.clone()), regions);
     }
 
     // expand calls inside main
 
-    expand_procedures(&ast.procedures, None, &mut ast.main.declarations, &mut ast.main.commands, &function_calls)?;
+    let regions = expand_procedures(&ast.procedures, None, &mut ast.main.declarations, &ast.globals.declarations, &mut ast.main.commands, function_calls, inlined)?;
+    inlined.insert(None, regions);
 
     Ok(())
 }
 
-// transform the source code's AST for more effective compilation
-pub fn transform(ast: &mut ProgramAll) -> Result<(), TranslationError> {
+// run a single optimisation on the AST
+fn run_pass(ast: &mut ProgramAll, pass: Pass, inlined: &mut InlinedRegions) -> Result<(), TranslationError> {
+    match pass {
+        Pass::ConstantFolding => {
+            for procedure in ast.procedures.iter_mut() {
+                fold_constants(&mut procedure.commands);
+            }
+            fold_constants(&mut ast.main.commands);
+        },
+        Pass::DeadProcedures => {
+            let function_calls = count_calls(ast)?;
+            remove_uncalled(ast, &function_calls);
+        },
+        Pass::Inline => {
+            let function_calls = count_calls(ast)?;
+            expand_procedures_all(ast, &function_calls, inlined)?;
+        },
+        Pass::RegisterCaching => {}, // done by the code generation
+    }
 
-    // count the number of times each procedure is called
+    Ok(())
+}
+
+// check the program and transform the source code's AST for more effective compilation with the enabled passes
+pub fn transform(ast: &mut ProgramAll, passes: &PassManager) -> Result<(), TranslationError> {
+    transform_with_regions(ast, passes)?;
+    Ok(())
+}
 
-    let function_calls = count_calls(ast)?;
+// transform the program, telling which of its commands come from the expanded procedures
+pub fn transform_with_regions(ast: &mut ProgramAll, passes: &PassManager) -> Result<InlinedRegions, TranslationError> {
 
-    // remove procedures that are never called
+    // make sure the names of constants and global variables are unique
 
-    ast.procedures.retain(|procedure| *function_calls.get(&procedure.proc_head.name).unwrap() > 0);
+    let constants = constant_table(&ast.constants)?;
+    check_global_names(ast, &constants)?;
 
-    // expand procedures which are called only once
+    // replace all uses of the constants with their values
 
-    expand_procedures_all(ast, &function_calls)?;
-    
-    let function_calls = count_calls(ast)?;
-    ast.procedures.retain(|procedure| *function_calls.get(&procedure.proc_head.name).unwrap() > 0);
+    for procedure in ast.procedures.iter_mut() {
+        substitute_constants(&mut procedure.commands, &constants)?;
+    }
+    substitute_constants(&mut ast.main.commands, &constants)?;
 
-    Ok(())
+    // make sure the IN parameters are never modified
+
+    for procedure in &ast.procedures {
+        let in_params: Vec<&Pidentifier> = procedure.proc_head.args_decl.iter().filter_map(|arg_decl| match arg_decl {
+            ArgumentDeclaration::Var(pid, ParameterMode::In, _) | ArgumentDeclaration::Arr(pid, ParameterMode::In, _) => Some(pid),
+            _ => None,
+        }).collect();
+        check_in_parameters(&procedure.commands, &in_params, &ast.procedures)?;
+    }
+
+    // make sure the OUT parameters are never read before something is stored in them
+
+    for procedure in &ast.procedures {
+        let mut unassigned: Vec<&Pidentifier> = procedure.proc_head.args_decl.iter().filter_map(|arg_decl| match arg_decl {
+            ArgumentDeclaration::Var(pid, ParameterMode::Out, _) => Some(pid),
+            _ => None,
+        }).collect();
+        check_out_parameters(&procedure.commands, &mut unassigned, &ast.procedures)?;
+    }
+
+    // make sure every called procedure exists
+
+    count_calls(ast)?;
+
+    // optimise the program
+
+    let mut inlined = InlinedRegions::new();
+    for pass in passes.enabled() {
+        run_pass(ast, pass, &mut inlined)?;
+    }
+
+    // the inlining leaves the expanded procedures uncalled, to be dropped with the other dead ones
+
+    if passes.is_enabled(Pass::Inline) && passes.is_enabled(Pass::DeadProcedures) {
+        run_pass(ast, Pass::DeadProcedures, &mut inlined)?;
+    }
+
+    Ok(inlined)
 }
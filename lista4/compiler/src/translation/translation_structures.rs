@@ -1,7 +1,10 @@
-use crate::ast::{ArgumentDeclarations, Location, Pidentifier, Identifier};
-use std::collections::HashMap;
+use crate::ast::{ArgumentDeclarations, Location, Num, Pidentifier, Identifier};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use num::BigInt;
 
+use crate::format::format_id;
+
 pub fn add_command(code: &mut Vec<String>, command: &str) {
     code.push(String::from(command));
 }
@@ -10,13 +13,80 @@ pub fn add_command_string(code: &mut Vec<String>, command: String) {
     code.push(command);
 }
 
-pub fn add_comment(code: &mut Vec<String>, comment: &str) {
-    if code.len() > 0 {
-        code[0] += " # ";
+// the annotations follow the instruction, each of them after the separator; the ones starting with
+// a marker are read by the listing, the rest are free-form notes for reading the raw code
+const ANNOTATION_SEPARATOR: &str = " # ";
+const LOCATION_MARKER: &str = "@src ";
+const LABEL_MARKER: &str = "@label ";
+const REGISTERS_MARKER: &str = "@regs ";
+
+pub fn add_comment(code: &mut [String], comment: &str) {
+    if !code.is_empty() {
+        code[0] += ANNOTATION_SEPARATOR;
         code[0] += comment;
     }
 }
 
+// name the sequence of code starting with its first line
+pub fn add_label(code: &mut [String], label: &str) {
+    add_comment(code, &(LABEL_MARKER.to_owned() + label));
+}
+
+// attribute the lines of code not attributed to any other command to the source location
+pub fn add_location(code: &mut [String], location: Location) {
+    let marker = ANNOTATION_SEPARATOR.to_owned() + LOCATION_MARKER;
+    for line in code.iter_mut() {
+        if !line.contains(&marker) {
+            *line += &format!("{}{} {}", marker, location.0, location.1);
+        }
+    }
+}
+
+// mark the first line of a command with what the compiler assumes about the registers there,
+// e.g. "@regs c = x, d = 5 (next: e)"
+pub fn add_assumed_registers(code: &mut [String], register_states: &RegisterStates) {
+    add_comment(code, &(REGISTERS_MARKER.to_owned() + &register_states.to_string()));
+}
+
+// a line of code split into the instruction and its annotations
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AnnotatedLine {
+    pub instruction: String,
+    pub location: Option<Location>,
+    pub labels: Vec<String>,
+    pub registers: Option<Vec<(String, String)>>, // the assumed contents of the registers, on the first line of a command
+}
+
+impl AnnotatedLine {
+    pub fn parse(line: &str) -> Self {
+        let mut parts = line.split(ANNOTATION_SEPARATOR);
+        let mut annotated = Self{instruction: parts.next().unwrap_or_default().to_owned(), ..Default::default()};
+        for part in parts {
+            if let Some(location) = part.strip_prefix(LOCATION_MARKER) {
+                let bytes: Vec<usize> = location.split(' ').filter_map(|byte| byte.parse().ok()).collect();
+                if let [start, end] = bytes[..] {
+                    annotated.location = Some((start, end));
+                }
+            } else if let Some(label) = part.strip_prefix(LABEL_MARKER) {
+                annotated.labels.push(label.to_owned());
+            } else if let Some(registers) = part.strip_prefix(REGISTERS_MARKER) {
+
+                // the registers holding noise are left out, and so is the next one to be used
+
+                let known = registers.rsplit_once(" (next: ").map_or(registers, |(known, _)| known);
+                annotated.registers = Some(known.split(", ").filter_map(|register| register.split_once(" = "))
+                    .map(|(register, content)| (register.to_owned(), content.to_owned())).collect());
+            }
+        }
+        return annotated;
+    }
+}
+
+// the bare instructions, as read by the virtual machine
+pub fn strip_annotations(code: &[String]) -> Vec<String> {
+    return code.iter().map(|line| AnnotatedLine::parse(line).instruction).collect();
+}
+
 pub enum DivisionType {
     Division,
     Modulo,
@@ -45,6 +115,14 @@ pub enum RegisterState {
 pub struct RegisterStates {
     pub registers: HashMap<Register, RegisterState>,
     next: Register,
+    pub caching: bool, // whether the registers may keep their values between the commands
+    pub aliased: HashSet<Pidentifier>, // the variables that may share their memory, never kept in registers
+}
+
+impl Default for RegisterStates {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RegisterStates {
@@ -59,7 +137,7 @@ impl RegisterStates {
             (Register::G, RegisterState::Noise),
             (Register::H, RegisterState::Noise),
         ]);
-        return Self{registers: starting_states, next: Register::D};
+        return Self{registers: starting_states, next: Register::D, caching: true, aliased: HashSet::new()};
     }
 
     fn next_register(&self, register: &Register) -> Register {
@@ -88,6 +166,29 @@ impl RegisterStates {
         return current_register;
     }
 
+    // the next register in order, skipping the registers in use
+    pub fn get_next_excluding(&mut self, used: &[&Register]) -> Register {
+        loop {
+            let register = self.get_next();
+            if !used.contains(&&register) {
+                return register;
+            }
+        }
+    }
+
+    // forget the contents of all registers
+    pub fn clear(&mut self) {
+        for state in self.registers.values_mut() {
+            *state = RegisterState::Noise;
+        }
+    }
+
+    // whether the variable, or the array of the element, may share its memory with another one
+    pub fn is_aliased(&self, id: &Identifier) -> bool {
+        let (Identifier::Pid(pid) | Identifier::ArrNum(pid, _) | Identifier::ArrPid(pid, _)) = id;
+        return self.aliased.contains(pid);
+    }
+
     pub fn scan(&self, id: &Identifier) -> Option<Register> {
         for (register, state) in self.registers.iter() {
             if *state == RegisterState::Variable(id.clone()) && register != &Register::A && register != &Register::B {
@@ -98,7 +199,26 @@ impl RegisterStates {
     }
 }
 
-pub fn register_to_string<'a>(r: &'a Register) -> &'a str {
+// the registers holding something known, e.g. "c = x, d = 5 (next: e)"
+impl fmt::Display for RegisterStates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registers: Vec<(&Register, &RegisterState)> = self.registers.iter().collect();
+        registers.sort();
+        let known: Vec<String> = registers.into_iter().filter_map(|(register, state)| match state {
+            RegisterState::Noise => None,
+            RegisterState::Variable(id) => Some(format!("{} = {}", register_to_string(register), format_id(id))),
+            RegisterState::Constant(num) => Some(format!("{} = {}", register_to_string(register), num)),
+        }).collect();
+        if known.is_empty() {
+            write!(f, "all noise")?;
+        } else {
+            write!(f, "{}", known.join(", "))?;
+        }
+        write!(f, " (next: {})", register_to_string(&self.next))
+    }
+}
+
+pub fn register_to_string(r: &Register) -> &str {
     match r {
         Register::A => return "a",
         Register::B => return "b",
@@ -124,16 +244,21 @@ pub enum TranslationError {
     RecurrenceNotAllowed(Location, Pidentifier),
     InvalidNumberOfArguments(Location, Pidentifier),
     UninitialisedVariable(Location, Pidentifier),
+    ReferenceExpected(Location, Pidentifier),
+    InParameterModified(Location, Pidentifier),
+    ConstantModified(Location, Pidentifier),
+    NegativeNumber(Location, Num),
+    ArrayTooBig(Location, Pidentifier),
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ValueHeld {
     Uninitialised,
     Dynamic,
     Constant(BigInt),
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Array {
     pub memloc: u64,
     pub len: u64,
@@ -147,7 +272,7 @@ impl Array {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Variable {
     pub memloc: u64,
     pub value: ValueHeld,
@@ -160,7 +285,7 @@ impl Variable {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ReturnLocation {
     pub memloc: u64,
 }
@@ -171,7 +296,7 @@ impl ReturnLocation {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum SymbolTableEntry {
     Var(Variable),
     Arr(Array),
@@ -180,6 +305,35 @@ pub enum SymbolTableEntry {
 
 pub type SymbolTable = HashMap<String, SymbolTableEntry>;
 
+// the lines of code compiled from a procedure or main, with the memory layout of the variables seen there
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    pub entry: usize, // the line jumped to by the calls
+    pub symbols: SymbolTable,
+}
+
+// where the compiled program keeps its code and its variables
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub globals: SymbolTable,
+    pub scopes: Vec<Scope>,
+}
+
+// a stretch of commands expanded from a procedure call: the number of its first command and of its commands,
+// counting every command of the procedure or main in the order they are written, the nested ones included
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InlinedRegion {
+    pub procedure: Pidentifier,
+    pub start: usize,
+    pub len: usize,
+}
+
+// the inlined regions of every procedure, and of main under None
+pub type InlinedRegions = HashMap<Option<Pidentifier>, Vec<InlinedRegion>>;
+
 pub struct ProcedureInfo {
     pub args_decl: ArgumentDeclarations,
     pub code_line_number: usize,
@@ -193,3 +347,23 @@ impl ProcedureInfo {
 }
 
 pub type FunctionTable = HashMap<String, ProcedureInfo>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assumed_registers_round_trip() {
+        let mut register_states = RegisterStates::new();
+        register_states.registers.insert(Register::C, RegisterState::Variable(Identifier::ArrNum("t".to_owned(), Num::from(2))));
+        register_states.registers.insert(Register::E, RegisterState::Constant(BigInt::from(5)));
+        let mut code = vec!["LOAD c".to_owned()];
+        add_assumed_registers(&mut code, &register_states);
+        let expected = vec![("c".to_owned(), "t[2]".to_owned()), ("e".to_owned(), "5".to_owned())];
+        assert_eq!(AnnotatedLine::parse(&code[0]).registers, Some(expected));
+
+        let mut code = vec!["LOAD c".to_owned()];
+        add_assumed_registers(&mut code, &RegisterStates::new());
+        assert_eq!(AnnotatedLine::parse(&code[0]).registers, Some(Vec::new()));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::ast::*;
+
+// a walk over the commands of the program: every method visits the node given to it and, by default,
+// goes on into the nodes inside it, so a visitor only overrides the nodes it cares about and calls
+// the matching `walk_` function where it wants to go deeper; the names of variables are visited as
+// pidentifiers, the names of procedures and the declarations are not visited at all
+pub trait Visitor {
+    fn visit_program(&mut self, program: &ProgramAll) {
+        walk_program(self, program);
+    }
+
+    fn visit_procedure(&mut self, procedure: &Procedure) {
+        walk_procedure(self, procedure);
+    }
+
+    fn visit_main(&mut self, main: &Main) {
+        walk_main(self, main);
+    }
+
+    fn visit_commands(&mut self, commands: &[Command]) {
+        walk_commands(self, commands);
+    }
+
+    fn visit_command(&mut self, command: &Command) {
+        walk_command(self, command);
+    }
+
+    fn visit_proc_call(&mut self, proc_call: &ProcCall, _location: Location) {
+        walk_proc_call(self, proc_call);
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr);
+    }
+
+    fn visit_condition(&mut self, condition: &Condition) {
+        walk_condition(self, condition);
+    }
+
+    fn visit_value(&mut self, value: &Value) {
+        walk_value(self, value);
+    }
+
+    fn visit_identifier(&mut self, id: &Identifier) {
+        walk_identifier(self, id);
+    }
+
+    fn visit_pidentifier(&mut self, _pid: &Pidentifier) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &ProgramAll) {
+    for procedure in &program.procedures {
+        visitor.visit_procedure(procedure);
+    }
+    visitor.visit_main(&program.main);
+}
+
+pub fn walk_procedure<V: Visitor + ?Sized>(visitor: &mut V, procedure: &Procedure) {
+    visitor.visit_commands(&procedure.commands);
+}
+
+pub fn walk_main<V: Visitor + ?Sized>(visitor: &mut V, main: &Main) {
+    visitor.visit_commands(&main.commands);
+}
+
+pub fn walk_commands<V: Visitor + ?Sized>(visitor: &mut V, commands: &[Command]) {
+    for command in commands {
+        visitor.visit_command(command);
+    }
+}
+
+// the parts of the command in the order they are written
+pub fn walk_command<V: Visitor + ?Sized>(visitor: &mut V, command: &Command) {
+    match command {
+        Command::Assignment(id, expr, _) => {
+            visitor.visit_identifier(id);
+            visitor.visit_expression(expr);
+        },
+        Command::IfElse(condition, if_commands, else_commands, _) => {
+            visitor.visit_condition(condition);
+            visitor.visit_commands(if_commands);
+            visitor.visit_commands(else_commands);
+        },
+        Command::If(condition, commands, _) | Command::While(condition, commands, _) => {
+            visitor.visit_condition(condition);
+            visitor.visit_commands(commands);
+        },
+        Command::Repeat(commands, condition, _) => {
+            visitor.visit_commands(commands);
+            visitor.visit_condition(condition);
+        },
+        Command::ProcedureCall(proc_call, location) => visitor.visit_proc_call(proc_call, *location),
+        Command::Read(id, _) => visitor.visit_identifier(id),
+        Command::Write(value, _) => visitor.visit_value(value),
+    }
+}
+
+pub fn walk_proc_call<V: Visitor + ?Sized>(visitor: &mut V, proc_call: &ProcCall) {
+    for arg in &proc_call.args {
+        visitor.visit_value(arg);
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match expr {
+        Expression::Val(value) => visitor.visit_value(value),
+        Expression::Add(lhs, rhs) | Expression::Sub(lhs, rhs) | Expression::Mul(lhs, rhs) | Expression::Div(lhs, rhs) | Expression::Mod(lhs, rhs) => {
+            visitor.visit_value(lhs);
+            visitor.visit_value(rhs);
+        },
+    }
+}
+
+pub fn walk_condition<V: Visitor + ?Sized>(visitor: &mut V, condition: &Condition) {
+    let (Condition::Equal(lhs, rhs)
+    | Condition::NotEqual(lhs, rhs)
+    | Condition::Greater(lhs, rhs)
+    | Condition::Lesser(lhs, rhs)
+    | Condition::GreaterOrEqual(lhs, rhs)
+    | Condition::LesserOrEqual(lhs, rhs)) = condition;
+    visitor.visit_value(lhs);
+    visitor.visit_value(rhs);
+}
+
+pub fn walk_value<V: Visitor + ?Sized>(visitor: &mut V, value: &Value) {
+    if let Value::Id(id) = value {
+        visitor.visit_identifier(id);
+    }
+}
+
+pub fn walk_identifier<V: Visitor + ?Sized>(visitor: &mut V, id: &Identifier) {
+    match id {
+        Identifier::Pid(pid) | Identifier::ArrNum(pid, _) => visitor.visit_pidentifier(pid),
+        Identifier::ArrPid(arr, idx) => {
+            visitor.visit_pidentifier(arr);
+            visitor.visit_pidentifier(idx);
+        },
+    }
+}
+
+// the same walk, changing the nodes in place; overriding `visit_commands_mut` lets a pass
+// rewrite the lists of commands as a whole, replacing a command with any number of others
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut ProgramAll) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_procedure_mut(&mut self, procedure: &mut Procedure) {
+        walk_procedure_mut(self, procedure);
+    }
+
+    fn visit_main_mut(&mut self, main: &mut Main) {
+        walk_main_mut(self, main);
+    }
+
+    fn visit_commands_mut(&mut self, commands: &mut Commands) {
+        walk_commands_mut(self, commands);
+    }
+
+    fn visit_command_mut(&mut self, command: &mut Command) {
+        walk_command_mut(self, command);
+    }
+
+    fn visit_proc_call_mut(&mut self, proc_call: &mut ProcCall, _location: Location) {
+        walk_proc_call_mut(self, proc_call);
+    }
+
+    fn visit_expression_mut(&mut self, expr: &mut Expression) {
+        walk_expression_mut(self, expr);
+    }
+
+    fn visit_condition_mut(&mut self, condition: &mut Condition) {
+        walk_condition_mut(self, condition);
+    }
+
+    fn visit_value_mut(&mut self, value: &mut Value) {
+        walk_value_mut(self, value);
+    }
+
+    fn visit_identifier_mut(&mut self, id: &mut Identifier) {
+        walk_identifier_mut(self, id);
+    }
+
+    fn visit_pidentifier_mut(&mut self, _pid: &mut Pidentifier) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut ProgramAll) {
+    for procedure in program.procedures.iter_mut() {
+        visitor.visit_procedure_mut(procedure);
+    }
+    visitor.visit_main_mut(&mut program.main);
+}
+
+pub fn walk_procedure_mut<V: VisitorMut + ?Sized>(visitor: &mut V, procedure: &mut Procedure) {
+    visitor.visit_commands_mut(&mut procedure.commands);
+}
+
+pub fn walk_main_mut<V: VisitorMut + ?Sized>(visitor: &mut V, main: &mut Main) {
+    visitor.visit_commands_mut(&mut main.commands);
+}
+
+pub fn walk_commands_mut<V: VisitorMut + ?Sized>(visitor: &mut V, commands: &mut Commands) {
+    for command in commands.iter_mut() {
+        visitor.visit_command_mut(command);
+    }
+}
+
+pub fn walk_command_mut<V: VisitorMut + ?Sized>(visitor: &mut V, command: &mut Command) {
+    match command {
+        Command::Assignment(id, expr, _) => {
+            visitor.visit_identifier_mut(id);
+            visitor.visit_expression_mut(expr);
+        },
+        Command::IfElse(condition, if_commands, else_commands, _) => {
+            visitor.visit_condition_mut(condition);
+            visitor.visit_commands_mut(if_commands);
+            visitor.visit_commands_mut(else_commands);
+        },
+        Command::If(condition, commands, _) | Command::While(condition, commands, _) => {
+            visitor.visit_condition_mut(condition);
+            visitor.visit_commands_mut(commands);
+        },
+        Command::Repeat(commands, condition, _) => {
+            visitor.visit_commands_mut(commands);
+            visitor.visit_condition_mut(condition);
+        },
+        Command::ProcedureCall(proc_call, location) => visitor.visit_proc_call_mut(proc_call, *location),
+        Command::Read(id, _) => visitor.visit_identifier_mut(id),
+        Command::Write(value, _) => visitor.visit_value_mut(value),
+    }
+}
+
+pub fn walk_proc_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, proc_call: &mut ProcCall) {
+    for arg in proc_call.args.iter_mut() {
+        visitor.visit_value_mut(arg);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match expr {
+        Expression::Val(value) => visitor.visit_value_mut(value),
+        Expression::Add(lhs, rhs) | Expression::Sub(lhs, rhs) | Expression::Mul(lhs, rhs) | Expression::Div(lhs, rhs) | Expression::Mod(lhs, rhs) => {
+            visitor.visit_value_mut(lhs);
+            visitor.visit_value_mut(rhs);
+        },
+    }
+}
+
+pub fn walk_condition_mut<V: VisitorMut + ?Sized>(visitor: &mut V, condition: &mut Condition) {
+    let (Condition::Equal(lhs, rhs)
+    | Condition::NotEqual(lhs, rhs)
+    | Condition::Greater(lhs, rhs)
+    | Condition::Lesser(lhs, rhs)
+    | Condition::GreaterOrEqual(lhs, rhs)
+    | Condition::LesserOrEqual(lhs, rhs)) = condition;
+    visitor.visit_value_mut(lhs);
+    visitor.visit_value_mut(rhs);
+}
+
+pub fn walk_value_mut<V: VisitorMut + ?Sized>(visitor: &mut V, value: &mut Value) {
+    if let Value::Id(id) = value {
+        visitor.visit_identifier_mut(id);
+    }
+}
+
+pub fn walk_identifier_mut<V: VisitorMut + ?Sized>(visitor: &mut V, id: &mut Identifier) {
+    match id {
+        Identifier::Pid(pid) | Identifier::ArrNum(pid, _) => visitor.visit_pidentifier_mut(pid),
+        Identifier::ArrPid(arr, idx) => {
+            visitor.visit_pidentifier_mut(arr);
+            visitor.visit_pidentifier_mut(idx);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::parse;
+    use super::*;
+
+    // the variables in the order they are used
+    struct Names(Vec<Pidentifier>);
+
+    impl Visitor for Names {
+        fn visit_pidentifier(&mut self, pid: &Pidentifier) {
+            self.0.push(pid.clone());
+        }
+    }
+
+    // the procedures called anywhere, with the calls dropped from the program
+    struct DropCalls(Vec<Pidentifier>);
+
+    impl VisitorMut for DropCalls {
+        fn visit_commands_mut(&mut self, commands: &mut Commands) {
+            commands.retain(|command| match command {
+                Command::ProcedureCall(proc_call, _) => {
+                    self.0.push(proc_call.name.clone());
+                    false
+                },
+                _ => true,
+            });
+            walk_commands_mut(self, commands);
+        }
+    }
+
+    #[test]
+    fn default_walks() {
+        let source = "PROCEDURE p(T t, n) IS IN t[n] := n + 1; END\n\
+            PROGRAM IS a, t[2] IN READ a; IF a > 0 THEN p(t, a); ELSE REPEAT a := t[a] - 1; p(t, a); UNTIL a = 0; ENDIF WRITE t[1]; END";
+        let mut ast = parse(source);
+
+        let mut names = Names(Vec::new());
+        names.visit_program(&ast);
+        assert_eq!(names.0, ["t", "n", "n", "a", "a", "t", "a", "a", "t", "a", "t", "a", "a", "t"]);
+
+        let mut calls = DropCalls(Vec::new());
+        calls.visit_program_mut(&mut ast);
+        assert_eq!(calls.0, ["p", "p"]);
+
+        let mut names = Names(Vec::new());
+        names.visit_program(&ast);
+        assert_eq!(names.0, ["t", "n", "n", "a", "a", "a", "t", "a", "a", "t"]);
+    }
+}
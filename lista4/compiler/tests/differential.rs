@@ -0,0 +1,250 @@
+#![allow(clippy::needless_return)]
+
+// the programs of the corpus compiled at every optimisation level and run in the emulator, checked against
+// the interpreter on the same inputs and against the snapshot next to every program, which holds the inputs,
+// the output and the cost of the code of every level; a changed cost fails the test as well, so that
+// the regressions show up and the improvements get recorded with
+//
+//     UPDATE_SNAPSHOTS=1 cargo test --test differential
+//
+// which rewrites the outputs and the costs of the snapshots, keeping their inputs; the programs in the
+// signed directory are compiled with --signed, their output decoded the way `run --signed` prints it
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use num::BigInt;
+
+use compiler::emulator::{self, BufferedIo, EmulatorError, Execution, Machine};
+use compiler::interpreter::interpret;
+use compiler::modules::{import_std_module, load_program, SourceMap};
+use compiler::translation::translate;
+use compiler::translation::passes::{PassManager, MAX_LEVEL};
+use compiler::translation::signed::{lower_signed, SignedIo, SIGNED_MODULE};
+use compiler::translation::transformation::transform;
+
+const STEP_LIMIT: u64 = 100_000_000;
+const UPDATE_VARIABLE: &str = "UPDATE_SNAPSHOTS";
+
+// one execution recorded in a snapshot
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Run {
+    input: Vec<BigInt>,
+    output: Option<Vec<BigInt>>,
+    costs: Vec<(u8, u64)>, // the optimisation level and the cost of its code
+}
+
+fn numbers(text: &str) -> Result<Vec<BigInt>, String> {
+    return text.split_whitespace().map(|number| number.parse().map_err(|_| format!("\"{}\" is not a number", number))).collect();
+}
+
+fn join(numbers: &[BigInt]) -> String {
+    return numbers.iter().map(|number| number.to_string()).collect::<Vec<String>>().join(" ");
+}
+
+// the runs of the snapshot, separated by empty lines, each of them starting with its input:
+//
+//     input: 37 15
+//     output: 1 555
+//     cost -O0: 4321
+fn parse_snapshot(text: &str) -> Result<Vec<Run>, String> {
+    let mut runs = Vec::new();
+    for block in text.split("\n\n").filter(|block| !block.trim().is_empty()) {
+        let mut run: Option<Run> = None;
+        for line in block.lines() {
+            let (key, value) = line.split_once(':').ok_or_else(|| format!("no key in the line \"{}\"", line))?;
+            match (key, run.as_mut()) {
+                ("input", None) => run = Some(Run{input: numbers(value)?, ..Run::default()}),
+                ("output", Some(run)) => run.output = Some(numbers(value)?),
+                (key, Some(run)) if key.starts_with("cost -O") => {
+                    let level = key["cost -O".len()..].parse().map_err(|_| format!("no level in \"{}\"", key))?;
+                    let cost = value.trim().parse().map_err(|_| format!("\"{}\" is not a cost", value.trim()))?;
+                    run.costs.push((level, cost));
+                },
+                _ => return Err(format!("unexpected line \"{}\"", line)),
+            }
+        }
+        runs.extend(run);
+    }
+    return Ok(runs);
+}
+
+// the line of the snapshot with the numbers, without a space after the colon when there are none
+fn numbers_line(key: &str, numbers: &[BigInt]) -> String {
+    return match numbers.is_empty() {
+        true => format!("{}:\n", key),
+        false => format!("{}: {}\n", key, join(numbers)),
+    };
+}
+
+fn format_snapshot(runs: &[Run]) -> String {
+    let blocks: Vec<String> = runs.iter().map(|run| {
+        let mut block = numbers_line("input", &run.input);
+        if let Some(output) = &run.output {
+            block += &numbers_line("output", output);
+        }
+        for (level, cost) in &run.costs {
+            writeln!(block, "cost -O{}: {}", level, cost).unwrap();
+        }
+        block
+    }).collect();
+    return blocks.join("\n");
+}
+
+// the execution of the code, the output of the signed code decoded into the numbers it stands for
+fn emulate(code: &[String], input: &[BigInt], signed: bool) -> Result<Execution, EmulatorError> {
+    if !signed {
+        return emulator::run(code, input, Some(STEP_LIMIT));
+    }
+    let mut machine = Machine::new(emulator::parse_code(code)?);
+    let mut io = BufferedIo::new(input);
+    machine.run(&mut SignedIo::new(&mut io), Some(STEP_LIMIT))?;
+    return Ok(Execution{output: io.output, cost: machine.cost});
+}
+
+// the runs as they are now, the compiled code having to write what the interpreter writes
+fn execute(path: &Path, inputs: &[Vec<BigInt>], signed: bool) -> Result<Vec<Run>, String> {
+    let mut source_map = SourceMap::new();
+    let ast = load_program(path, &mut source_map).map_err(|e| e.to_string())?;
+    let mut runs: Vec<Run> = inputs.iter().map(|input| Run{input: input.clone(), ..Run::default()}).collect();
+    for run in runs.iter_mut() {
+        let output = interpret(&ast, &run.input, signed, Some(STEP_LIMIT)).map_err(|e| format!("the interpreter stops on {}: {}", join(&run.input), e))?;
+        run.output = Some(output);
+    }
+
+    // the signed program is compiled the way --signed compiles it
+
+    let mut compiled = ast;
+    if signed {
+        import_std_module(&mut compiled, &mut source_map, SIGNED_MODULE).map_err(|e| e.to_string())?;
+        lower_signed(&mut compiled);
+    }
+
+    for level in 0..=MAX_LEVEL {
+        let passes = PassManager::for_level(level);
+        let mut lowered = compiled.clone();
+        transform(&mut lowered, &passes).map_err(|e| format!("{:?} at -O{}", e, level))?;
+        let code = translate(lowered, &passes).map_err(|e| format!("{:?} at -O{}", e, level))?;
+
+        for run in runs.iter_mut() {
+            let execution = emulate(&code, &run.input, signed).map_err(|e| format!("the code of -O{} stops on {}: {}", level, join(&run.input), e))?;
+            if Some(&execution.output) != run.output.as_ref() {
+                return Err(format!(
+                    "the code of -O{} writes {} on {}, the interpreter {}",
+                    level, join(&execution.output), join(&run.input), join(run.output.as_ref().unwrap()),
+                ));
+            }
+            run.costs.push((level, execution.cost));
+        }
+    }
+    return Ok(runs);
+}
+
+// the differences between the snapshot and the runs as they are now
+fn compare(expected: &[Run], actual: &[Run]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (expected, actual) in expected.iter().zip(actual) {
+        let input = join(&actual.input);
+        if expected.output != actual.output {
+            let output = expected.output.as_deref().map_or("nothing".to_owned(), join);
+            differences.push(format!("on {} writes {} instead of {}", input, join(actual.output.as_ref().unwrap()), output));
+        }
+        for (level, cost) in &actual.costs {
+            match expected.costs.iter().find(|(expected_level, _)| expected_level == level) {
+                Some((_, old)) if cost > old => differences.push(format!("on {} costs {} at -O{}, up from {}", input, cost, level, old)),
+                Some((_, old)) if cost < old => differences.push(format!("on {} costs {} at -O{}, down from {}: record the improvement", input, cost, level, old)),
+                Some(_) => {},
+                None => differences.push(format!("on {} has no cost recorded for -O{}", input, level)),
+            }
+        }
+    }
+    return differences;
+}
+
+fn corpus(dir: &Path) -> Vec<PathBuf> {
+    let paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    let has_extension = |path: &PathBuf, wanted: &str| path.extension().is_some_and(|extension| extension == wanted);
+
+    // a snapshot without its program would never be checked
+
+    let orphans: Vec<String> = paths.iter()
+        .filter(|path| has_extension(path, "snap") && !path.with_extension("imp").exists())
+        .map(|path| path.display().to_string())
+        .collect();
+    assert!(orphans.is_empty(), "the snapshots have no programs: {}", orphans.join(", "));
+
+    let mut programs: Vec<PathBuf> = paths.into_iter().filter(|path| has_extension(path, "imp")).collect();
+    assert!(!programs.is_empty(), "no programs in {}", dir.display());
+    programs.sort();
+    return programs;
+}
+
+fn corpus_dir() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+}
+
+fn check_corpus(dir: &Path, signed: bool) {
+    let update = env::var_os(UPDATE_VARIABLE).is_some();
+    let mut failures = Vec::new();
+
+    for path in corpus(dir) {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let snapshot_path = path.with_extension("snap");
+        let expected = match fs::read_to_string(&snapshot_path).map_err(|e| e.to_string()).and_then(|text| parse_snapshot(&text)) {
+            Ok(runs) if !runs.is_empty() => runs,
+            Ok(_) => {
+                failures.push(format!("{}: the snapshot has no runs, add a line `input: ...` for each of them", name));
+                continue;
+            },
+            Err(e) => {
+                failures.push(format!("{}: cannot read the snapshot: {}", name, e));
+                continue;
+            },
+        };
+
+        let inputs: Vec<Vec<BigInt>> = expected.iter().map(|run| run.input.clone()).collect();
+        let actual = match execute(&path, &inputs, signed) {
+            Ok(runs) => runs,
+            Err(e) => {
+                failures.push(format!("{}: {}", name, e));
+                continue;
+            },
+        };
+
+        // the snapshot is brought up to date, or has to be up to date already
+
+        if update {
+            fs::write(&snapshot_path, format_snapshot(&actual)).unwrap();
+        } else {
+            failures.extend(compare(&expected, &actual).into_iter().map(|difference| format!("{} {}", name, difference)));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n\nrun `{}=1 cargo test --test differential` once the changes are intended", failures.join("\n"), UPDATE_VARIABLE);
+}
+
+#[test]
+fn corpus_matches_the_interpreter_and_the_snapshots() {
+    check_corpus(&corpus_dir(), false);
+}
+
+#[test]
+fn signed_corpus_matches_the_interpreter_and_the_snapshots() {
+    check_corpus(&corpus_dir().join("signed"), true);
+}
+
+#[test]
+fn snapshot_format_round_trip() {
+    let text = "input: 37 15\noutput: 1 555\ncost -O0: 4321\ncost -O2: 1234\n\ninput:\noutput:\n";
+    let runs = parse_snapshot(text).unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].costs, [(0, 4321), (2, 1234)]);
+    assert_eq!(runs[1], Run{input: Vec::new(), output: Some(Vec::new()), costs: Vec::new()});
+    assert_eq!(format_snapshot(&runs), text);
+
+    let mut cheaper = parse_snapshot(text).unwrap();
+    cheaper[0].costs[1].1 = 1000;
+    assert_eq!(compare(&runs, &cheaper), ["on 37 15 costs 1000 at -O2, down from 1234: record the improvement"]);
+    assert!(parse_snapshot("output: 1\n").is_err());
+}